@@ -39,7 +39,7 @@ fn main() {
         }
     }
 
-    writer.flush().unwrap();
+    writer.close().expect("Can't close log file");
 
     sparkey::hash::Writer::write(index, log, None).expect("Can't create index file");
 }
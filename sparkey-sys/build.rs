@@ -1,8 +1,21 @@
 extern crate cc;
 
+use std::env;
+
 fn main() {
     println!("cargo:rustc-link-lib=snappy");
-    cc::Build::new()
+    println!("cargo:rerun-if-changed=sparkey/src");
+    println!("cargo:rerun-if-changed=src/ext.c");
+
+    let mut build = cc::Build::new();
+    // Set by zstd-sys, which builds and links libzstd for us
+    if let Ok(includes) = env::var("DEP_ZSTD_INCLUDE") {
+        for include in includes.split(';') {
+            build.include(include);
+        }
+    }
+
+    build
         .include("sparkey/src")
         .flag("-std=c99")
         .flag("-Wno-implicit-fallthrough")
@@ -19,5 +32,6 @@ fn main() {
         .file("sparkey/src/logwriter.c")
         .file("sparkey/src/returncodes.c")
         .file("sparkey/src/util.c")
+        .file("src/ext.c")
         .compile("libsparkey.a");
 }
@@ -128,6 +128,11 @@ static sparkey_returncode assert_reader_open(sparkey_hashreader *reader) {
 sparkey_returncode sparkey_hash_get(sparkey_hashreader *reader, const uint8_t *key, uint64_t keylen, sparkey_logiter *iter) {
   RETHROW(assert_reader_open(reader));
   uint64_t hash = reader->header.hash_algorithm.hash(key, keylen, reader->header.hash_seed);
+  return sparkey_hash_get_hashed(reader, key, keylen, hash, iter);
+}
+
+sparkey_returncode sparkey_hash_get_hashed(sparkey_hashreader *reader, const uint8_t *key, uint64_t keylen, uint64_t hash, sparkey_logiter *iter) {
+  RETHROW(assert_reader_open(reader));
   uint64_t wanted_slot = hash % reader->header.hash_capacity;
 
   int slot_size = reader->header.address_size + reader->header.hash_size;
@@ -351,6 +351,10 @@ free:
 }
 
 sparkey_returncode sparkey_hash_write(const char *hash_filename, const char *log_filename, int hash_size) {
+  return sparkey_hash_write_report(hash_filename, log_filename, hash_size, NULL);
+}
+
+sparkey_returncode sparkey_hash_write_report(const char *hash_filename, const char *log_filename, int hash_size, int *incremental) {
   sparkey_logheader log_header;
   sparkey_logreader *log;
   sparkey_logiter *iter = NULL;
@@ -426,6 +430,9 @@ sparkey_returncode sparkey_hash_write(const char *hash_filename, const char *log
     copy_old = 0;
   }
   hash_header.hash_algorithm = sparkey_get_hash_algorithm(hash_header.hash_size);
+  if (incremental != NULL) {
+    *incremental = copy_old;
+  }
 
   int slot_size = hash_header.hash_size + hash_header.address_size;
   uint64_t hashsize = slot_size * hash_header.hash_capacity;
@@ -22,7 +22,7 @@
 #include "endiantools.h"
 #include "util.h"
 
-static char * compression_types[] = { "Uncompressed", "Snappy", NULL };
+static char * compression_types[] = { "Uncompressed", "Snappy", "Zstd", NULL };
 
 void print_logheader(sparkey_logheader *header) {
   printf("Log file version %d.%d\n", header->major_version,
@@ -59,16 +59,32 @@ static sparkey_returncode logheader_version0(sparkey_logheader *header, FILE *fp
   if (header->num_deletes > header->data_end) {
     return SPARKEY_LOG_HEADER_CORRUPT;
   }
-  if (header->compression_type > SPARKEY_COMPRESSION_SNAPPY) {
+  if (header->compression_type > SPARKEY_COMPRESSION_ZSTD) {
     return SPARKEY_LOG_HEADER_CORRUPT;
   }
   return SPARKEY_SUCCESS;
 }
+static sparkey_returncode logheader_version2(sparkey_logheader *header, FILE *fp) {
+  RETHROW(logheader_version0(header, fp));
+  uint32_t dictionary_size;
+  RETHROW(fread_little_endian32(fp, &dictionary_size));
+  if (dictionary_size > UINT32_MAX - LOG_DICTIONARY_OFFSET) {
+    return SPARKEY_LOG_HEADER_CORRUPT;
+  }
+  header->header_size = LOG_DICTIONARY_OFFSET + dictionary_size;
 
+  if (header->compression_type != SPARKEY_COMPRESSION_ZSTD) {
+    return SPARKEY_LOG_HEADER_CORRUPT;
+  }
+  if (header->data_end < header->header_size) {
+    return SPARKEY_LOG_HEADER_CORRUPT;
+  }
+  return SPARKEY_SUCCESS;
+}
 
 typedef sparkey_returncode (*loader)(sparkey_logheader *header, FILE *fp);
 
-static loader loaders[1] = { logheader_version0 };
+static loader loaders[3] = { logheader_version0, logheader_version0, logheader_version2 };
 
 sparkey_returncode sparkey_load_logheader(sparkey_logheader *header, const char *filename) {
   FILE *fp = fopen(filename, "r");
@@ -106,7 +122,7 @@ sparkey_returncode sparkey_load_logheader(sparkey_logheader *header, const char
 sparkey_returncode write_logheader(int fd, sparkey_logheader *header) {
   RETHROW(fwrite_little_endian32(fd, LOG_MAGIC_NUMBER));
   RETHROW(fwrite_little_endian32(fd, LOG_MAJOR_VERSION));
-  RETHROW(fwrite_little_endian32(fd, LOG_MINOR_VERSION));
+  RETHROW(fwrite_little_endian32(fd, header->minor_version));
   RETHROW(fwrite_little_endian32(fd, header->file_identifier));
   RETHROW(fwrite_little_endian64(fd, header->num_puts));
   RETHROW(fwrite_little_endian64(fd, header->num_deletes));
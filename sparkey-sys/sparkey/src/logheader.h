@@ -22,8 +22,13 @@
 
 #define LOG_MAGIC_NUMBER (0x49b39c95)
 #define LOG_MAJOR_VERSION (1)
-#define LOG_MINOR_VERSION (0)
+#define LOG_MINOR_VERSION (2)
+// Logs that don't use zstd are still written as version 1.0, so that older readers can open them
+#define LOG_MINOR_VERSION_ZSTD (1)
+#define LOG_MINOR_VERSION_DICTIONARY (2)
 #define LOG_HEADER_SIZE (84)
+// Dictionary logs store a 32 bit dictionary size followed by the dictionary after the header.
+#define LOG_DICTIONARY_OFFSET (LOG_HEADER_SIZE + 4)
 
 typedef struct {
   uint32_t major_version;
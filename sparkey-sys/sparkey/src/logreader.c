@@ -58,6 +58,7 @@ static inline uint64_t read_vlq(uint8_t * array, uint64_t *position) {
 sparkey_returncode sparkey_logreader_open_noalloc(sparkey_logreader *log, const char *filename) {
   int fd = 0;
   sparkey_returncode returncode;
+  log->zstd_ddict = NULL;
   TRY(sparkey_load_logheader(&log->header, filename), cleanup);
   log->data_len = log->header.data_end;
 
@@ -81,6 +82,15 @@ sparkey_returncode sparkey_logreader_open_noalloc(sparkey_logreader *log, const
     goto cleanup;
   }
 
+  if (log->header.minor_version >= LOG_MINOR_VERSION_DICTIONARY) {
+    log->zstd_ddict = ZSTD_createDDict(&log->data[LOG_DICTIONARY_OFFSET], log->header.header_size - LOG_DICTIONARY_OFFSET);
+    if (log->zstd_ddict == NULL) {
+      munmap(log->data, log->data_len);
+      returncode = SPARKEY_INTERNAL_ERROR;
+      goto cleanup;
+    }
+  }
+
   log->open_status = MAGIC_VALUE_LOGREADER;
   return SPARKEY_SUCCESS;
 
@@ -120,6 +130,8 @@ void sparkey_logreader_close_nodealloc(sparkey_logreader *log) {
     munmap(log->data, log->data_len);
     log->data = NULL;
   }
+  ZSTD_freeDDict(log->zstd_ddict);
+  log->zstd_ddict = NULL;
   close(log->fd);
   log->fd = -1;
 }
@@ -167,15 +179,24 @@ sparkey_returncode sparkey_logiter_create(sparkey_logiter **iter_ref, sparkey_lo
   iter->block_offset = 0;
   iter->block_len = 0;
   iter->state = SPARKEY_ITER_NEW;
+  iter->zstd_dctx = NULL;
 
   switch (log->header.compression_type) {
   case SPARKEY_COMPRESSION_NONE:
     iter->compression_buf_allocated = 0;
     break;
+  case SPARKEY_COMPRESSION_ZSTD:
+    iter->zstd_dctx = ZSTD_createDCtx();
+    if (iter->zstd_dctx == NULL) {
+      free(iter);
+      return SPARKEY_INTERNAL_ERROR;
+    }
+    // fall through
   case SPARKEY_COMPRESSION_SNAPPY:
     iter->compression_buf_allocated = 1;
     iter->compression_buf = malloc(log->header.compression_block_size);
     if (iter->compression_buf == NULL) {
+      ZSTD_freeDCtx(iter->zstd_dctx);
       free(iter);
       return SPARKEY_INTERNAL_ERROR;
     }
@@ -205,6 +226,7 @@ void sparkey_logiter_close(sparkey_logiter **iter_ref) {
   if (iter->compression_buf_allocated) {
     free(iter->compression_buf);
   }
+  ZSTD_freeDCtx(iter->zstd_dctx);
   free(iter);
   *iter_ref = NULL;
 }
@@ -244,6 +266,25 @@ static sparkey_returncode seekblock(sparkey_logiter *iter, sparkey_logreader *lo
     iter->block_len = uncompressed_size;
     return SPARKEY_SUCCESS;
   }
+  if (log->header.compression_type == SPARKEY_COMPRESSION_ZSTD) {
+    uint64_t pos = position;
+    size_t compressed_size = read_vlq(log->data, &pos);
+    uint64_t next_pos = pos + compressed_size;
+
+    size_t uncompressed_size;
+    if (log->zstd_ddict != NULL) {
+      uncompressed_size = ZSTD_decompress_usingDDict(iter->zstd_dctx, iter->compression_buf, log->header.compression_block_size, &log->data[pos], compressed_size, log->zstd_ddict);
+    } else {
+      uncompressed_size = ZSTD_decompressDCtx(iter->zstd_dctx, iter->compression_buf, log->header.compression_block_size, &log->data[pos], compressed_size);
+    }
+    if (ZSTD_isError(uncompressed_size)) {
+      return SPARKEY_INTERNAL_ERROR;
+    }
+    iter->block_position = position;
+    iter->next_block_position = next_pos;
+    iter->block_len = uncompressed_size;
+    return SPARKEY_SUCCESS;
+  }
 
   return SPARKEY_INTERNAL_ERROR;
 }
@@ -51,27 +51,62 @@ static sparkey_returncode assert_writer_open(sparkey_logwriter *log) {
   return SPARKEY_SUCCESS;
 }
 
-sparkey_returncode sparkey_logwriter_create(sparkey_logwriter **log_ref, const char *filename, sparkey_compression_type compression_type, int compression_block_size) {
+static sparkey_returncode init_compression(sparkey_logwriter *log, sparkey_compression_type compression_type, int compression_block_size) {
+  if (compression_type == SPARKEY_COMPRESSION_ZSTD) {
+    log->max_compressed_size = ZSTD_compressBound(compression_block_size);
+    log->zstd_cctx = ZSTD_createCCtx();
+    if (log->zstd_cctx == NULL) {
+      return SPARKEY_INTERNAL_ERROR;
+    }
+  } else {
+    log->max_compressed_size = snappy_max_compressed_length(compression_block_size);
+  }
+  log->compressed = malloc(log->max_compressed_size);
+  if (log->compressed == NULL) {
+    ZSTD_freeCCtx(log->zstd_cctx);
+    log->zstd_cctx = NULL;
+    return SPARKEY_INTERNAL_ERROR;
+  }
+  return SPARKEY_SUCCESS;
+}
+
+static sparkey_returncode logwriter_create(sparkey_logwriter **log_ref, const char *filename, sparkey_compression_type compression_type, int compression_block_size, const uint8_t *dictionary, uint32_t dictionary_size) {
   sparkey_returncode returncode;
   int fd = 0;
   sparkey_logwriter *l = malloc(sizeof(sparkey_logwriter));
   if (l == NULL) {
     TRY(SPARKEY_INTERNAL_ERROR, error);
   }
+  l->compression_level = ZSTD_CLEVEL_DEFAULT;
+  l->zstd_cctx = NULL;
+  l->dictionary = NULL;
+  l->dictionary_size = 0;
+  l->zstd_cdict = NULL;
+  if (dictionary != NULL) {
+    if (compression_type != SPARKEY_COMPRESSION_ZSTD) {
+      TRY(SPARKEY_INVALID_COMPRESSION_TYPE, error);
+    }
+    if (dictionary_size > UINT32_MAX - LOG_DICTIONARY_OFFSET) {
+      TRY(SPARKEY_FILE_SIZE_EXCEEDED, error);
+    }
+    l->dictionary = malloc(dictionary_size > 0 ? dictionary_size : 1);
+    if (l->dictionary == NULL) {
+      TRY(SPARKEY_INTERNAL_ERROR, error);
+    }
+    memcpy(l->dictionary, dictionary, dictionary_size);
+    l->dictionary_size = dictionary_size;
+  }
   switch (compression_type) {
   case SPARKEY_COMPRESSION_NONE:
     compression_block_size = 0;
     l->compressed = NULL;
     break;
   case SPARKEY_COMPRESSION_SNAPPY:
+  case SPARKEY_COMPRESSION_ZSTD:
     if (compression_block_size < 10) {
       TRY(SPARKEY_INVALID_COMPRESSION_BLOCK_SIZE, error);
     }
-    l->max_compressed_size = snappy_max_compressed_length(compression_block_size);
-    l->compressed = malloc(l->max_compressed_size);
-    if (l->compressed == NULL) {
-      TRY(SPARKEY_INTERNAL_ERROR, error);
-    }
+    TRY(init_compression(l, compression_type, compression_block_size), error);
     break;
   default:
     TRY(SPARKEY_INVALID_COMPRESSION_TYPE, error);
@@ -95,9 +130,15 @@ sparkey_returncode sparkey_logwriter_create(sparkey_logwriter **log_ref, const c
   l->header.compression_type = compression_type;
 
   TRY(rand32(&(l->header.file_identifier)), error);
-  l->header.data_end = LOG_HEADER_SIZE;
   l->header.major_version = LOG_MAJOR_VERSION;
-  l->header.minor_version = LOG_MINOR_VERSION;
+  if (l->dictionary != NULL) {
+    l->header.minor_version = LOG_MINOR_VERSION_DICTIONARY;
+    l->header.header_size = LOG_DICTIONARY_OFFSET + l->dictionary_size;
+  } else {
+    l->header.minor_version = compression_type == SPARKEY_COMPRESSION_ZSTD ? LOG_MINOR_VERSION_ZSTD : 0;
+    l->header.header_size = LOG_HEADER_SIZE;
+  }
+  l->header.data_end = l->header.header_size;
   l->header.put_size = 0;
   l->header.delete_size = 0;
   l->header.num_puts = 0;
@@ -107,8 +148,12 @@ sparkey_returncode sparkey_logwriter_create(sparkey_logwriter **log_ref, const c
   l->header.max_value_len = 0;
 
   TRY(write_logheader(fd, &l->header), error);
+  if (l->dictionary != NULL) {
+    TRY(fwrite_little_endian32(fd, l->dictionary_size), error);
+    TRY(write_full(fd, l->dictionary, l->dictionary_size), error);
+  }
   off_t pos = lseek(fd, 0, SEEK_CUR);
-  if (pos != LOG_HEADER_SIZE) {
+  if (pos != (off_t) l->header.header_size) {
     TRY(SPARKEY_INTERNAL_ERROR, error);
   }
 
@@ -121,11 +166,47 @@ sparkey_returncode sparkey_logwriter_create(sparkey_logwriter **log_ref, const c
   *log_ref = l;
   return SPARKEY_SUCCESS;
 error:
+  if (l != NULL) {
+    free(l->dictionary);
+  }
   free(l);
   if (fd > 0) close(fd);
   return returncode;
 }
 
+sparkey_returncode sparkey_logwriter_create(sparkey_logwriter **log_ref, const char *filename, sparkey_compression_type compression_type, int compression_block_size) {
+  return logwriter_create(log_ref, filename, compression_type, compression_block_size, NULL, 0);
+}
+
+sparkey_returncode sparkey_logwriter_create_dictionary(sparkey_logwriter **log_ref, const char *filename, int compression_block_size, const uint8_t *dictionary, uint32_t dictionary_size) {
+  if (dictionary == NULL) {
+    return SPARKEY_INTERNAL_ERROR;
+  }
+  return logwriter_create(log_ref, filename, SPARKEY_COMPRESSION_ZSTD, compression_block_size, dictionary, dictionary_size);
+}
+
+static sparkey_returncode read_dictionary(const char *filename, uint8_t *dictionary, uint32_t dictionary_size) {
+  int fd = open(filename, O_RDONLY);
+  if (fd == -1) {
+    return sparkey_open_returncode(errno);
+  }
+  sparkey_returncode returncode = SPARKEY_SUCCESS;
+  if (lseek(fd, LOG_DICTIONARY_OFFSET, SEEK_SET) != LOG_DICTIONARY_OFFSET) {
+    returncode = SPARKEY_UNEXPECTED_EOF;
+  }
+  while (returncode == SPARKEY_SUCCESS && dictionary_size > 0) {
+    ssize_t actual = read(fd, dictionary, dictionary_size);
+    if (actual <= 0) {
+      returncode = SPARKEY_UNEXPECTED_EOF;
+    } else {
+      dictionary += actual;
+      dictionary_size -= actual;
+    }
+  }
+  close(fd);
+  return returncode;
+}
+
 sparkey_returncode sparkey_logwriter_append(sparkey_logwriter **log_ref, const char *filename) {
   sparkey_returncode returncode;
   int fd = 0;
@@ -138,21 +219,26 @@ sparkey_returncode sparkey_logwriter_append(sparkey_logwriter **log_ref, const c
   if (log->header.major_version != LOG_MAJOR_VERSION) {
     TRY(SPARKEY_WRONG_LOG_MAJOR_VERSION, error);
   }
-  if (log->header.minor_version != LOG_MINOR_VERSION) {
+  if (log->header.minor_version > LOG_MINOR_VERSION) {
     TRY(SPARKEY_UNSUPPORTED_LOG_MINOR_VERSION, error);
   }
 
+  log->compression_level = ZSTD_CLEVEL_DEFAULT;
+  log->zstd_cctx = NULL;
+  log->dictionary = NULL;
+  log->dictionary_size = 0;
+  log->zstd_cdict = NULL;
   switch (log->header.compression_type) {
   case SPARKEY_COMPRESSION_NONE:
     log->header.compression_block_size = 0;
     log->compressed = NULL;
     break;
   case SPARKEY_COMPRESSION_SNAPPY:
+  case SPARKEY_COMPRESSION_ZSTD:
     if (log->header.compression_block_size < 10) {
       TRY(SPARKEY_INVALID_COMPRESSION_BLOCK_SIZE, error);
     }
-    log->max_compressed_size = snappy_max_compressed_length(log->header.compression_block_size);
-    log->compressed = malloc(log->max_compressed_size);
+    TRY(init_compression(log, log->header.compression_type, log->header.compression_block_size), error);
     break;
   default:
     TRY(SPARKEY_INVALID_COMPRESSION_TYPE, error);
@@ -165,6 +251,15 @@ sparkey_returncode sparkey_logwriter_append(sparkey_logwriter **log_ref, const c
   }
   log->fd = fd;
 
+  if (log->header.minor_version >= LOG_MINOR_VERSION_DICTIONARY) {
+    log->dictionary_size = log->header.header_size - LOG_DICTIONARY_OFFSET;
+    log->dictionary = malloc(log->dictionary_size > 0 ? log->dictionary_size : 1);
+    if (log->dictionary == NULL) {
+      TRY(SPARKEY_INTERNAL_ERROR, error);
+    }
+    TRY(read_dictionary(filename, log->dictionary, log->dictionary_size), error);
+  }
+
   lseek(fd, log->header.data_end, SEEK_SET);
 
   TRY(buf_init(&log->file_buf, 1024*1024), error);
@@ -176,12 +271,15 @@ sparkey_returncode sparkey_logwriter_append(sparkey_logwriter **log_ref, const c
   *log_ref = log;
   return SPARKEY_SUCCESS;
 error:
+  if (log != NULL) {
+    free(log->dictionary);
+  }
   free(log);
   if (fd > 0) close(fd);
   return returncode;
 }
 
-static sparkey_returncode flush_snappy(sparkey_logwriter *log) {
+static sparkey_returncode flush_block(sparkey_logwriter *log) {
   log->flushed = 1;
   if (log->entry_count > (int) log->header.max_entries_per_block) {
     log->header.max_entries_per_block = log->entry_count;
@@ -194,13 +292,31 @@ static sparkey_returncode flush_snappy(sparkey_logwriter *log) {
   int fd = log->fd;
 
   size_t compressed_size = max_compressed_size;
-  snappy_status status = snappy_compress((char *) block_buf->start, buf_used(block_buf), (char *) compressed, &compressed_size);
-  switch (status) {
-  case SNAPPY_OK: break;
-  case SNAPPY_INVALID_INPUT:
-  case SNAPPY_BUFFER_TOO_SMALL:
-  default:
-    return SPARKEY_INTERNAL_ERROR;
+  if (log->header.compression_type == SPARKEY_COMPRESSION_ZSTD && log->dictionary != NULL) {
+    if (log->zstd_cdict == NULL) {
+      log->zstd_cdict = ZSTD_createCDict(log->dictionary, log->dictionary_size, log->compression_level);
+      if (log->zstd_cdict == NULL) {
+        return SPARKEY_INTERNAL_ERROR;
+      }
+    }
+    compressed_size = ZSTD_compress_usingCDict(log->zstd_cctx, compressed, max_compressed_size, block_buf->start, buf_used(block_buf), log->zstd_cdict);
+    if (ZSTD_isError(compressed_size)) {
+      return SPARKEY_INTERNAL_ERROR;
+    }
+  } else if (log->header.compression_type == SPARKEY_COMPRESSION_ZSTD) {
+    compressed_size = ZSTD_compressCCtx(log->zstd_cctx, compressed, max_compressed_size, block_buf->start, buf_used(block_buf), log->compression_level);
+    if (ZSTD_isError(compressed_size)) {
+      return SPARKEY_INTERNAL_ERROR;
+    }
+  } else {
+    snappy_status status = snappy_compress((char *) block_buf->start, buf_used(block_buf), (char *) compressed, &compressed_size);
+    switch (status) {
+    case SNAPPY_OK: break;
+    case SNAPPY_INVALID_INPUT:
+    case SNAPPY_BUFFER_TOO_SMALL:
+    default:
+      return SPARKEY_INTERNAL_ERROR;
+    }
   }
   uint8_t buf1[10];
   ptrdiff_t written1 = write_vlq(buf1, compressed_size);
@@ -214,7 +330,7 @@ static sparkey_returncode flush_snappy(sparkey_logwriter *log) {
 sparkey_returncode sparkey_logwriter_flush(sparkey_logwriter *log) {
   RETHROW(assert_writer_open(log));
   if (buf_used(&log->block_buf) > 0) {
-    RETHROW(flush_snappy(log));
+    RETHROW(flush_block(log));
   }
   if (buf_used(&log->file_buf) > 0) {
     RETHROW(buf_flushfile(&log->file_buf, log->fd));
@@ -243,6 +359,9 @@ sparkey_returncode sparkey_logwriter_close(sparkey_logwriter **log) {
   if (l->compressed != NULL) {
     free(l->compressed);
   }
+  ZSTD_freeCCtx(l->zstd_cctx);
+  ZSTD_freeCDict(l->zstd_cdict);
+  free(l->dictionary);
 
   l->open_status = 0;
   free(l);
@@ -264,7 +383,7 @@ static sparkey_returncode snappy_add(sparkey_logwriter *log, const uint8_t *data
       block_buf->cur += remaining;
       data += remaining;
       len -= remaining;
-      RETHROW(flush_snappy(log));
+      RETHROW(flush_block(log));
     }
   }
   return SPARKEY_SUCCESS;
@@ -288,12 +407,13 @@ static sparkey_returncode log_add(sparkey_logwriter *log, uint64_t num1, uint64_
     RETHROW(buf_add(&log->file_buf, log->fd, data2, len2));
     break;
   case SPARKEY_COMPRESSION_SNAPPY:
+  case SPARKEY_COMPRESSION_ZSTD:
     remaining = buf_remaining(&log->block_buf);
     // todo: make it smarter by checking if it's better to flush directly
     uint64_t fits_in_one = written1 + written2 + len1 + len2 <= buf_size(&log->block_buf);
     uint64_t doesnt_fit_this = written1 + written2 + len1 + len2 > buf_remaining(&log->block_buf);
     if ((remaining < written1 + written2) || (fits_in_one && doesnt_fit_this)) {
-      RETHROW(flush_snappy(log));
+      RETHROW(flush_block(log));
     }
     log->entry_count++;
     log->flushed = 0;
@@ -302,7 +422,7 @@ static sparkey_returncode log_add(sparkey_logwriter *log, uint64_t num1, uint64_
     RETHROW(snappy_add(log, data1, len1));
     RETHROW(snappy_add(log, data2, len2));
     if (log->flushed && buf_used(&log->block_buf) > 0) {
-      RETHROW(flush_snappy(log));
+      RETHROW(flush_block(log));
     }
     break;
   default:
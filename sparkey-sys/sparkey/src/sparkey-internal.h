@@ -16,6 +16,7 @@
 #ifndef SPARKEY_INTERNAL_H
 #define SPARKEY_INTERNAL_H
 #include <stdint.h>
+#include <zstd.h>
 
 #include "sparkey.h"
 
@@ -30,6 +31,7 @@ struct sparkey_logreader {
 
   uint64_t data_len;
   uint8_t *data;
+  ZSTD_DDict *zstd_ddict;
 };
 
 struct sparkey_logiter {
@@ -46,6 +48,7 @@ struct sparkey_logiter {
   // compression buffer
   int compression_buf_allocated;
   uint8_t *compression_buf;
+  ZSTD_DCtx *zstd_dctx;
 
   // current entry
   uint64_t entry_block_position;
@@ -68,6 +71,11 @@ struct sparkey_logwriter {
   uint8_t *compressed;
   sparkey_buf file_buf;
   int flushed;
+  int compression_level;
+  ZSTD_CCtx *zstd_cctx;
+  uint8_t *dictionary;
+  uint32_t dictionary_size;
+  ZSTD_CDict *zstd_cdict;
 
   int entry_count;
 };
@@ -266,7 +266,8 @@ typedef struct sparkey_logwriter sparkey_logwriter;
 
 typedef enum {
   SPARKEY_COMPRESSION_NONE,
-  SPARKEY_COMPRESSION_SNAPPY
+  SPARKEY_COMPRESSION_SNAPPY,
+  SPARKEY_COMPRESSION_ZSTD
 } sparkey_compression_type;
 
 typedef enum {
@@ -295,13 +296,25 @@ typedef struct sparkey_hashreader sparkey_hashreader;
  * Creates a new Sparkey log file, possibly overwriting an already existing.
  * @param log a double reference to a sparkey_logwriter structure that gets allocated and initialized by this call.
  * @param filename the file to create.
- * @param compression_type NONE or SNAPPY, specifies if block compression should be used or not.
+ * @param compression_type NONE, SNAPPY or ZSTD, specifies if block compression should be used or not.
  * @param compression_block_size is only relevant if compression type is not NONE.
  * It represents the maximum number of bytes of an uncompressed block.
  * @return SPARKEY_SUCCESS if all goes well.
  */
 sparkey_returncode sparkey_logwriter_create(sparkey_logwriter **log, const char *filename, sparkey_compression_type compression_type, int compression_block_size);
 
+/**
+ * Creates a new ZSTD compressed Sparkey log file where every block is compressed with a shared dictionary.
+ * The dictionary is stored in the log header, so readers don't need to be given it separately.
+ * @param log a double reference to a sparkey_logwriter structure that gets allocated and initialized by this call.
+ * @param filename the file to create.
+ * @param compression_block_size the maximum number of bytes of an uncompressed block.
+ * @param dictionary the dictionary, typically trained with ZDICT_trainFromBuffer.
+ * @param dictionary_size the size of the dictionary in bytes.
+ * @return SPARKEY_SUCCESS if all goes well.
+ */
+sparkey_returncode sparkey_logwriter_create_dictionary(sparkey_logwriter **log, const char *filename, int compression_block_size, const uint8_t *dictionary, uint32_t dictionary_size);
+
 /**
  * Append to an existing Sparkey log file.
  * @param log a double reference to a sparkey_logwriter structure that gets allocated and initialized by this call.
@@ -592,6 +605,13 @@ uint64_t sparkey_logiter_valuelen(sparkey_logiter *iter);
  */
 sparkey_returncode sparkey_hash_write(const char *hash_filename, const char *log_filename, int hash_size);
 
+/**
+ * Like sparkey_hash_write, but also reports how the hash table was written.
+ * @param incremental set to 1 if the existing hash file was reused and only the new log entries
+ *        were added to it, or 0 if the hash table was built from scratch. May be NULL.
+ */
+sparkey_returncode sparkey_hash_write_report(const char *hash_filename, const char *log_filename, int hash_size, int *incremental);
+
 /* hashreader */
 /**
  * Opens a hash file and a log file for reading. The the hashreader is threadsafe, except during opening or closing.
@@ -630,6 +650,13 @@ void sparkey_hash_close(sparkey_hashreader **reader);
  */
 sparkey_returncode sparkey_hash_get(sparkey_hashreader *reader, const uint8_t *key, uint64_t keylen, sparkey_logiter *iter);
 
+/**
+ * Like sparkey_hash_get, but with the hash of the key already computed, as
+ * sparkey_hash_key computes it.
+ * @param hash the hash of the key.
+ */
+sparkey_returncode sparkey_hash_get_hashed(sparkey_hashreader *reader, const uint8_t *key, uint64_t keylen, uint64_t hash, sparkey_logiter *iter);
+
 /**
  * Works the same as sparkey_logiter_next, except it skips entries that are not of type SPARKEY_ENTRY_PUT
  * and entries that have been overwritten or deleted. Thus it only stops at live entries.
@@ -0,0 +1,223 @@
+/*
+ * Accessors for parts of the Sparkey internals that the public API does not
+ * expose.  The vendored sources are patched to add zstd block compression
+ * and dictionaries, and the two entry points that need their static helpers
+ * (sparkey_hash_write_report and sparkey_hash_get_hashed); everything else
+ * lives here, to keep that patch small.
+ */
+#include <stdint.h>
+#include <stdlib.h>
+#include <unistd.h>
+
+#include <snappy-c.h>
+#include <zdict.h>
+
+#include "sparkey.h"
+#include "sparkey-internal.h"
+#include "hashheader.h"
+#include "util.h"
+
+const sparkey_logheader *sparkey_logreader_header(sparkey_logreader *log) {
+  return &log->header;
+}
+
+const sparkey_logheader *sparkey_logwriter_header(sparkey_logwriter *log) {
+  return &log->header;
+}
+
+const sparkey_hashheader *sparkey_hash_header(sparkey_hashreader *reader) {
+  return &reader->header;
+}
+
+uint64_t sparkey_logreader_header_size(sparkey_logreader *log) {
+  return log->header.header_size;
+}
+
+uint64_t sparkey_logreader_data_end(sparkey_logreader *log) {
+  return log->header.data_end;
+}
+
+sparkey_returncode sparkey_logiter_tell(sparkey_logiter *iter, sparkey_logreader *log, uint64_t *position, int *entry_index) {
+  uint8_t *chunk;
+  uint64_t len;
+
+  switch (iter->state) {
+  case SPARKEY_ITER_CLOSED:
+    *position = log->header.data_end;
+    *entry_index = 0;
+    return SPARKEY_SUCCESS;
+  case SPARKEY_ITER_ACTIVE:
+    // Consume the rest of the current entry so that the offsets below point past it
+    do {
+      RETHROW(sparkey_logiter_valuechunk(iter, log, UINT64_MAX, &chunk, &len));
+    } while (len > 0);
+    break;
+  case SPARKEY_ITER_NEW:
+    break;
+  default:
+    return SPARKEY_LOG_ITERATOR_INACTIVE;
+  }
+
+  if (iter->block_offset >= iter->block_len) {
+    // Blocks always start at an entry boundary, since the writer flushes after entries
+    // spanning several blocks
+    *position = iter->next_block_position < log->header.data_end ? iter->next_block_position : log->header.data_end;
+    *entry_index = 0;
+  } else if (log->header.compression_type == SPARKEY_COMPRESSION_NONE) {
+    *position = iter->block_position + iter->block_offset;
+    *entry_index = 0;
+  } else {
+    *position = iter->block_position;
+    *entry_index = iter->entry_count + 1;
+  }
+  return SPARKEY_SUCCESS;
+}
+
+void sparkey_logwriter_set_compression_level(sparkey_logwriter *log, int level) {
+  log->compression_level = level;
+  // The dictionary is digested for a specific level, so it has to be rebuilt
+  ZSTD_freeCDict(log->zstd_cdict);
+  log->zstd_cdict = NULL;
+}
+
+// Releases a writer without flushing it, for when `sparkey_logwriter_close` failed and left it
+// open.  Anything that wasn't flushed yet is lost.
+void sparkey_logwriter_discard(sparkey_logwriter **log) {
+  sparkey_logwriter *l = *log;
+  if (l == NULL) {
+    return;
+  }
+  close(l->fd);
+  buf_close(&l->file_buf);
+  buf_close(&l->block_buf);
+  free(l->compressed);
+  ZSTD_freeCCtx(l->zstd_cctx);
+  ZSTD_freeCDict(l->zstd_cdict);
+  free(l->dictionary);
+  l->open_status = 0;
+  free(l);
+  *log = NULL;
+}
+
+static sparkey_returncode read_vlq(sparkey_logreader *log, uint64_t *position, uint64_t *value) {
+  uint64_t result = 0;
+  int shift = 0;
+  while (1) {
+    if (*position >= log->header.data_end || shift > 63) {
+      return SPARKEY_UNEXPECTED_EOF;
+    }
+    uint8_t b = log->data[(*position)++];
+    result |= (uint64_t) (b & 0x7f) << shift;
+    if (b < 0x80) {
+      break;
+    }
+    shift += 7;
+  }
+  *value = result;
+  return SPARKEY_SUCCESS;
+}
+
+// Finds the first offset at or after `min` where a range of entries can be read independently,
+// walking from `position`, which has to be one, and sets `*next` to it, or to data_end if there is
+// none.  Any entry can start a range in an uncompressed log.  In a compressed log, entries can
+// span several blocks, but the writer only fills a block up completely when the entry continues
+// in the next one (or happens to end right there), so only blocks after a partially filled one
+// can start a range.
+sparkey_returncode sparkey_logreader_next_range(sparkey_logreader *log, uint64_t position, uint64_t min, uint64_t *next) {
+  uint64_t end = log->header.data_end;
+
+  if (log->header.compression_type == SPARKEY_COMPRESSION_NONE) {
+    while (position < min && position < end) {
+      uint64_t a, b;
+      RETHROW(read_vlq(log, &position, &a));
+      RETHROW(read_vlq(log, &position, &b));
+      uint64_t len = a == 0 ? b : a - 1 + b;
+      if (len < b || len > end - position) {
+        return SPARKEY_UNEXPECTED_EOF;
+      }
+      position += len;
+    }
+    *next = position < end ? position : end;
+    return SPARKEY_SUCCESS;
+  }
+
+  int starts_entry = 1;
+  while (position < end) {
+    if (position >= min && starts_entry) {
+      *next = position;
+      return SPARKEY_SUCCESS;
+    }
+    uint64_t size;
+    RETHROW(read_vlq(log, &position, &size));
+    if (size > end - position) {
+      return SPARKEY_UNEXPECTED_EOF;
+    }
+    uint64_t uncompressed_size = log->header.compression_block_size;
+    if (log->header.compression_type == SPARKEY_COMPRESSION_SNAPPY) {
+      size_t len;
+      if (snappy_uncompressed_length((const char *) &log->data[position], size, &len) == SNAPPY_OK) {
+        uncompressed_size = len;
+      }
+    } else {
+      unsigned long long len = ZSTD_getFrameContentSize(&log->data[position], size);
+      if (len != ZSTD_CONTENTSIZE_UNKNOWN && len != ZSTD_CONTENTSIZE_ERROR) {
+        uncompressed_size = len;
+      }
+    }
+    starts_entry = uncompressed_size < log->header.compression_block_size;
+    position += size;
+  }
+  *next = end;
+  return SPARKEY_SUCCESS;
+}
+
+// Returns the log address stored in a slot of the hash table, or 0 if the slot is empty.
+uint64_t sparkey_hash_address(sparkey_hashreader *reader, uint64_t slot) {
+  uint8_t *hashtable = reader->data + reader->header.header_size;
+  int slot_size = reader->header.address_size + reader->header.hash_size;
+  return read_addr(hashtable, slot * slot_size + reader->header.hash_size, reader->header.address_size);
+}
+
+// Returns the hash of a key, as sparkey_hash_get computes it.
+uint64_t sparkey_hash_key(sparkey_hashreader *reader, const uint8_t *key, uint64_t keylen) {
+  return reader->header.hash_algorithm.hash(key, keylen, reader->header.hash_seed);
+}
+
+// Returns the log address of the first entry with the given hash that a lookup would read, or 0
+// if a lookup wouldn't read any.
+uint64_t sparkey_hash_first_address(sparkey_hashreader *reader, uint64_t hash) {
+  uint64_t capacity = reader->header.hash_capacity;
+  if (capacity == 0) {
+    return 0;
+  }
+  uint8_t *hashtable = reader->data + reader->header.header_size;
+  int slot_size = reader->header.address_size + reader->header.hash_size;
+
+  uint64_t slot = hash % capacity;
+  for (uint64_t displacement = 0; displacement < capacity; displacement++) {
+    uint64_t pos = slot * slot_size;
+    uint64_t hash2 = reader->header.hash_algorithm.read_hash(hashtable, pos);
+    uint64_t address = read_addr(hashtable, pos + reader->header.hash_size, reader->header.address_size);
+    if (address == 0) {
+      return 0;
+    }
+    if (hash == hash2) {
+      return address;
+    }
+    if (displacement > get_displacement(capacity, slot, hash2)) {
+      return 0;
+    }
+    slot = slot + 1 < capacity ? slot + 1 : 0;
+  }
+  return 0;
+}
+
+// Returns NULL on success, or a description of why no dictionary could be trained.
+const char *sparkey_train_dictionary(uint8_t *dictionary, size_t *dictionary_size, const uint8_t *samples, const size_t *sample_sizes, unsigned num_samples) {
+  size_t result = ZDICT_trainFromBuffer(dictionary, *dictionary_size, samples, sample_sizes, num_samples);
+  if (ZDICT_isError(result)) {
+    return ZDICT_getErrorName(result);
+  }
+  *dictionary_size = result;
+  return NULL;
+}
@@ -6,6 +6,10 @@
     non_upper_case_globals,
     non_snake_case
 )]
+
+// Only linked for the libzstd symbols that the C library uses
+extern crate zstd_sys;
+
 pub type int8_t = i8;
 pub type int16_t = i16;
 pub type int32_t = i32;
@@ -79,6 +83,7 @@ pub enum logwriter {}
 pub enum compression_type {
     COMPRESSION_NONE = 0,
     COMPRESSION_SNAPPY = 1,
+    COMPRESSION_ZSTD = 2,
 }
 #[derive(Copy, Clone)]
 #[repr(u32)]
@@ -99,6 +104,59 @@ pub enum iter_state {
 pub enum logreader {}
 pub enum logiter {}
 pub enum hashreader {}
+#[repr(C)]
+#[derive(Debug, Copy, Clone)]
+pub struct logheader {
+    pub major_version: uint32_t,
+    pub minor_version: uint32_t,
+    pub file_identifier: uint32_t,
+    pub num_puts: uint64_t,
+    pub num_deletes: uint64_t,
+    pub data_end: uint64_t,
+    pub max_key_len: uint64_t,
+    pub max_value_len: uint64_t,
+    pub delete_size: uint64_t,
+    pub compression_type: compression_type,
+    pub compression_block_size: uint32_t,
+    pub put_size: uint64_t,
+    pub header_size: uint32_t,
+    pub max_entries_per_block: uint32_t,
+}
+#[repr(C)]
+#[derive(Debug, Copy, Clone)]
+pub struct hash_algorithm {
+    pub hash: ::std::option::Option<
+        unsafe extern "C" fn(data: *const uint8_t, len: uint64_t, seed: uint32_t) -> uint64_t,
+    >,
+    pub read_hash: ::std::option::Option<
+        unsafe extern "C" fn(data: *const uint8_t, pos: uint64_t) -> uint64_t,
+    >,
+    pub write_hash: ::std::option::Option<unsafe extern "C" fn(data: *mut uint8_t, hash: uint64_t)>,
+}
+#[repr(C)]
+#[derive(Debug, Copy, Clone)]
+pub struct hashheader {
+    pub major_version: uint32_t,
+    pub minor_version: uint32_t,
+    pub file_identifier: uint32_t,
+    pub hash_seed: uint32_t,
+    pub header_size: uint32_t,
+    pub data_end: uint64_t,
+    pub max_key_len: uint64_t,
+    pub max_value_len: uint64_t,
+    pub garbage_size: uint64_t,
+    pub num_entries: uint64_t,
+    pub address_size: uint32_t,
+    pub hash_size: uint32_t,
+    pub hash_capacity: uint64_t,
+    pub max_displacement: uint64_t,
+    pub num_puts: uint64_t,
+    pub entry_block_bits: uint32_t,
+    pub entry_block_bitmask: uint32_t,
+    pub hash_collisions: uint64_t,
+    pub total_displacement: uint64_t,
+    pub hash_algorithm: hash_algorithm,
+}
 extern "C" {
     #[link_name = "sparkey_errstring"]
     pub fn errstring(code: returncode) -> *const ::std::os::raw::c_char;
@@ -109,6 +167,14 @@ extern "C" {
         compression_type: compression_type,
         compression_block_size: ::std::os::raw::c_int,
     ) -> returncode;
+    #[link_name = "sparkey_logwriter_create_dictionary"]
+    pub fn logwriter_create_dictionary(
+        log: *mut *mut logwriter,
+        filename: *const ::std::os::raw::c_char,
+        compression_block_size: ::std::os::raw::c_int,
+        dictionary: *const uint8_t,
+        dictionary_size: uint32_t,
+    ) -> returncode;
     #[link_name = "sparkey_logwriter_append"]
     pub fn logwriter_append(
         log: *mut *mut logwriter,
@@ -216,6 +282,13 @@ extern "C" {
         log_filename: *const ::std::os::raw::c_char,
         hash_size: ::std::os::raw::c_int,
     ) -> returncode;
+    #[link_name = "sparkey_hash_write_report"]
+    pub fn hash_write_report(
+        hash_filename: *const ::std::os::raw::c_char,
+        log_filename: *const ::std::os::raw::c_char,
+        hash_size: ::std::os::raw::c_int,
+        incremental: *mut ::std::os::raw::c_int,
+    ) -> returncode;
     #[link_name = "sparkey_hash_open"]
     pub fn hash_open(
         reader: *mut *mut hashreader,
@@ -233,6 +306,14 @@ extern "C" {
         keylen: uint64_t,
         iter: *mut logiter,
     ) -> returncode;
+    #[link_name = "sparkey_hash_get_hashed"]
+    pub fn hash_get_hashed(
+        reader: *mut hashreader,
+        key: *const uint8_t,
+        keylen: uint64_t,
+        hash: uint64_t,
+        iter: *mut logiter,
+    ) -> returncode;
     #[link_name = "sparkey_logiter_hashnext"]
     pub fn logiter_hashnext(iter: *mut logiter, reader: *mut hashreader) -> returncode;
     #[link_name = "sparkey_hash_numentries"]
@@ -247,4 +328,56 @@ extern "C" {
     pub fn create_index_filename(
         log_filename: *const ::std::os::raw::c_char,
     ) -> *mut ::std::os::raw::c_char;
+    #[link_name = "sparkey_load_logheader"]
+    pub fn load_logheader(
+        header: *mut logheader,
+        filename: *const ::std::os::raw::c_char,
+    ) -> returncode;
+    #[link_name = "sparkey_load_hashheader"]
+    pub fn load_hashheader(
+        header: *mut hashheader,
+        filename: *const ::std::os::raw::c_char,
+    ) -> returncode;
+    #[link_name = "sparkey_logreader_header"]
+    pub fn logreader_header(log: *mut logreader) -> *const logheader;
+    #[link_name = "sparkey_logwriter_header"]
+    pub fn logwriter_header(log: *mut logwriter) -> *const logheader;
+    #[link_name = "sparkey_logwriter_discard"]
+    pub fn logwriter_discard(log: *mut *mut logwriter);
+    #[link_name = "sparkey_logwriter_set_compression_level"]
+    pub fn logwriter_set_compression_level(log: *mut logwriter, level: ::std::os::raw::c_int);
+    #[link_name = "sparkey_logreader_next_range"]
+    pub fn logreader_next_range(
+        log: *mut logreader,
+        position: uint64_t,
+        min: uint64_t,
+        next: *mut uint64_t,
+    ) -> returncode;
+    #[link_name = "sparkey_hash_address"]
+    pub fn hash_address(reader: *mut hashreader, slot: uint64_t) -> uint64_t;
+    #[link_name = "sparkey_hash_key"]
+    pub fn hash_key(reader: *mut hashreader, key: *const uint8_t, keylen: uint64_t) -> uint64_t;
+    #[link_name = "sparkey_hash_first_address"]
+    pub fn hash_first_address(reader: *mut hashreader, hash: uint64_t) -> uint64_t;
+    #[link_name = "sparkey_train_dictionary"]
+    pub fn train_dictionary(
+        dictionary: *mut uint8_t,
+        dictionary_size: *mut usize,
+        samples: *const uint8_t,
+        sample_sizes: *const usize,
+        num_samples: ::std::os::raw::c_uint,
+    ) -> *const ::std::os::raw::c_char;
+    #[link_name = "sparkey_hash_header"]
+    pub fn hash_header(reader: *mut hashreader) -> *const hashheader;
+    #[link_name = "sparkey_logreader_header_size"]
+    pub fn logreader_header_size(log: *mut logreader) -> uint64_t;
+    #[link_name = "sparkey_logreader_data_end"]
+    pub fn logreader_data_end(log: *mut logreader) -> uint64_t;
+    #[link_name = "sparkey_logiter_tell"]
+    pub fn logiter_tell(
+        iter: *mut logiter,
+        log: *mut logreader,
+        position: *mut uint64_t,
+        entry_index: *mut ::std::os::raw::c_int,
+    ) -> returncode;
 }
@@ -0,0 +1,198 @@
+use std::collections;
+use std::future;
+use std::io;
+use std::path;
+use std::pin;
+use std::sync;
+use std::task;
+
+use crate::error;
+use crate::hash;
+use crate::log;
+
+// Number of entries that `Entries` reads per blocking task
+const BATCH_SIZE: usize = 1024;
+
+// Runs lookups against a `hash::Reader` on tokio's blocking pool, so that page faults against
+// cold parts of the mmap don't stall the async workers.  At most `max_concurrency` lookups run
+// at the same time; clones share both the reader and the limit.
+#[derive(Clone)]
+pub struct AsyncReader(sync::Arc<hash::Reader>, sync::Arc<tokio::sync::Semaphore>);
+
+// The entries that were read before an error are kept, and the error is yielded after them
+type Batch = error::Result<(
+    log::Entries<'static>,
+    collections::VecDeque<log::Entry>,
+    Option<error::Error>,
+)>;
+
+pub struct Entries {
+    reader: AsyncReader,
+    // `None` before the first batch has been read
+    iter: Option<log::Entries<'static>>,
+    batch: collections::VecDeque<log::Entry>,
+    error: Option<error::Error>,
+    done: bool,
+    pending: Option<pin::Pin<Box<dyn future::Future<Output = Batch> + Send>>>,
+}
+
+impl AsyncReader {
+    pub fn new(reader: hash::Reader, max_concurrency: usize) -> Self {
+        AsyncReader(
+            sync::Arc::new(reader),
+            sync::Arc::new(tokio::sync::Semaphore::new(max_concurrency.max(1))),
+        )
+    }
+
+    pub async fn open<P1, P2>(
+        hash_path: P1,
+        log_path: P2,
+        max_concurrency: usize,
+    ) -> error::Result<Self>
+    where
+        P1: AsRef<path::Path>,
+        P2: AsRef<path::Path>,
+    {
+        let hash_path = hash_path.as_ref().to_path_buf();
+        let log_path = log_path.as_ref().to_path_buf();
+
+        let reader = run_blocking(move || hash::Reader::open(&hash_path, &log_path)).await?;
+
+        Ok(Self::new(reader, max_concurrency))
+    }
+
+    pub fn reader(&self) -> &hash::Reader {
+        &self.0
+    }
+
+    pub async fn get(&self, key: &[u8]) -> error::Result<Option<bytes::BytesMut>> {
+        let key = key.to_vec();
+        self.run(move |reader| reader.get(&key)).await
+    }
+
+    pub async fn multi_get<K>(
+        &self,
+        keys: Vec<K>,
+        order: hash::ProbeOrder,
+    ) -> error::Result<Vec<Option<bytes::BytesMut>>>
+    where
+        K: AsRef<[u8]> + Send + 'static,
+    {
+        self.run(move |reader| reader.multi_get(&keys, order)).await
+    }
+
+    // Streams the live entries in log order, reading them in batches on the blocking pool.  The
+    // stream ends after the first error.
+    pub fn entries(&self) -> Entries {
+        Entries {
+            reader: self.clone(),
+            iter: None,
+            batch: collections::VecDeque::new(),
+            error: None,
+            done: false,
+            pending: None,
+        }
+    }
+
+    async fn run<F, A>(&self, f: F) -> error::Result<A>
+    where
+        F: FnOnce(&hash::Reader) -> error::Result<A> + Send + 'static,
+        A: Send + 'static,
+    {
+        let permit = self
+            .1
+            .clone()
+            .acquire_owned()
+            .await
+            .expect("the semaphore is never closed");
+        let reader = self.0.clone();
+
+        run_blocking(move || {
+            let result = f(&reader);
+            drop(permit);
+            result
+        })
+        .await
+    }
+}
+
+async fn run_blocking<F, A>(f: F) -> error::Result<A>
+where
+    F: FnOnce() -> error::Result<A> + Send + 'static,
+    A: Send + 'static,
+{
+    match tokio::task::spawn_blocking(f).await {
+        Ok(result) => result,
+        Err(e) if e.is_panic() => std::panic::resume_unwind(e.into_panic()),
+        Err(e) => Err(error::Error::IO(io::Error::other(e))),
+    }
+}
+
+impl futures_core::Stream for Entries {
+    type Item = error::Result<log::Entry>;
+
+    fn poll_next(
+        mut self: pin::Pin<&mut Self>,
+        cx: &mut task::Context<'_>,
+    ) -> task::Poll<Option<Self::Item>> {
+        loop {
+            if let Some(entry) = self.batch.pop_front() {
+                return task::Poll::Ready(Some(Ok(entry)));
+            }
+
+            if let Some(e) = self.error.take() {
+                return task::Poll::Ready(Some(Err(e)));
+            }
+
+            if let Some(pending) = self.pending.as_mut() {
+                let result = match pending.as_mut().poll(cx) {
+                    task::Poll::Ready(result) => result,
+                    task::Poll::Pending => return task::Poll::Pending,
+                };
+                self.pending = None;
+
+                match result {
+                    Ok((iter, batch, error)) => {
+                        if error.is_some() || batch.len() < BATCH_SIZE {
+                            self.done = true;
+                        } else {
+                            self.iter = Some(iter);
+                        }
+                        self.batch = batch;
+                        self.error = error;
+                        continue;
+                    }
+                    Err(e) => {
+                        self.done = true;
+                        return task::Poll::Ready(Some(Err(e)));
+                    }
+                }
+            }
+
+            if self.done {
+                return task::Poll::Ready(None);
+            }
+
+            let iter = self.iter.take();
+            let reader = self.reader.clone();
+            self.pending = Some(Box::pin(async move {
+                reader
+                    .run(move |reader| {
+                        let mut iter = match iter {
+                            Some(iter) => iter,
+                            None => reader.clone().into_entries()?,
+                        };
+                        let mut batch = collections::VecDeque::with_capacity(BATCH_SIZE);
+                        for entry in (&mut iter).take(BATCH_SIZE) {
+                            match entry {
+                                Ok(entry) => batch.push_back(entry),
+                                Err(e) => return Ok((iter, batch, Some(e))),
+                            }
+                        }
+                        Ok((iter, batch, None))
+                    })
+                    .await
+            }));
+        }
+    }
+}
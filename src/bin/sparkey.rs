@@ -83,7 +83,8 @@ enum Command {
         index_format: IndexFormatOptions,
     },
     /// Prunes an existing log (.spl) file, making sure there is only one entry per index (.spi)
-    /// file entry, and writes the corresponding index (.spi) file
+    /// file entry, and publishes it with the corresponding index (.spi) file as a new generation
+    /// of a store
     #[structopt(name = "prune")]
     Prune {
         #[structopt(flatten)]
@@ -92,8 +93,8 @@ enum Command {
         #[structopt(flatten)]
         log_format: LogFormatOptions,
 
-        /// The path at which the new pruned sparkey file will be written (either the .spi or .spl
-        /// file, or the path without an extension)
+        /// The store directory under which the new pruned sparkey files will be published; readers
+        /// find them through its CURRENT file
         output: path::PathBuf,
     },
 }
@@ -191,6 +192,10 @@ struct LogFormatOptions {
     /// Compression block size to use
     #[structopt(long = "compression-block-size", short = "b", default_value = "4096")]
     compression_block_size: u32,
+
+    /// Compression level to use with zstd (0 means zstd's default level)
+    #[structopt(long = "compression-level", default_value = "0")]
+    compression_level: i32,
 }
 
 arg_enum! {
@@ -209,6 +214,7 @@ arg_enum! {
     enum CompressionAlgorithm {
         none,
         snappy,
+        zstd,
     }
 }
 
@@ -283,7 +289,7 @@ fn run() -> Result<(), failure::Error> {
             let mut writer = if !log_file.exists() && auto_create {
                 sparkey::log::Writer::create(
                     &log_file,
-                    log_format.compression_algorithm.into(),
+                    log_format.compression_type(),
                     log_format.compression_block_size,
                 )?
             } else {
@@ -295,8 +301,7 @@ fn run() -> Result<(), failure::Error> {
             debug!("raw value is {:?}", value);
 
             writer.put(&key, &value)?;
-            writer.flush()?;
-            drop(writer);
+            writer.close()?;
 
             if auto_index {
                 debug!("performing automatic index");
@@ -331,10 +336,10 @@ fn run() -> Result<(), failure::Error> {
         } => {
             sparkey::log::Writer::create(
                 &log_file,
-                log_format.compression_algorithm.into(),
+                log_format.compression_type(),
                 log_format.compression_block_size,
             )?
-            .flush()?;
+            .close()?;
 
             if index {
                 sparkey::hash::Writer::write(
@@ -356,28 +361,20 @@ fn run() -> Result<(), failure::Error> {
             log_format,
             output,
         } => {
-            let output_index = output.with_extension("spi");
-            let output_log = output.with_extension("spl");
-
-            let reader = sparkey::hash::Reader::open(index_file, log_file)?;
-            let mut writer = sparkey::log::Writer::create(
-                &output_log,
-                log_format.compression_algorithm.into(),
-                log_format.compression_block_size,
-            )?;
-
-            for entry in reader.entries()? {
-                let entry = entry?;
-                writer.put(&entry.key, &entry.value)?;
-            }
-
-            debug!("writing index");
-
-            sparkey::hash::Writer::write(
-                &output_index,
-                &output_log,
-                index_format.hash_algorithm.map(From::from),
-            )?;
+            let options = sparkey::CompactOptions {
+                compression_type: Some(log_format.compression_type()),
+                compression_block_size: Some(log_format.compression_block_size),
+                hash_type: index_format.hash_algorithm.map(From::from),
+                ..Default::default()
+            };
+            let report = sparkey::compact(&path, &output, options)?;
+            debug!(
+                "pruned {} bytes of garbage, from {} to {} bytes, into generation {:?}",
+                report.reclaimed_bytes(),
+                report.old_size,
+                report.new_size,
+                report.generation
+            );
         }
     }
 
@@ -408,18 +405,49 @@ fn init_log() {
 }
 
 fn show_index(reader: &sparkey::hash::Reader) {
-    println!("index_num_entries\t{}", reader.num_entries());
-    println!("index_num_collisions\t{}", reader.num_collisions());
+    let header = reader.header();
+    println!(
+        "index_version\t{}.{}",
+        header.major_version, header.minor_version
+    );
+    println!("index_file_identifier\t{:08x}", header.file_identifier);
+    println!("index_hash_type\t{:?}", header.hash_type);
+    println!("index_hash_seed\t{:08x}", header.hash_seed);
+    println!("index_hash_capacity\t{}", header.hash_capacity);
+    println!("index_address_size\t{}", header.address_size);
+    println!("index_entry_block_bits\t{}", header.entry_block_bits);
+    println!("index_data_end\t{}", header.data_end);
+    println!("index_num_puts\t{}", header.num_puts);
+    println!("index_num_entries\t{}", header.num_entries);
+    println!("index_num_collisions\t{}", header.num_collisions);
+    println!("index_garbage_size\t{}", header.garbage_size);
+    println!("index_max_displacement\t{}", header.max_displacement);
+    println!("index_total_displacement\t{}", header.total_displacement);
 }
 
 fn show_log(reader: &sparkey::log::Reader) {
-    println!("log_max_key_len\t{}", reader.max_key_len());
-    println!("log_max_value_len\t{}", reader.max_value_len());
+    let header = reader.header();
+    println!(
+        "log_version\t{}.{}",
+        header.major_version, header.minor_version
+    );
+    println!("log_file_identifier\t{:08x}", header.file_identifier);
+    println!("log_data_end\t{}", header.data_end);
+    println!("log_num_puts\t{}", header.num_puts);
+    println!("log_num_deletes\t{}", header.num_deletes);
+    println!("log_put_size\t{}", header.put_size);
+    println!("log_delete_size\t{}", header.delete_size);
+    println!("log_max_key_len\t{}", header.max_key_len);
+    println!("log_max_value_len\t{}", header.max_value_len);
     println!(
         "log_compression_block_size\t{}",
-        reader.compression_block_size()
+        header.compression_block_size
+    );
+    println!("log_compression_type\t{}", header.compression_type);
+    println!(
+        "log_max_entries_per_block\t{}",
+        header.max_entries_per_block
     );
-    println!("log_compression_type\t{}", reader.compression_type());
 }
 
 fn decode(format: Format, data: String) -> Result<bytes::BytesMut, failure::Error> {
@@ -462,11 +490,14 @@ impl ValueFormatOptions {
     }
 }
 
-impl From<CompressionAlgorithm> for sparkey::log::CompressionType {
-    fn from(value: CompressionAlgorithm) -> Self {
-        match value {
+impl LogFormatOptions {
+    fn compression_type(&self) -> sparkey::log::CompressionType {
+        match self.compression_algorithm {
             CompressionAlgorithm::none => sparkey::log::CompressionType::None,
             CompressionAlgorithm::snappy => sparkey::log::CompressionType::Snappy,
+            CompressionAlgorithm::zstd => sparkey::log::CompressionType::Zstd {
+                level: self.compression_level,
+            },
         }
     }
 }
@@ -0,0 +1,200 @@
+use std::fs;
+use std::io;
+use std::io::Read;
+use std::path;
+
+use crate::error;
+use crate::hash;
+use crate::log;
+use crate::store;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct CompactOptions {
+    // `None` keeps the compression type or block size of the source log
+    pub compression_type: Option<log::CompressionType>,
+    pub compression_block_size: Option<u32>,
+    // `None` picks the hash type from the number of entries
+    pub hash_type: Option<hash::Type>,
+    // Skip compaction when the garbage ratio is below this
+    pub min_garbage_ratio: f64,
+    pub garbage_ratio_base: GarbageRatioBase,
+}
+
+// What the garbage size of the index is divided by to get the garbage ratio.  The garbage size
+// counts uncompressed entry bytes, so for compressed logs the two differ, and the ratio against
+// `DataEnd` can be above 1.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum GarbageRatioBase {
+    // The end of the log data in the index header, which is what the file takes up on disk
+    DataEnd,
+    // The uncompressed size of the puts and deletes in the log
+    EntrySize,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct CompactReport {
+    // `false` if the garbage ratio was below `min_garbage_ratio`, and nothing was written
+    pub compacted: bool,
+    pub garbage_ratio: f64,
+    // The generation that the compacted store was published as
+    pub generation: Option<u64>,
+    // Combined size of the log and index files, before and after
+    pub old_size: u64,
+    pub new_size: u64,
+}
+
+impl Default for CompactOptions {
+    fn default() -> Self {
+        CompactOptions {
+            compression_type: None,
+            compression_block_size: None,
+            hash_type: None,
+            min_garbage_ratio: 0.0,
+            garbage_ratio_base: GarbageRatioBase::DataEnd,
+        }
+    }
+}
+
+impl CompactReport {
+    pub fn reclaimed_bytes(&self) -> u64 {
+        self.old_size.saturating_sub(self.new_size)
+    }
+}
+
+// Rewrites the log at `src`, a base path like `store::Store::open` takes, with only the live
+// entries of its index, and publishes it with a new index as the next generation of the store
+// under `root` (see `store::Store::publish`).  Readers of `root` switch from one whole pair to the
+// other, and the files at `src` are left alone; to compact a store in place, pass the log path of
+// its current generation as `src`.
+pub fn compact<P1, P2>(src: P1, root: P2, options: CompactOptions) -> error::Result<CompactReport>
+where
+    P1: AsRef<path::Path>,
+    P2: AsRef<path::Path>,
+{
+    let (src_log, src_index) = store::file_paths(src.as_ref());
+
+    let log_header = log::LogHeader::read(&src_log)?;
+    let hash_header = hash::HashHeader::read(&src_index)?;
+    let old_size = file_size(&src_log)? + file_size(&src_index)?;
+
+    let base = match options.garbage_ratio_base {
+        GarbageRatioBase::DataEnd => hash_header.data_end,
+        GarbageRatioBase::EntrySize => log_header.put_size + log_header.delete_size,
+    };
+    let garbage_ratio = if base == 0 {
+        0.0
+    } else {
+        hash_header.garbage_size as f64 / base as f64
+    };
+
+    if garbage_ratio < options.min_garbage_ratio {
+        return Ok(CompactReport {
+            compacted: false,
+            garbage_ratio,
+            generation: None,
+            old_size,
+            new_size: old_size,
+        });
+    }
+
+    let store_options = store::StoreOptions {
+        compression_type: options
+            .compression_type
+            .unwrap_or(log_header.compression_type),
+        compression_block_size: options
+            .compression_block_size
+            .unwrap_or(log_header.compression_block_size),
+        hash_type: options.hash_type,
+    };
+    let build = store::Store::create_generation(root, store_options)?;
+    if let Err(e) = write_compacted(&src_log, &src_index, &log_header, &build, &store_options) {
+        build.discard();
+        return Err(e);
+    }
+    let store = build.publish()?;
+
+    Ok(CompactReport {
+        compacted: true,
+        garbage_ratio,
+        generation: store.generation(),
+        old_size,
+        new_size: file_size(store.log_path())? + file_size(store.index_path())?,
+    })
+}
+
+fn write_compacted(
+    src_log: &path::Path,
+    src_index: &path::Path,
+    log_header: &log::LogHeader,
+    build: &store::Store,
+    options: &store::StoreOptions,
+) -> error::Result<()> {
+    // The empty index of the build would otherwise be updated incrementally instead of rewritten
+    match fs::remove_file(build.index_path()) {
+        Ok(()) => {}
+        Err(ref e) if e.kind() == io::ErrorKind::NotFound => {}
+        Err(e) => return Err(error::Error::IO(e)),
+    }
+
+    let reader = hash::Reader::open(src_index, src_log)?;
+    let mut writer = match read_dictionary(src_log, log_header)? {
+        Some(ref dictionary)
+            if options
+                .compression_type
+                .same_algorithm(&log_header.compression_type) =>
+        {
+            log::Writer::create_with_dictionary(
+                build.log_path(),
+                options.compression_type,
+                options.compression_block_size,
+                dictionary,
+            )?
+        }
+        _ => log::Writer::create(
+            build.log_path(),
+            options.compression_type,
+            options.compression_block_size,
+        )?,
+    };
+
+    for entry in reader.entries()? {
+        let entry = entry?;
+        writer.put(&entry.key, &entry.value)?;
+    }
+    writer.close()?;
+
+    hash::Writer::write(build.index_path(), build.log_path(), options.hash_type)
+}
+
+// The zstd dictionary that the log was written with, which is kept as long as the log stays
+// zstd compressed
+fn read_dictionary(
+    path: &path::Path,
+    log_header: &log::LogHeader,
+) -> error::Result<Option<Vec<u8>>> {
+    if log_header.minor_version < 2 {
+        return Ok(None);
+    }
+
+    let mut data = vec![0; log_header.header_size as usize];
+    fs::File::open(path)
+        .and_then(|mut file| file.read_exact(&mut data))
+        .map_err(error::Error::IO)?;
+
+    // The dictionary size is the u32 right before the dictionary
+    let offset = log::DICTIONARY_OFFSET as usize;
+    let mut size = [0; 4];
+    size.copy_from_slice(
+        data.get(offset - 4..offset)
+            .ok_or(error::Error::LogHeaderCorrupt)?,
+    );
+    if offset + u32::from_le_bytes(size) as usize != data.len() {
+        return Err(error::Error::LogHeaderCorrupt);
+    }
+
+    Ok(Some(data.split_off(offset)))
+}
+
+fn file_size(path: &path::Path) -> error::Result<u64> {
+    Ok(fs::metadata(path).map_err(error::Error::IO)?.len())
+}
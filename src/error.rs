@@ -1,7 +1,12 @@
+#![allow(non_local_definitions)]
+
+use failure::Fail;
 use std::io;
 use std::path;
 use std::result;
 
+use crate::log;
+
 pub type Result<A> = result::Result<A, Error>;
 
 #[derive(Debug, failure::Fail)]
@@ -36,6 +41,8 @@ pub enum Error {
     LogTooSmall,
     #[fail(display = "log closed")]
     LogClosed,
+    #[fail(display = "log writer failed earlier and can't be written to")]
+    LogWriterPoisoned,
     #[fail(display = "log iterator inactive")]
     LogIteratorInactive,
     #[fail(display = "log iterator mismatch")]
@@ -48,6 +55,17 @@ pub enum Error {
     InvalidCompressionBlockSize,
     #[fail(display = "invalid compression type")]
     InvalidCompressionType,
+    #[fail(display = "corrupt compressed block at offset {}", offset)]
+    CorruptBlock { offset: u64 },
+    #[fail(display = "invalid log position: {:?}", position)]
+    InvalidLogPosition { position: log::LogPosition },
+    #[fail(display = "failed to train dictionary: {}", reason)]
+    DictionaryTrainingFailed { reason: String },
+    #[fail(
+        display = "dictionary of {} bytes is larger than the maximum of {}",
+        size, max
+    )]
+    DictionaryTooLarge { size: u64, max: u64 },
 
     #[fail(display = "wrong hash magic number")]
     WrongHashMagicNumber,
@@ -61,8 +79,38 @@ pub enum Error {
     HashClosed,
     #[fail(display = "file identifier mismatch")]
     FileIdentifierMismatch,
+    #[fail(display = "index missing: {:?}", path)]
+    IndexMissing { path: path::PathBuf },
+    #[fail(display = "index is {} bytes behind its log", unindexed_bytes)]
+    StaleIndex { unindexed_bytes: u64 },
+    #[fail(display = "invalid store generation: {:?}", name)]
+    InvalidGeneration { name: String },
+    #[fail(display = "store is not an unpublished generation")]
+    NotPublishable,
     #[fail(display = "hash header corrupt")]
     HashHeaderCorrupt,
     #[fail(display = "hash size invalid")]
     HashSizeInvalid,
+    #[fail(display = "invalid capacity factor: {}", factor)]
+    InvalidCapacityFactor { factor: f64 },
+    #[fail(
+        display = "memory budget of {} bytes is too small, {} bytes are needed",
+        budget, needed
+    )]
+    MemoryBudgetTooSmall { budget: u64, needed: u64 },
+
+    #[fail(display = "failed to encode: {}", reason)]
+    Encode { reason: String },
+    #[fail(display = "failed to decode: {}", reason)]
+    Decode { reason: String },
+}
+
+impl From<Error> for io::Error {
+    fn from(error: Error) -> Self {
+        match error {
+            Error::IO(e) => e,
+            Error::UnexpectedEof => io::Error::new(io::ErrorKind::UnexpectedEof, error.compat()),
+            e => io::Error::other(e.compat()),
+        }
+    }
 }
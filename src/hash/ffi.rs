@@ -0,0 +1,738 @@
+use std::borrow;
+use std::fmt;
+use std::mem;
+use std::os;
+use std::path;
+use std::ptr;
+use std::sync;
+
+use sparkey_sys::*;
+
+use crate::error;
+use crate::hash::{HashHeader, Type};
+use crate::log;
+use crate::util;
+
+// Iterators that a reader keeps around for lookups; more concurrent lookups than this allocate
+// iterators that are closed again afterwards
+const MAX_POOLED_ITERS: usize = 64;
+
+pub struct Writer;
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum UpdateKind {
+    // Only the entries appended since the last index was written were applied
+    Incremental,
+    // The index was missing, or didn't match the log, so it was built from scratch
+    FullRebuild,
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct IndexUpdateReport {
+    pub kind: UpdateKind,
+    // Puts and deletes that were applied to the index
+    pub puts: u64,
+    pub deletes: u64,
+    pub garbage_size: u64,
+    pub data_end: u64,
+}
+
+// Clones share the same underlying reader, which stays open until the last of them (and of the
+// iterators created from them) is dropped.
+pub struct Reader(
+    *mut hashreader,
+    log::Reader,
+    sync::Mutex<Vec<*mut logiter>>,
+    // The log path, for checking whether the log has been appended to since opening
+    path::PathBuf,
+);
+
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub struct ReaderOptions {
+    pub on_stale: OnStale,
+}
+
+// What to do when the index doesn't cover the whole log when opening a reader
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub enum OnStale {
+    // Open it anyway; entries past the end of the index aren't visible
+    #[default]
+    Serve,
+    // Fail with `StaleIndex`
+    Fail,
+    // Update the index first (see `Writer::update`), which also builds a missing index
+    Reindex,
+}
+
+// The order that batched lookups probe the keys in
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub enum ProbeOrder {
+    // The order of the keys
+    #[default]
+    None,
+    // The order of the slots that the keys want in the hash table, which keeps the accesses to
+    // the table close together
+    Slot,
+    // The order of the entries in the log, which keeps the accesses to the log close together.
+    // Keys that aren't in the index come first.
+    LogOffset,
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Freshness {
+    Fresh,
+    // The log has been appended to since the index was written
+    Stale {
+        unindexed_bytes: u64,
+        unindexed_puts: u64,
+    },
+}
+
+// Closes the reader when dropped, along with anything that has to stay around until then
+#[derive(Debug)]
+struct Handle(
+    *mut hashreader,
+    #[allow(dead_code)] Option<sync::Arc<dyn fmt::Debug + Send + Sync>>,
+);
+
+pub struct Lookup<'a>(*mut logiter, &'a Reader, Vec<u8>);
+
+// An iterator taken from the pool of a reader, which goes back to the pool when dropped, unless
+// it might have been left in a bad state
+struct PooledIter<'a>(*mut logiter, &'a Reader, bool);
+
+impl Type {
+    fn as_raw(&self) -> os::raw::c_int {
+        match *self {
+            Type::Murmur3_32 => 4,
+            Type::Murmur3_64 => 8,
+        }
+    }
+}
+
+impl HashHeader {
+    pub fn read<P>(path: P) -> error::Result<Self>
+    where
+        P: AsRef<path::Path>,
+    {
+        let mut raw = mem::MaybeUninit::zeroed();
+        let path = util::path_to_cstring(path)?;
+
+        util::handle(unsafe { load_hashheader(raw.as_mut_ptr(), path.as_ptr()) })?;
+
+        Ok(Self::from_raw(unsafe { &raw.assume_init() }))
+    }
+
+    pub fn from_raw(raw: &hashheader) -> Self {
+        HashHeader {
+            major_version: raw.major_version,
+            minor_version: raw.minor_version,
+            file_identifier: raw.file_identifier,
+            hash_seed: raw.hash_seed,
+            header_size: raw.header_size,
+            data_end: raw.data_end,
+            max_key_len: raw.max_key_len,
+            max_value_len: raw.max_value_len,
+            num_puts: raw.num_puts,
+            garbage_size: raw.garbage_size,
+            num_entries: raw.num_entries,
+            address_size: raw.address_size,
+            // The header loader rejects any other hash sizes
+            hash_type: if raw.hash_size == 4 {
+                Type::Murmur3_32
+            } else {
+                Type::Murmur3_64
+            },
+            hash_capacity: raw.hash_capacity,
+            max_displacement: raw.max_displacement,
+            total_displacement: raw.total_displacement,
+            num_collisions: raw.hash_collisions,
+            entry_block_bits: raw.entry_block_bits,
+        }
+    }
+}
+
+impl Writer {
+    // Hashes the log on the calling thread; see `write_with_options` for more threads
+    pub fn write<P1, P2>(hash_path: P1, log_path: P2, hash_type: Option<Type>) -> error::Result<()>
+    where
+        P1: AsRef<path::Path>,
+        P2: AsRef<path::Path>,
+    {
+        let hash_path = util::path_to_cstring(hash_path)?;
+        let log_path = util::path_to_cstring(log_path)?;
+
+        util::handle(unsafe {
+            hash_write(
+                hash_path.as_ptr(),
+                log_path.as_ptr(),
+                hash_type.map_or(0, |t| t.as_raw()),
+            )
+        })?;
+
+        Ok(())
+    }
+
+    // Builds the index with the native writer, which reads and hashes the log on
+    // `options.threads` threads.  The index is a standard one, and opens with `Reader` as usual.
+    #[cfg(feature = "native")]
+    pub fn write_with_options<P1, P2>(
+        hash_path: P1,
+        log_path: P2,
+        options: crate::native::hash::WriterOptions,
+    ) -> error::Result<HashHeader>
+    where
+        P1: AsRef<path::Path>,
+        P2: AsRef<path::Path>,
+    {
+        crate::native::hash::Writer::write(hash_path, log_path, options)
+    }
+
+    // Like `write_with_options`, but calls `progress` every so often while indexing, and once at
+    // the end
+    #[cfg(feature = "native")]
+    pub fn write_with_progress<P1, P2, F>(
+        hash_path: P1,
+        log_path: P2,
+        options: crate::native::hash::WriterOptions,
+        progress: F,
+    ) -> error::Result<HashHeader>
+    where
+        P1: AsRef<path::Path>,
+        P2: AsRef<path::Path>,
+        F: FnMut(crate::native::hash::Progress),
+    {
+        crate::native::hash::Writer::write_with_progress(hash_path, log_path, options, progress)
+    }
+
+    // Like `write`, but reports whether the existing index was updated incrementally, and what
+    // was applied to it
+    pub fn update<P1, P2>(hash_path: P1, log_path: P2) -> error::Result<IndexUpdateReport>
+    where
+        P1: AsRef<path::Path>,
+        P2: AsRef<path::Path>,
+    {
+        let hash_path = hash_path.as_ref();
+        let log_path = log_path.as_ref();
+        let old_header = HashHeader::read(hash_path).ok();
+
+        let mut incremental = 0;
+        let hash_path_c = util::path_to_cstring(hash_path)?;
+        let log_path_c = util::path_to_cstring(log_path)?;
+        util::handle(unsafe {
+            hash_write_report(
+                hash_path_c.as_ptr(),
+                log_path_c.as_ptr(),
+                0,
+                &mut incremental,
+            )
+        })?;
+        let header = HashHeader::read(hash_path)?;
+
+        let (kind, puts, deletes) = match old_header {
+            // Only the entries after the old index were added
+            Some(old_header) if incremental != 0 => {
+                let (puts, deletes) =
+                    count_entries(log_path, old_header.data_end, header.data_end)?;
+                (UpdateKind::Incremental, puts, deletes)
+            }
+            _ => {
+                let log_header = log::LogHeader::read(log_path)?;
+                let (puts, deletes) = if log_header.data_end == header.data_end {
+                    (log_header.num_puts, log_header.num_deletes)
+                } else {
+                    // The log was appended to after the index was written
+                    count_entries(log_path, u64::from(log_header.header_size), header.data_end)?
+                };
+                (UpdateKind::FullRebuild, puts, deletes)
+            }
+        };
+
+        Ok(IndexUpdateReport {
+            kind,
+            puts,
+            deletes,
+            garbage_size: header.garbage_size,
+            data_end: header.data_end,
+        })
+    }
+}
+
+impl Reader {
+    pub fn open<P1, P2>(hash_path: P1, log_path: P2) -> error::Result<Self>
+    where
+        P1: AsRef<path::Path>,
+        P2: AsRef<path::Path>,
+    {
+        Self::open_guarded(hash_path, log_path, None)
+    }
+
+    pub fn open_with_options<P1, P2>(
+        hash_path: P1,
+        log_path: P2,
+        options: ReaderOptions,
+    ) -> error::Result<Self>
+    where
+        P1: AsRef<path::Path>,
+        P2: AsRef<path::Path>,
+    {
+        let hash_path = hash_path.as_ref();
+        let log_path = log_path.as_ref();
+
+        if options.on_stale == OnStale::Reindex {
+            let log_header = log::LogHeader::read(log_path)?;
+            let fresh = match HashHeader::read(hash_path) {
+                Ok(ref hash_header) => {
+                    freshness(hash_header, &log_header).ok() == Some(Freshness::Fresh)
+                }
+                Err(_) => false,
+            };
+            if !fresh {
+                Writer::update(hash_path, log_path)?;
+            }
+        }
+
+        let reader = Self::open(hash_path, log_path)?;
+
+        if options.on_stale == OnStale::Fail {
+            if let Freshness::Stale {
+                unindexed_bytes, ..
+            } = reader.freshness()?
+            {
+                return Err(error::Error::StaleIndex { unindexed_bytes });
+            }
+        }
+
+        Ok(reader)
+    }
+
+    // Like `open`, but keeps `guard` alive until the reader and everything created from it is gone
+    pub(crate) fn open_guarded<P1, P2>(
+        hash_path: P1,
+        log_path: P2,
+        guard: Option<sync::Arc<dyn fmt::Debug + Send + Sync>>,
+    ) -> error::Result<Self>
+    where
+        P1: AsRef<path::Path>,
+        P2: AsRef<path::Path>,
+    {
+        let mut raw = ptr::null_mut();
+        let path = log_path.as_ref().to_path_buf();
+        let hash_path = util::path_to_cstring(hash_path)?;
+        let log_path = util::path_to_cstring(log_path)?;
+
+        util::handle(unsafe { hash_open(&mut raw, hash_path.as_ptr(), log_path.as_ptr()) })?;
+
+        // The log reader is part of the hash reader, so it keeps the hash reader open
+        let handle = sync::Arc::new(Handle(raw, guard));
+        let log_reader = unsafe { log::Reader::with_owner(hash_getreader(raw), handle) };
+
+        Ok(Self(raw, log_reader, sync::Mutex::new(Vec::new()), path))
+    }
+
+    pub fn as_raw(&self) -> *mut hashreader {
+        self.0
+    }
+
+    pub fn log_reader(&self) -> &log::Reader {
+        &self.1
+    }
+
+    pub fn get(&self, key: &[u8]) -> error::Result<Option<bytes::BytesMut>> {
+        let log_reader = self.log_reader().as_raw();
+
+        self.with_iter(|log_iter| {
+            util::handle(unsafe { hash_get(self.0, key.as_ptr(), key.len() as u64, log_iter) })?;
+
+            match unsafe { logiter_state(log_iter) } {
+                iter_state::ITER_ACTIVE => {
+                    let value = util::read_value(log_iter, log_reader)?;
+                    Ok(Some(value))
+                }
+                _ => Ok(None),
+            }
+        })
+    }
+
+    pub fn get_borrowed(&self, key: &[u8]) -> error::Result<Option<borrow::Cow<'_, [u8]>>> {
+        let log_reader = self.log_reader().as_raw();
+
+        self.with_iter(|log_iter| {
+            util::handle(unsafe { hash_get(self.0, key.as_ptr(), key.len() as u64, log_iter) })?;
+
+            match unsafe { logiter_state(log_iter) } {
+                iter_state::ITER_ACTIVE => {
+                    let value = unsafe { util::borrow_value(log_iter, log_reader)? };
+                    Ok(Some(value))
+                }
+                _ => Ok(None),
+            }
+        })
+    }
+
+    // Looks the keys up with one iterator, in the given order, and returns the values in the
+    // order of the keys
+    pub fn multi_get<K>(
+        &self,
+        keys: &[K],
+        order: ProbeOrder,
+    ) -> error::Result<Vec<Option<bytes::BytesMut>>>
+    where
+        K: AsRef<[u8]>,
+    {
+        let mut result = vec![None; keys.len()];
+
+        self.multi_get_with(keys, order, |i, value| {
+            result[i] = value.map(bytes::BytesMut::from);
+        })?;
+
+        Ok(result)
+    }
+
+    // Like `multi_get`, but passes the index of each key and its value to `f`, in probe order
+    pub fn multi_get_with<K, F>(&self, keys: &[K], order: ProbeOrder, mut f: F) -> error::Result<()>
+    where
+        K: AsRef<[u8]>,
+        F: FnMut(usize, Option<&[u8]>),
+    {
+        let mut lookup = self.lookup()?;
+
+        for (i, hash) in self.probe_order(keys, order) {
+            f(i, lookup.get_hashed(keys[i].as_ref(), hash)?);
+        }
+
+        Ok(())
+    }
+
+    pub fn lookup(&self) -> error::Result<Lookup<'_>> {
+        let mut raw = ptr::null_mut();
+
+        util::handle(unsafe { logiter_create(&mut raw, self.1.as_raw()) })?;
+
+        Ok(Lookup(raw, self, Vec::new()))
+    }
+
+    pub fn get_reader(&self, key: &[u8]) -> error::Result<Option<log::ValueReader<'_>>> {
+        let log_reader = self.log_reader().as_raw();
+        let mut log_iter = ptr::null_mut();
+
+        util::handle(unsafe { logiter_create(&mut log_iter, log_reader) })?;
+
+        if let Err(e) =
+            util::handle(unsafe { hash_get(self.0, key.as_ptr(), key.len() as u64, log_iter) })
+        {
+            unsafe { logiter_close(&mut log_iter) };
+            return Err(e);
+        }
+
+        match unsafe { logiter_state(log_iter) } {
+            iter_state::ITER_ACTIVE => Ok(Some(unsafe {
+                log::ValueReader::new(log_iter, &self.1, true)
+            })),
+            _ => {
+                unsafe { logiter_close(&mut log_iter) };
+                Ok(None)
+            }
+        }
+    }
+
+    pub fn entries(&self) -> error::Result<log::Entries<'_>> {
+        let mut raw = ptr::null_mut();
+
+        util::handle(unsafe { logiter_create(&mut raw, self.1.as_raw()) })?;
+
+        Ok(unsafe { log::Entries::from_raw(raw, &self.1, Some(self.0)) })
+    }
+
+    // Like `entries`, but the iterator keeps the reader open, so it can be returned from
+    // functions or moved to other threads
+    pub fn into_entries(self) -> error::Result<log::Entries<'static>> {
+        let raw = self.iter()?;
+
+        Ok(unsafe { log::Entries::from_raw_owned(raw, self.1.clone(), Some(self.0)) })
+    }
+
+    pub fn into_keys(self) -> error::Result<log::Keys<'static>> {
+        let raw = self.iter()?;
+
+        Ok(unsafe { log::Keys::from_raw_owned(raw, self.1.clone(), Some(self.0)) })
+    }
+
+    pub fn into_values(self) -> error::Result<log::Values<'static>> {
+        let raw = self.iter()?;
+
+        Ok(unsafe { log::Values::from_raw_owned(raw, self.1.clone(), Some(self.0)) })
+    }
+
+    fn iter(&self) -> error::Result<*mut logiter> {
+        let mut raw = ptr::null_mut();
+
+        util::handle(unsafe { logiter_create(&mut raw, self.1.as_raw()) })?;
+
+        Ok(raw)
+    }
+
+    pub fn keys(&self) -> error::Result<log::Keys<'_>> {
+        let mut raw = ptr::null_mut();
+
+        util::handle(unsafe { logiter_create(&mut raw, self.1.as_raw()) })?;
+
+        Ok(unsafe { log::Keys::from_raw(raw, &self.1, Some(self.0)) })
+    }
+
+    pub fn values(&self) -> error::Result<log::Values<'_>> {
+        let mut raw = ptr::null_mut();
+
+        util::handle(unsafe { logiter_create(&mut raw, self.1.as_raw()) })?;
+
+        Ok(unsafe { log::Values::from_raw(raw, &self.1, Some(self.0)) })
+    }
+
+    pub fn borrowed_entries(&self) -> error::Result<log::BorrowedEntries<'_>> {
+        let mut raw = ptr::null_mut();
+
+        util::handle(unsafe { logiter_create(&mut raw, self.1.as_raw()) })?;
+
+        Ok(unsafe { log::BorrowedEntries::from_raw(raw, &self.1, Some(self.0)) })
+    }
+
+    pub fn streaming_entries(&self) -> error::Result<log::StreamingEntries<'_>> {
+        let mut raw = ptr::null_mut();
+
+        util::handle(unsafe { logiter_create(&mut raw, self.1.as_raw()) })?;
+
+        Ok(unsafe { log::StreamingEntries::from_raw(raw, &self.1, Some(self.0)) })
+    }
+
+    pub fn header(&self) -> HashHeader {
+        HashHeader::from_raw(unsafe { &*hash_header(self.0) })
+    }
+
+    // Compares the index against the log as it is on disk now, which may have been appended to
+    // since the reader was opened.  Fails with `FileIdentifierMismatch` if the log was replaced.
+    pub fn freshness(&self) -> error::Result<Freshness> {
+        freshness(&self.header(), &log::LogHeader::read(&self.3)?)
+    }
+
+    pub fn num_entries(&self) -> u64 {
+        unsafe { hash_numentries(self.0) }
+    }
+
+    pub fn num_collisions(&self) -> u64 {
+        unsafe { hash_numcollisions(self.0) }
+    }
+
+    // Runs `f` with an iterator from the pool, so that lookups don't need to allocate one each
+    fn with_iter<F, A>(&self, f: F) -> error::Result<A>
+    where
+        F: FnOnce(*mut logiter) -> error::Result<A>,
+    {
+        let pooled = self.pool().pop();
+        let log_iter = match pooled {
+            Some(log_iter) => log_iter,
+            None => {
+                let mut raw = ptr::null_mut();
+                util::handle(unsafe { logiter_create(&mut raw, self.1.as_raw()) })?;
+                raw
+            }
+        };
+        // Closes the iterator if `f` panics
+        let mut guard = PooledIter(log_iter, self, false);
+
+        let result = f(log_iter);
+        guard.2 = result.is_ok();
+
+        result
+    }
+
+    // Indices of `keys` and their hashes, in the order to probe them in.  Each key is hashed once,
+    // and the hashes are passed on to `Lookup::get_hashed`.
+    pub(crate) fn probe_order<K>(&self, keys: &[K], order: ProbeOrder) -> Vec<(usize, u64)>
+    where
+        K: AsRef<[u8]>,
+    {
+        let mut probes = keys
+            .iter()
+            .enumerate()
+            .map(|(i, key)| {
+                let key = key.as_ref();
+                (i, unsafe {
+                    hash_key(self.0, key.as_ptr(), key.len() as u64)
+                })
+            })
+            .collect::<Vec<_>>();
+
+        match order {
+            ProbeOrder::None => {}
+            ProbeOrder::Slot => {
+                let capacity = self.header().hash_capacity.max(1);
+                probes.sort_by_key(|&(_, hash)| hash % capacity);
+            }
+            ProbeOrder::LogOffset => {
+                probes.sort_by_cached_key(|&(_, hash)| unsafe { hash_first_address(self.0, hash) });
+            }
+        }
+
+        probes
+    }
+
+    fn pool(&self) -> sync::MutexGuard<'_, Vec<*mut logiter>> {
+        match self.2.lock() {
+            Ok(pool) => pool,
+            Err(poisoned) => poisoned.into_inner(),
+        }
+    }
+}
+
+impl<'a> Drop for PooledIter<'a> {
+    fn drop(&mut self) {
+        if self.2 {
+            let mut pool = self.1.pool();
+            if pool.len() < MAX_POOLED_ITERS {
+                pool.push(self.0);
+                return;
+            }
+        }
+        unsafe { logiter_close(&mut self.0) }
+    }
+}
+
+impl Drop for Reader {
+    fn drop(&mut self) {
+        let pool = match self.2.get_mut() {
+            Ok(pool) => pool,
+            Err(poisoned) => poisoned.into_inner(),
+        };
+        for mut log_iter in pool.drain(..) {
+            unsafe { logiter_close(&mut log_iter) }
+        }
+    }
+}
+
+impl Clone for Reader {
+    fn clone(&self) -> Self {
+        Reader(
+            self.0,
+            self.1.clone(),
+            sync::Mutex::new(Vec::new()),
+            self.3.clone(),
+        )
+    }
+}
+
+impl Drop for Handle {
+    fn drop(&mut self) {
+        unsafe { hash_close(&mut self.0) }
+    }
+}
+
+unsafe impl Send for Reader {}
+
+unsafe impl Sync for Reader {}
+
+unsafe impl Send for Handle {}
+
+unsafe impl Sync for Handle {}
+
+impl<'a> Lookup<'a> {
+    pub fn as_raw(&self) -> *mut logiter {
+        self.0
+    }
+
+    pub fn get(&mut self, key: &[u8]) -> error::Result<Option<&[u8]>> {
+        if !self.find(key)? {
+            return Ok(None);
+        }
+
+        self.value()
+    }
+
+    // Like `get`, with the hash of the key from `Reader::probe_order`
+    pub(crate) fn get_hashed(&mut self, key: &[u8], hash: u64) -> error::Result<Option<&[u8]>> {
+        util::handle(unsafe {
+            hash_get_hashed((self.1).0, key.as_ptr(), key.len() as u64, hash, self.0)
+        })?;
+
+        match unsafe { logiter_state(self.0) } {
+            iter_state::ITER_ACTIVE => self.value(),
+            _ => Ok(None),
+        }
+    }
+
+    pub fn get_into(&mut self, key: &[u8], value: &mut Vec<u8>) -> error::Result<bool> {
+        if self.find(key)? {
+            util::read_value_into(self.0, (self.1).1.as_raw(), value)?;
+            Ok(true)
+        } else {
+            value.clear();
+            Ok(false)
+        }
+    }
+
+    fn find(&mut self, key: &[u8]) -> error::Result<bool> {
+        util::handle(unsafe { hash_get((self.1).0, key.as_ptr(), key.len() as u64, self.0) })?;
+
+        match unsafe { logiter_state(self.0) } {
+            iter_state::ITER_ACTIVE => Ok(true),
+            _ => Ok(false),
+        }
+    }
+
+    fn value(&mut self) -> error::Result<Option<&[u8]>> {
+        match unsafe { util::borrow_value_into(self.0, (self.1).1.as_raw(), &mut self.2)? } {
+            Some(value) => Ok(Some(value)),
+            None => Ok(Some(&self.2)),
+        }
+    }
+}
+
+impl<'a> Drop for Lookup<'a> {
+    fn drop(&mut self) {
+        unsafe { logiter_close(&mut self.0) }
+    }
+}
+
+unsafe impl<'a> Send for Lookup<'a> {}
+
+// Whether an index covers its log, for `Reader::freshness` and `store::Store::index_status`
+pub(crate) fn freshness(
+    hash_header: &HashHeader,
+    log_header: &log::LogHeader,
+) -> error::Result<Freshness> {
+    if hash_header.file_identifier != log_header.file_identifier {
+        return Err(error::Error::FileIdentifierMismatch);
+    }
+
+    if hash_header.data_end < log_header.data_end {
+        Ok(Freshness::Stale {
+            unindexed_bytes: log_header.data_end - hash_header.data_end,
+            unindexed_puts: log_header.num_puts.saturating_sub(hash_header.num_puts),
+        })
+    } else {
+        Ok(Freshness::Fresh)
+    }
+}
+
+// The puts and deletes in `start..end` of the log, where `start` is an entry boundary
+fn count_entries(log_path: &path::Path, start: u64, end: u64) -> error::Result<(u64, u64)> {
+    let mut puts = 0;
+    let mut deletes = 0;
+    if start >= end {
+        return Ok((puts, deletes));
+    }
+
+    let position = log::LogPosition {
+        offset: start,
+        entry_index: 0,
+    };
+    let mut entries = log::Reader::open(log_path)?.into_entries_from(position)?;
+    while entries.position()?.offset < end {
+        match entries.next().transpose()? {
+            Some(entry) if entry.entry_type == log::EntryType::Put => puts += 1,
+            Some(_) => deletes += 1,
+            None => break,
+        }
+    }
+
+    Ok((puts, deletes))
+}
@@ -0,0 +1,46 @@
+#[cfg(feature = "native")]
+use crate::murmur3;
+
+// The parts that are backed by the C library
+#[cfg(feature = "ffi")]
+mod ffi;
+
+#[cfg(feature = "ffi")]
+pub use self::ffi::*;
+
+#[allow(non_camel_case_types)]
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Type {
+    Murmur3_32,
+    Murmur3_64,
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct HashHeader {
+    pub major_version: u32,
+    pub minor_version: u32,
+    pub file_identifier: u32,
+    pub hash_seed: u32,
+    pub header_size: u32,
+    pub data_end: u64,
+    pub max_key_len: u64,
+    pub max_value_len: u64,
+    pub num_puts: u64,
+    pub garbage_size: u64,
+    pub num_entries: u64,
+    pub address_size: u32,
+    pub hash_type: Type,
+    pub hash_capacity: u64,
+    pub max_displacement: u64,
+    pub total_displacement: u64,
+    pub num_collisions: u64,
+    pub entry_block_bits: u32,
+}
+
+#[cfg(feature = "native")]
+pub(crate) fn hash_key(hash_type: Type, seed: u32, key: &[u8]) -> u64 {
+    match hash_type {
+        Type::Murmur3_32 => u64::from(murmur3::hash_32(key, seed)),
+        Type::Murmur3_64 => murmur3::hash_64(key, seed),
+    }
+}
@@ -1,9 +1,30 @@
+#[cfg(feature = "async")]
+pub mod async_reader;
+#[cfg(feature = "ffi")]
+mod compact;
 pub mod error;
 pub mod hash;
 pub mod log;
+#[cfg(feature = "native")]
+mod murmur3;
+#[cfg(feature = "native")]
+pub mod native;
+#[cfg(feature = "rayon")]
+mod par;
+#[cfg(feature = "ffi")]
+pub mod reload;
+#[cfg(feature = "ffi")]
+pub mod store;
+#[cfg(feature = "ffi")]
+pub mod typed;
+#[cfg(feature = "ffi")]
 mod util;
 
-#[cfg(test)]
+#[cfg(feature = "ffi")]
+pub use crate::compact::{compact, CompactOptions, CompactReport, GarbageRatioBase};
+
+// Most tests compare against the C library, so they need it
+#[cfg(all(test, feature = "ffi"))]
 mod test {
     use super::*;
 
@@ -64,6 +85,1232 @@ mod test {
         );
     }
 
+    #[test]
+    fn roundtrip_zstd() {
+        let dir = tempdir::TempDir::new("sparkey-rs").unwrap();
+        let log = dir.path().join("data.spl");
+        let hash = dir.path().join("data.spi");
+        let value = br#"{"name": "sparkey", "tags": ["a", "b", "c"]}"#.repeat(20);
+
+        {
+            let compression_type = log::CompressionType::Zstd { level: 19 };
+            let mut writer = log::Writer::create(&log, compression_type, 1024).unwrap();
+            for i in 0..100u32 {
+                writer.put(&i.to_be_bytes(), &value).unwrap();
+            }
+            writer.close().unwrap();
+        }
+        hash::Writer::write(&hash, &log, None).unwrap();
+
+        let header = log::LogHeader::read(&log).unwrap();
+        assert_eq!(1, header.minor_version);
+        // The level isn't stored, so only the algorithm is the same
+        assert!(log::CompressionType::Zstd { level: 19 }.same_algorithm(&header.compression_type));
+        assert!(!log::CompressionType::Snappy.same_algorithm(&header.compression_type));
+        assert_ne!(
+            log::CompressionType::Zstd { level: 19 },
+            header.compression_type
+        );
+        assert_eq!(Some(0), header.compression_type.level());
+        assert!(header.data_end < 100 * value.len() as u64 / 10);
+
+        let reader = hash::Reader::open(&hash, &log).unwrap();
+        for i in 0..100u32 {
+            assert_eq!(
+                &value[..],
+                &reader.get(&i.to_be_bytes()).unwrap().unwrap()[..]
+            );
+        }
+        assert_eq!(100, reader.entries().unwrap().count());
+
+        let mut writer = log::Writer::append(&log).unwrap();
+        writer.put(b"appended", b"value").unwrap();
+        writer.close().unwrap();
+        assert_eq!(101, log::LogHeader::read(&log).unwrap().num_puts);
+
+        let log = dir.path().join("snappy.spl");
+        log::Writer::create(&log, log::CompressionType::Snappy, 1024)
+            .unwrap()
+            .close()
+            .unwrap();
+        assert_eq!(0, log::LogHeader::read(&log).unwrap().minor_version);
+    }
+
+    pub(super) fn dictionary_samples() -> Vec<Vec<u8>> {
+        (0..1000u32)
+            .map(|i| {
+                format!(
+                    r#"{{"id": {}, "name": "user-{}", "country": "{}", "active": {}}}"#,
+                    i,
+                    i * 7919 % 1000,
+                    ["se", "us", "de", "fr"][i as usize % 4],
+                    i % 3 == 0
+                )
+                .into_bytes()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn roundtrip_dictionary() {
+        let dir = tempdir::TempDir::new("sparkey-rs").unwrap();
+        let log = dir.path().join("data.spl");
+        let hash = dir.path().join("data.spi");
+        let samples = dictionary_samples();
+
+        let dictionary = log::train_dictionary(&samples, 4096).unwrap();
+        assert!(!dictionary.is_empty() && dictionary.len() <= 4096);
+
+        {
+            let compression_type = log::CompressionType::Zstd { level: 3 };
+            let mut writer =
+                log::Writer::create_with_dictionary(&log, compression_type, 256, &dictionary)
+                    .unwrap();
+            for (i, sample) in samples.iter().enumerate() {
+                writer.put(&(i as u32).to_be_bytes(), sample).unwrap();
+            }
+            writer.close().unwrap();
+        }
+        hash::Writer::write(&hash, &log, None).unwrap();
+
+        let header = log::LogHeader::read(&log).unwrap();
+        assert_eq!(2, header.minor_version);
+        assert_eq!(88 + dictionary.len() as u32, header.header_size);
+
+        let reader = hash::Reader::open(&hash, &log).unwrap();
+        for (i, sample) in samples.iter().enumerate() {
+            assert_eq!(
+                &sample[..],
+                &reader.get(&(i as u32).to_be_bytes()).unwrap().unwrap()[..]
+            );
+        }
+        let log_reader = log::Reader::open(&log).unwrap();
+        let values = log_reader
+            .values()
+            .unwrap()
+            .map(|v| v.unwrap().to_vec())
+            .collect::<Vec<_>>();
+        assert_eq!(samples, values);
+
+        let mut writer = log::Writer::append(&log).unwrap();
+        writer.put(b"appended", &samples[0]).unwrap();
+        writer.close().unwrap();
+        hash::Writer::write(&hash, &log, None).unwrap();
+        let reader = hash::Reader::open(&hash, &log).unwrap();
+        assert_eq!(
+            &samples[0][..],
+            &reader.get(b"appended").unwrap().unwrap()[..]
+        );
+
+        // Compaction keeps the dictionary
+        let root = dir.path().join("compacted");
+        super::compact(dir.path().join("data"), &root, Default::default()).unwrap();
+        let compacted_log = store::Store::open_current(&root)
+            .unwrap()
+            .log_path()
+            .to_path_buf();
+        assert_eq!(
+            header.header_size,
+            log::LogHeader::read(&compacted_log).unwrap().header_size
+        );
+        assert_eq!(
+            fs::read(&log).unwrap()[88..header.header_size as usize],
+            fs::read(&compacted_log).unwrap()[88..header.header_size as usize]
+        );
+
+        match log::Writer::create_with_dictionary(
+            &log,
+            log::CompressionType::Snappy,
+            256,
+            &dictionary,
+        ) {
+            Err(error::Error::InvalidCompressionType) => {}
+            r => panic!("unexpected result: {:?}", r),
+        }
+        match log::train_dictionary(&[b"too small"], 4096) {
+            Err(error::Error::DictionaryTrainingFailed { .. }) => {}
+            r => panic!("unexpected result: {:?}", r),
+        }
+    }
+
+    #[test]
+    fn update_report() {
+        let dir = tempdir::TempDir::new("sparkey-rs").unwrap();
+        let log = dir.path().join("data.spl");
+        let hash = dir.path().join("data.spi");
+
+        {
+            let mut writer = log::Writer::create(&log, log::CompressionType::Snappy, 64).unwrap();
+            for i in 0..100u32 {
+                writer.put(&i.to_be_bytes(), &[0; 10]).unwrap();
+            }
+            writer.delete(&7u32.to_be_bytes()).unwrap();
+            writer.close().unwrap();
+        }
+        let report = hash::Writer::update(&hash, &log).unwrap();
+        assert_eq!(hash::UpdateKind::FullRebuild, report.kind);
+        assert_eq!((100, 1), (report.puts, report.deletes));
+        let hash_seed = hash::HashHeader::read(&hash).unwrap().hash_seed;
+
+        {
+            let mut writer = log::Writer::append(&log).unwrap();
+            for i in 0..10u32 {
+                writer.put(&i.to_be_bytes(), &[1; 10]).unwrap();
+            }
+            writer.delete(&50u32.to_be_bytes()).unwrap();
+            writer.delete(&51u32.to_be_bytes()).unwrap();
+            writer.close().unwrap();
+        }
+        let report = hash::Writer::update(&hash, &log).unwrap();
+        let header = hash::HashHeader::read(&hash).unwrap();
+        assert_eq!(hash::UpdateKind::Incremental, report.kind);
+        // Rebuilds pick a new seed
+        assert_eq!(hash_seed, header.hash_seed);
+        assert_eq!((10, 2), (report.puts, report.deletes));
+        assert_eq!(header.garbage_size, report.garbage_size);
+        assert_eq!(header.data_end, report.data_end);
+        assert!(report.garbage_size > 0);
+
+        let report = hash::Writer::update(&hash, &log).unwrap();
+        assert_eq!(hash::UpdateKind::Incremental, report.kind);
+        assert_eq!((0, 0), (report.puts, report.deletes));
+
+        log::Writer::create(&log, log::CompressionType::None, 0)
+            .unwrap()
+            .close()
+            .unwrap();
+        let report = hash::Writer::update(&hash, &log).unwrap();
+        assert_eq!(hash::UpdateKind::FullRebuild, report.kind);
+        assert_eq!(
+            (0, 0, 0),
+            (report.puts, report.deletes, report.garbage_size)
+        );
+    }
+
+    #[test]
+    fn freshness() {
+        let dir = tempdir::TempDir::new("sparkey-rs").unwrap();
+        let log = dir.path().join("data.spl");
+        let hash = dir.path().join("data.spi");
+
+        {
+            let mut writer = log::Writer::create(&log, log::CompressionType::Snappy, 64).unwrap();
+            writer.put(b"first", b"value").unwrap();
+            writer.close().unwrap();
+        }
+        hash::Writer::write(&hash, &log, None).unwrap();
+
+        let reader = hash::Reader::open(&hash, &log).unwrap();
+        assert_eq!(hash::Freshness::Fresh, reader.freshness().unwrap());
+
+        {
+            let mut writer = log::Writer::append(&log).unwrap();
+            writer.put(b"second", b"value").unwrap();
+            writer.delete(b"first").unwrap();
+            writer.close().unwrap();
+        }
+        match reader.freshness().unwrap() {
+            hash::Freshness::Stale {
+                unindexed_bytes,
+                unindexed_puts,
+            } => {
+                assert!(unindexed_bytes > 0);
+                assert_eq!(1, unindexed_puts);
+            }
+            f => panic!("unexpected freshness: {:?}", f),
+        }
+
+        let open = |on_stale| {
+            hash::Reader::open_with_options(&hash, &log, hash::ReaderOptions { on_stale })
+        };
+        let reader = open(hash::OnStale::Serve).unwrap();
+        assert_eq!(None, reader.get(b"second").unwrap());
+        match open(hash::OnStale::Fail) {
+            Err(error::Error::StaleIndex { unindexed_bytes }) => assert!(unindexed_bytes > 0),
+            r => panic!("unexpected result: {:?}", r.map(|_| ())),
+        }
+
+        let reader = open(hash::OnStale::Reindex).unwrap();
+        assert_eq!(hash::Freshness::Fresh, reader.freshness().unwrap());
+        assert!(reader.get(b"second").unwrap().is_some());
+        assert_eq!(None, reader.get(b"first").unwrap());
+        open(hash::OnStale::Fail).unwrap();
+
+        // A missing index is built too
+        fs::remove_file(&hash).unwrap();
+        assert!(open(hash::OnStale::Reindex).is_ok());
+
+        log::Writer::create(&log, log::CompressionType::None, 0)
+            .unwrap()
+            .close()
+            .unwrap();
+        match reader.freshness() {
+            Err(error::Error::FileIdentifierMismatch) => {}
+            r => panic!("unexpected result: {:?}", r),
+        }
+    }
+
+    #[test]
+    fn compact() {
+        let dir = tempdir::TempDir::new("sparkey-rs").unwrap();
+        let root = dir.path().join("store");
+        let write = |name: &str, compression_type| {
+            let base = dir.path().join(name);
+            let log = base.with_extension("spl");
+            let mut writer = log::Writer::create(&log, compression_type, 1024).unwrap();
+            for i in 0..1000u32 {
+                writer
+                    .put(&(i % 100).to_be_bytes(), &[i as u8; 32])
+                    .unwrap();
+            }
+            writer.delete(&7u32.to_be_bytes()).unwrap();
+            writer.close().unwrap();
+            hash::Writer::write(base.with_extension("spi"), &log, None).unwrap();
+            base
+        };
+
+        let base = write("data", log::CompressionType::None);
+        let log_header = log::LogHeader::read(base.with_extension("spl")).unwrap();
+        let expected = {
+            let reader =
+                hash::Reader::open(base.with_extension("spi"), base.with_extension("spl")).unwrap();
+            (0..100u32)
+                .map(|i| reader.get(&i.to_be_bytes()).unwrap())
+                .collect::<Vec<_>>()
+        };
+
+        let options = CompactOptions {
+            min_garbage_ratio: 0.95,
+            ..Default::default()
+        };
+        let report = super::compact(&base, &root, options).unwrap();
+        assert!(!report.compacted);
+        assert!(report.garbage_ratio > 0.85 && report.garbage_ratio < 0.95);
+        assert_eq!((None, 0), (report.generation, report.reclaimed_bytes()));
+        assert!(!root.exists());
+
+        let options = CompactOptions {
+            compression_type: Some(log::CompressionType::Snappy),
+            compression_block_size: Some(1024),
+            min_garbage_ratio: 0.5,
+            ..Default::default()
+        };
+        let report = super::compact(&base, &root, options).unwrap();
+        assert!(report.compacted);
+        assert_eq!(Some(1), report.generation);
+        assert!(report.reclaimed_bytes() > report.new_size);
+        // The source is left alone, and the build was published
+        assert_eq!(
+            log_header,
+            log::LogHeader::read(base.with_extension("spl")).unwrap()
+        );
+        assert_eq!(3, fs::read_dir(&root).unwrap().count());
+
+        let current = store::Store::open_current(&root).unwrap();
+        assert_eq!(Some(1), current.generation());
+        let header = log::LogHeader::read(current.log_path()).unwrap();
+        assert_eq!(log::CompressionType::Snappy, header.compression_type);
+        assert_eq!((99, 0), (header.num_puts, header.num_deletes));
+        assert_eq!(
+            0,
+            hash::HashHeader::read(current.index_path())
+                .unwrap()
+                .garbage_size
+        );
+
+        let reader = current.reader().unwrap();
+        for (i, expected) in expected.iter().enumerate() {
+            assert_eq!(expected, &reader.get(&(i as u32).to_be_bytes()).unwrap());
+        }
+
+        // Compressed logs take up less than their entries, so the ratio against `data_end` is
+        // higher than the one against the entry size
+        let base = write("compressed", log::CompressionType::Zstd { level: 3 });
+        let log_header = log::LogHeader::read(base.with_extension("spl")).unwrap();
+        let hash_header = hash::HashHeader::read(base.with_extension("spi")).unwrap();
+        let options = CompactOptions {
+            min_garbage_ratio: f64::INFINITY,
+            ..Default::default()
+        };
+        let by_data_end = super::compact(&base, &root, options).unwrap();
+        assert_eq!(
+            hash_header.garbage_size as f64 / hash_header.data_end as f64,
+            by_data_end.garbage_ratio
+        );
+        let options = CompactOptions {
+            garbage_ratio_base: GarbageRatioBase::EntrySize,
+            ..options
+        };
+        let by_entry_size = super::compact(&base, &root, options).unwrap();
+        assert_eq!(
+            hash_header.garbage_size as f64 / (log_header.put_size + log_header.delete_size) as f64,
+            by_entry_size.garbage_ratio
+        );
+        assert!(by_data_end.garbage_ratio > 1.0);
+        assert!(by_entry_size.garbage_ratio < 1.0);
+
+        let options = CompactOptions {
+            min_garbage_ratio: 1.0,
+            ..options
+        };
+        assert!(!super::compact(&base, &root, options).unwrap().compacted);
+        let options = CompactOptions {
+            garbage_ratio_base: GarbageRatioBase::DataEnd,
+            ..options
+        };
+        let report = super::compact(&base, &root, options).unwrap();
+        assert_eq!(Some(2), report.generation);
+        drop(current);
+        drop(reader);
+        assert_eq!(1, store::Store::collect_garbage(&root).unwrap());
+    }
+
+    #[test]
+    fn store() {
+        let dir = tempdir::TempDir::new("sparkey-rs").unwrap();
+        let base = dir.path().join("data");
+
+        match store::Store::open(&base) {
+            Err(error::Error::IO(_)) => {}
+            r => panic!("unexpected result: {:?}", r),
+        }
+
+        let store = store::Store::create(&base, Default::default()).unwrap();
+        assert_eq!(base.with_extension("spl"), store.log_path());
+        assert_eq!(store::IndexStatus::Fresh, store.index_status().unwrap());
+
+        {
+            let mut writer = store.writer().unwrap();
+            writer.put(b"key", b"value").unwrap();
+            writer.close().unwrap();
+        }
+        let store = store::Store::open(base.with_extension("spi")).unwrap();
+        match store.index_status().unwrap() {
+            store::IndexStatus::Stale { unindexed_bytes } => assert!(unindexed_bytes > 0),
+            status => panic!("unexpected status: {:?}", status),
+        }
+        assert_eq!(None, store.reader().unwrap().get(b"key").unwrap());
+
+        assert_eq!(1, store.reindex().unwrap().puts);
+        assert_eq!(store::IndexStatus::Fresh, store.index_status().unwrap());
+        assert_eq!(
+            &b"value"[..],
+            &store.reader().unwrap().get(b"key").unwrap().unwrap()[..]
+        );
+
+        fs::remove_file(store.index_path()).unwrap();
+        assert_eq!(store::IndexStatus::Missing, store.index_status().unwrap());
+        match store.reader() {
+            Err(error::Error::IndexMissing { .. }) => {}
+            r => panic!("unexpected result: {:?}", r.map(|_| ())),
+        }
+
+        let other = dir.path().join("other");
+        store::Store::create(&other, Default::default()).unwrap();
+        fs::rename(other.with_extension("spi"), store.index_path()).unwrap();
+        assert_eq!(
+            store::IndexStatus::Mismatched,
+            store.index_status().unwrap()
+        );
+
+        store.delete().unwrap();
+        assert_eq!(1, fs::read_dir(dir.path()).unwrap().count());
+
+        // Dots in the base name are kept
+        let store = store::Store::create(dir.path().join("data.v1"), Default::default()).unwrap();
+        assert_eq!(dir.path().join("data.v1.spl"), store.log_path());
+        assert_eq!(dir.path().join("data.v1.spi"), store.index_path());
+        let store = store::Store::open(dir.path().join("data.v1.spi")).unwrap();
+        assert_eq!(dir.path().join("data.v1.spl"), store.log_path());
+    }
+
+    #[test]
+    fn store_generations() {
+        let dir = tempdir::TempDir::new("sparkey-rs").unwrap();
+        let root = dir.path().join("store");
+
+        let publish = |value: &[u8]| {
+            let store = store::Store::create_generation(&root, Default::default()).unwrap();
+            assert_eq!(None, store.generation());
+            // Not visible before it's published
+            assert!(store::Store::open_current(&root)
+                .map_or(true, |current| current.log_path() != store.log_path()));
+
+            let mut writer = store.writer().unwrap();
+            writer.put(b"key", value).unwrap();
+            writer.close().unwrap();
+            store.reindex().unwrap();
+            store.publish().unwrap()
+        };
+
+        let first = publish(b"first");
+        assert_eq!(Some(1), first.generation());
+        match first.clone().publish() {
+            Err(error::Error::NotPublishable) => {}
+            r => panic!("unexpected result: {:?}", r),
+        }
+
+        let reader = store::Store::open_current(&root).unwrap().reader().unwrap();
+        drop(first);
+
+        // The first generation is kept around for the reader
+        let second = publish(b"second");
+        assert_eq!(Some(2), second.generation());
+        drop(second);
+        assert!(root.join("gen-1").exists());
+        assert_eq!(&b"first"[..], &reader.get(b"key").unwrap().unwrap()[..]);
+
+        let current = store::Store::open_current(&root).unwrap();
+        assert_eq!(Some(2), current.generation());
+        assert_eq!(
+            &b"second"[..],
+            &current.reader().unwrap().get(b"key").unwrap().unwrap()[..]
+        );
+
+        drop(reader);
+        assert_eq!(1, store::Store::collect_garbage(&root).unwrap());
+        assert!(!root.join("gen-1").exists());
+        assert_eq!(0, store::Store::collect_garbage(&root).unwrap());
+        assert!(root.join("gen-2").exists());
+
+        // An index that doesn't cover the log isn't published, and the build is removed
+        let stale = store::Store::create_generation(&root, Default::default()).unwrap();
+        let mut writer = stale.writer().unwrap();
+        writer.put(b"key", b"stale").unwrap();
+        writer.close().unwrap();
+        let build_dir = stale.log_path().parent().unwrap().to_path_buf();
+        match stale.publish() {
+            Err(error::Error::StaleIndex { unindexed_bytes }) => assert!(unindexed_bytes > 0),
+            r => panic!("unexpected result: {:?}", r),
+        }
+        assert!(!build_dir.exists());
+
+        // Builds are only collected once nothing holds them
+        let abandoned = store::Store::create_generation(&root, Default::default()).unwrap();
+        let build_dir = abandoned.log_path().parent().unwrap().to_path_buf();
+        assert_eq!(0, store::Store::collect_garbage(&root).unwrap());
+        drop(abandoned);
+        assert_eq!(1, store::Store::collect_garbage(&root).unwrap());
+        assert!(!build_dir.exists());
+
+        // `CURRENT` never moves backwards, even past a generation that is gone
+        drop(current);
+        fs::write(root.join("CURRENT"), "gen-5").unwrap();
+        assert_eq!(Some(6), publish(b"sixth").generation());
+        assert!(!root.join("gen-2").exists());
+
+        // A generation that a publish renamed into place without moving `CURRENT` is collected
+        let orphaned = store::Store::create_generation(&root, Default::default()).unwrap();
+        let build_dir = orphaned.log_path().parent().unwrap().to_path_buf();
+        drop(orphaned);
+        fs::rename(&build_dir, root.join("gen-7")).unwrap();
+        assert_eq!(1, store::Store::collect_garbage(&root).unwrap());
+        assert!(!root.join("gen-7").exists());
+        assert!(root.join("gen-6").exists());
+
+        fs::write(root.join("CURRENT"), "bogus").unwrap();
+        match store::Store::open_current(&root) {
+            Err(error::Error::InvalidGeneration { name }) => assert_eq!("bogus", name),
+            r => panic!("unexpected result: {:?}", r),
+        }
+    }
+
+    #[test]
+    fn reloading_reader() {
+        use std::sync;
+        use std::thread;
+        use std::time;
+
+        let dir = tempdir::TempDir::new("sparkey-rs").unwrap();
+        let root = dir.path().join("store");
+
+        let build = |value: &[u8], reindex: bool| {
+            let store = store::Store::create_generation(&root, Default::default()).unwrap();
+            let mut writer = store.writer().unwrap();
+            writer.put(b"key", value).unwrap();
+            writer.close().unwrap();
+            if reindex {
+                store.reindex().unwrap();
+            }
+            store
+        };
+        let publish = |value: &[u8]| {
+            build(value, true).publish().unwrap();
+        };
+        let wait_for = |f: &dyn Fn() -> bool| {
+            let deadline = time::Instant::now() + time::Duration::from_secs(10);
+            while !f() {
+                assert!(time::Instant::now() < deadline, "timed out");
+                thread::sleep(time::Duration::from_millis(5));
+            }
+        };
+
+        publish(b"first");
+
+        for &force_polling in &[false, true] {
+            let events = sync::Arc::new(sync::Mutex::new(Vec::new()));
+            let options = reload::ReloadOptions {
+                poll_interval: time::Duration::from_millis(20),
+                force_polling,
+            };
+            let reader = {
+                let events = events.clone();
+                reload::ReloadingReader::open_with_callback(&root, options, move |event| {
+                    events.lock().unwrap().push(format!("{:?}", event));
+                })
+                .unwrap()
+            };
+            let first = reader.generation();
+            let borrowed = reader.reader();
+
+            publish(b"second");
+            wait_for(&|| reader.generation() == first + 1);
+            assert_eq!(&b"second"[..], &reader.get(b"key").unwrap().unwrap()[..]);
+            // The old reader stays usable, and its generation stays around until it's dropped
+            assert!(&borrowed.get(b"key").unwrap().unwrap()[..] != b"second");
+            assert!(root.join(format!("gen-{}", first)).exists());
+            drop(borrowed);
+            store::Store::collect_garbage(&root).unwrap();
+            assert!(!root.join(format!("gen-{}", first)).exists());
+
+            // An index that doesn't cover its log is not swapped in, even if the generation was
+            // put in place without `publish`
+            let store = build(b"unindexed", false);
+            let next = format!("gen-{}", first + 2);
+            fs::rename(store.log_path().parent().unwrap(), root.join(&next)).unwrap();
+            drop(store);
+            fs::write(root.join("CURRENT.tmp"), &next).unwrap();
+            fs::rename(root.join("CURRENT.tmp"), root.join("CURRENT")).unwrap();
+            wait_for(&|| events.lock().unwrap().len() == 2);
+            assert_eq!(first + 1, reader.generation());
+            match reader.reload() {
+                Err(error::Error::StaleIndex { unindexed_bytes }) => assert!(unindexed_bytes > 0),
+                r => panic!("unexpected result: {:?}", r),
+            }
+
+            let events = events.lock().unwrap();
+            assert_eq!(
+                format!(
+                    "Reloaded {{ generation: {}, previous: {} }}",
+                    first + 1,
+                    first
+                ),
+                events[0]
+            );
+            assert!(events[1].starts_with("Failed"));
+
+            publish(b"third");
+        }
+    }
+
+    #[test]
+    fn roundtrip_typed() {
+        let dir = tempdir::TempDir::new("sparkey-rs").unwrap();
+        let log = dir.path().join("data.spl");
+        let hash = dir.path().join("data.spi");
+
+        {
+            let mut writer = typed::TypedWriter::<u64, u64, typed::BigEndian>::create(
+                &log,
+                log::CompressionType::Snappy,
+                64,
+            )
+            .unwrap();
+            for i in 0..100u64 {
+                writer.put(&i, &(i * i)).unwrap();
+            }
+            writer.delete(&7).unwrap();
+            writer.close().unwrap();
+        }
+        hash::Writer::write(&hash, &log, None).unwrap();
+
+        let reader = typed::TypedReader::<u64, u64, typed::BigEndian>::open(&hash, &log).unwrap();
+        assert_eq!(Some(81), reader.get(&9).unwrap());
+        assert_eq!(None, reader.get(&7).unwrap());
+        assert_eq!(None, reader.get(&100).unwrap());
+        assert_eq!(99, reader.entries().unwrap().count());
+
+        let log_reader = log::Reader::open(&log).unwrap();
+        let entries =
+            typed::TypedEntries::<u64, u64, typed::BigEndian>::new(log_reader.entries().unwrap())
+                .collect::<error::Result<Vec<_>>>()
+                .unwrap();
+        assert_eq!(101, entries.len());
+        assert_eq!(
+            typed::TypedEntry {
+                entry_type: log::EntryType::Delete,
+                key: 7,
+                value: None,
+            },
+            entries[100]
+        );
+
+        // Reading the same data with the wrong types fails instead of panicking
+        let reader = typed::TypedReader::<u64, u32, typed::BigEndian>::new(reader.into_inner());
+        match reader.get(&9) {
+            Err(error::Error::Decode { .. }) => {}
+            r => panic!("unexpected result: {:?}", r),
+        }
+        let reader = typed::TypedReader::<String, String, typed::Utf8>::new(
+            hash::Reader::open(&hash, &log).unwrap(),
+        );
+        assert!(reader
+            .entries()
+            .unwrap()
+            .any(|e| matches!(e, Err(error::Error::Decode { .. }))));
+    }
+
+    #[cfg(all(feature = "serde-bincode", feature = "serde-json"))]
+    #[test]
+    fn roundtrip_typed_serde() {
+        #[derive(Debug, PartialEq, serde::Serialize, serde::Deserialize)]
+        struct User {
+            name: String,
+            tags: Vec<String>,
+        }
+
+        fn check<C>(dir: &path::Path)
+        where
+            C: typed::Codec<u32> + typed::Codec<User>,
+        {
+            let log = dir.join("data.spl");
+            let hash = dir.join("data.spi");
+            let user = User {
+                name: "sparkey".to_owned(),
+                tags: vec!["a".to_owned(), "b".to_owned()],
+            };
+
+            let mut writer =
+                typed::TypedWriter::<u32, User, C>::create(&log, log::CompressionType::None, 0)
+                    .unwrap();
+            writer.put(&1, &user).unwrap();
+            writer.close().unwrap();
+            hash::Writer::write(&hash, &log, None).unwrap();
+
+            let reader = typed::TypedReader::<u32, User, C>::open(&hash, &log).unwrap();
+            assert_eq!(Some(user), reader.get(&1).unwrap());
+        }
+
+        let dir = tempdir::TempDir::new("sparkey-rs").unwrap();
+        check::<typed::Bincode>(dir.path());
+        check::<typed::Json>(dir.path());
+
+        let reader = typed::TypedReader::<u32, u32, typed::Json>::open(
+            dir.path().join("data.spi"),
+            dir.path().join("data.spl"),
+        )
+        .unwrap();
+        match reader.get(&1) {
+            Err(error::Error::Decode { .. }) => {}
+            r => panic!("unexpected result: {:?}", r),
+        }
+    }
+
+    #[test]
+    fn owned_iterators() {
+        use std::sync;
+        use std::thread;
+
+        let dir = tempdir::TempDir::new("sparkey-rs").unwrap();
+        let log = dir.path().join("data.spl");
+        let hash = dir.path().join("data.spi");
+
+        {
+            let mut writer = log::Writer::create(&log, log::CompressionType::Snappy, 64).unwrap();
+            for i in 0..100u32 {
+                writer.put(&i.to_be_bytes(), &[i as u8; 10]).unwrap();
+            }
+            writer.delete(&0u32.to_be_bytes()).unwrap();
+        }
+        hash::Writer::write(&hash, &log, None).unwrap();
+
+        fn keys(reader: &sync::Arc<hash::Reader>) -> log::Keys<'static> {
+            hash::Reader::clone(reader).into_keys().unwrap()
+        }
+
+        let reader = sync::Arc::new(hash::Reader::open(&hash, &log).unwrap());
+        let entries = reader.as_ref().clone().into_entries().unwrap();
+        let keys = keys(&reader);
+        let clone = reader.as_ref().clone();
+        drop(reader);
+
+        let count = thread::spawn(move || entries.count()).join().unwrap();
+        assert_eq!(99, count);
+        assert_eq!(99, keys.count());
+        assert_eq!(
+            &[5; 10],
+            &clone.get(&5u32.to_be_bytes()).unwrap().unwrap()[..]
+        );
+        let log_reader = clone.log_reader().clone();
+        let values = clone.into_values().unwrap();
+        assert_eq!(99, values.count());
+
+        // The log reader of a hash reader keeps the hash reader open
+        assert_eq!(101, log_reader.into_entries().unwrap().count());
+
+        let log_reader = log::Reader::open(&log).unwrap();
+        let values = log_reader.clone().into_values().unwrap();
+        drop(log_reader);
+        assert_eq!(101, values.count());
+    }
+
+    #[cfg(feature = "async")]
+    #[test]
+    fn async_reader() {
+        use futures_core::Stream;
+        use std::future;
+        use std::pin;
+
+        let dir = tempdir::TempDir::new("sparkey-rs").unwrap();
+        let log = dir.path().join("data.spl");
+        let hash = dir.path().join("data.spi");
+
+        {
+            let mut writer = log::Writer::create(&log, log::CompressionType::Snappy, 64).unwrap();
+            for i in 0..3000u32 {
+                writer.put(&i.to_be_bytes(), &[i as u8; 10]).unwrap();
+            }
+        }
+        hash::Writer::write(&hash, &log, None).unwrap();
+
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .build()
+            .unwrap();
+        runtime.block_on(async {
+            let reader = async_reader::AsyncReader::open(&hash, &log, 2)
+                .await
+                .unwrap();
+
+            assert_eq!(
+                &[7; 10],
+                &reader.get(&7u32.to_be_bytes()).await.unwrap().unwrap()[..]
+            );
+            assert_eq!(None, reader.get(b"missing").await.unwrap());
+
+            let values = reader
+                .multi_get(
+                    vec![1u32.to_be_bytes(), 5000u32.to_be_bytes()],
+                    Default::default(),
+                )
+                .await
+                .unwrap();
+            assert_eq!(Some(&[1; 10][..]), values[0].as_deref());
+            assert_eq!(None, values[1]);
+
+            let mut entries = reader.entries();
+            let mut count = 0;
+            while let Some(entry) =
+                future::poll_fn(|cx| pin::Pin::new(&mut entries).poll_next(cx)).await
+            {
+                let entry = entry.unwrap();
+                assert_eq!(&entry.key[3..], &entry.value[..1]);
+                count += 1;
+            }
+            assert_eq!(3000, count);
+        });
+
+        // Cut a compressed block short halfway through the second batch of 1024 entries
+        let log = dir.path().join("truncated.spl");
+        let hash = dir.path().join("truncated.spi");
+        {
+            let mut writer =
+                log::Writer::create(&log, log::CompressionType::Zstd { level: 3 }, 64).unwrap();
+            for i in 0..3000u32 {
+                writer.put(&i.to_be_bytes(), &[i as u8; 10]).unwrap();
+            }
+        }
+        hash::Writer::write(&hash, &log, None).unwrap();
+
+        let header = log::LogHeader::read(&log).unwrap();
+        let mut data = fs::read(&log).unwrap();
+        let mut blocks = Vec::new();
+        let mut pos = header.header_size as usize;
+        while pos < header.data_end as usize {
+            // Blocks this small have a one byte length
+            assert!(data[pos] < 0x80);
+            blocks.push(pos);
+            pos += 1 + data[pos] as usize;
+        }
+        data[blocks[blocks.len() / 2]] /= 2;
+        fs::write(&log, &data).unwrap();
+
+        runtime.block_on(async {
+            let reader = async_reader::AsyncReader::open(&hash, &log, 2)
+                .await
+                .unwrap();
+
+            let mut entries = reader.entries();
+            let mut count = 0u32;
+            let error = loop {
+                match future::poll_fn(|cx| pin::Pin::new(&mut entries).poll_next(cx)).await {
+                    Some(Ok(entry)) => {
+                        assert_eq!(&count.to_be_bytes()[..], &entry.key[..]);
+                        count += 1;
+                    }
+                    Some(Err(e)) => break e,
+                    None => panic!("no error after {} entries", count),
+                }
+            };
+            match error {
+                error::Error::Internal => {}
+                e => panic!("unexpected error: {:?}", e),
+            }
+            // The entries of the second batch before the broken block came first
+            assert!(count > 1024 && count < 2048);
+            assert!(
+                future::poll_fn(|cx| pin::Pin::new(&mut entries).poll_next(cx))
+                    .await
+                    .is_none()
+            );
+        });
+    }
+
+    #[cfg(feature = "rayon")]
+    #[test]
+    fn par_entries() {
+        use rayon::iter::ParallelIterator;
+
+        fn sorted<I>(entries: I) -> Vec<(log::EntryType, Vec<u8>, Vec<u8>)>
+        where
+            I: Iterator<Item = error::Result<log::Entry>>,
+        {
+            let mut entries = entries
+                .map(|e| e.unwrap())
+                .map(|e| (e.entry_type, e.key.to_vec(), e.value.to_vec()))
+                .collect::<Vec<_>>();
+            entries.sort();
+            entries
+        }
+
+        for &(compression_type, block_size) in &[
+            (log::CompressionType::None, 0),
+            (log::CompressionType::Snappy, 128),
+            (log::CompressionType::Zstd { level: 0 }, 1024),
+        ] {
+            let dir = tempdir::TempDir::new("sparkey-rs").unwrap();
+            let log = dir.path().join("data.spl");
+            let hash = dir.path().join("data.spi");
+
+            {
+                let mut writer = log::Writer::create(&log, compression_type, block_size).unwrap();
+                for i in 0..20_000u32 {
+                    // Some values span several blocks
+                    let value = if i % 100 == 0 {
+                        vec![i as u8; 3000]
+                    } else {
+                        i.to_le_bytes().to_vec()
+                    };
+                    writer.put(&(i % 15_000).to_be_bytes(), &value).unwrap();
+                    if i % 7 == 0 {
+                        writer.delete(&(i / 2).to_be_bytes()).unwrap();
+                    }
+                }
+            }
+            hash::Writer::write(&hash, &log, None).unwrap();
+
+            let log_reader = log::Reader::open(&log).unwrap();
+            let par = log_reader.par_entries().unwrap().collect::<Vec<_>>();
+            assert_eq!(
+                sorted(log_reader.entries().unwrap()),
+                sorted(par.into_iter())
+            );
+
+            let reader = hash::Reader::open(&hash, &log).unwrap();
+            let par = reader.par_entries().collect::<Vec<_>>();
+            assert_eq!(reader.num_entries(), par.len() as u64);
+            assert_eq!(sorted(reader.entries().unwrap()), sorted(par.into_iter()));
+        }
+    }
+
+    #[test]
+    fn roundtrip_borrowed() {
+        use std::borrow::Cow;
+
+        for &compression_type in &[log::CompressionType::None, log::CompressionType::Snappy] {
+            let dir = tempdir::TempDir::new("sparkey-rs").unwrap();
+            let log = dir.path().join("data.spl");
+            let hash = dir.path().join("data.spi");
+
+            {
+                let mut writer = log::Writer::create(&log, compression_type, 16).unwrap();
+                writer.put(&[1], &[2, 3, 4, 5]).unwrap();
+                writer.put(&[6], &[7; 100]).unwrap();
+            }
+            hash::Writer::write(&hash, &log, None).unwrap();
+
+            let reader = hash::Reader::open(&hash, &log).unwrap();
+
+            let value = reader.get_borrowed(&[1]).unwrap().unwrap();
+            assert_eq!(&[2, 3, 4, 5], &*value);
+            assert_eq!(
+                compression_type == log::CompressionType::None,
+                match value {
+                    Cow::Borrowed(_) => true,
+                    Cow::Owned(_) => false,
+                }
+            );
+            assert_eq!(&[7; 100][..], &*reader.get_borrowed(&[6]).unwrap().unwrap());
+            assert_eq!(None, reader.get_borrowed(&[11]).unwrap());
+
+            let entries = reader
+                .log_reader()
+                .borrowed_entries()
+                .unwrap()
+                .collect::<error::Result<Vec<_>>>()
+                .unwrap();
+            assert_eq!(2, entries.len());
+            assert_eq!(&[6], &*entries[1].key);
+            assert_eq!(&[7; 100][..], &*entries[1].value);
+        }
+    }
+
+    #[test]
+    fn roundtrip_streaming() {
+        use std::io::Read;
+
+        let dir = tempdir::TempDir::new("sparkey-rs").unwrap();
+        let log = dir.path().join("data.spl");
+        let hash = dir.path().join("data.spi");
+        let big = (0..10_000u32).map(|i| i as u8).collect::<Vec<_>>();
+
+        {
+            let mut writer = log::Writer::create(&log, log::CompressionType::Snappy, 64).unwrap();
+            writer.put(&[1], &big).unwrap();
+            writer.put(&[6], &[7, 8, 9, 10]).unwrap();
+        }
+        hash::Writer::write(&hash, &log, None).unwrap();
+
+        let reader = hash::Reader::open(&hash, &log).unwrap();
+
+        let mut value_reader = reader.get_reader(&[1]).unwrap().unwrap();
+        assert_eq!(big.len() as u64, value_reader.len());
+        let mut actual = Vec::new();
+        value_reader.read_to_end(&mut actual).unwrap();
+        assert_eq!(big, actual);
+        assert!(reader.get_reader(&[11]).unwrap().is_none());
+
+        let mut entries = reader.log_reader().streaming_entries().unwrap();
+        // Leave the first value unread to make sure the iterator skips past it
+        assert_eq!(&[1], &entries.next_entry().unwrap().unwrap().key[..]);
+        let mut entry = entries.next_entry().unwrap().unwrap();
+        assert_eq!(&[6], &entry.key[..]);
+        let mut actual = Vec::new();
+        entry.value.read_to_end(&mut actual).unwrap();
+        assert_eq!(vec![7, 8, 9, 10], actual);
+        drop(entry);
+        assert!(entries.next_entry().unwrap().is_none());
+    }
+
+    #[test]
+    fn roundtrip_lookup() {
+        for &compression_type in &[log::CompressionType::None, log::CompressionType::Snappy] {
+            let dir = tempdir::TempDir::new("sparkey-rs").unwrap();
+            let log = dir.path().join("data.spl");
+            let hash = dir.path().join("data.spi");
+
+            {
+                let mut writer = log::Writer::create(&log, compression_type, 16).unwrap();
+                writer.put(&[1], &[2, 3, 4, 5]).unwrap();
+                writer.put(&[6], &[7; 100]).unwrap();
+            }
+            hash::Writer::write(&hash, &log, None).unwrap();
+
+            let reader = hash::Reader::open(&hash, &log).unwrap();
+            let mut lookup = reader.lookup().unwrap();
+            let mut value = Vec::new();
+
+            for _ in 0..3 {
+                assert_eq!(Some(&[2, 3, 4, 5][..]), lookup.get(&[1]).unwrap());
+                assert_eq!(Some(&[7; 100][..]), lookup.get(&[6]).unwrap());
+                assert_eq!(None, lookup.get(&[11]).unwrap());
+
+                assert!(lookup.get_into(&[6], &mut value).unwrap());
+                assert_eq!(&[7; 100][..], &value[..]);
+                assert!(!lookup.get_into(&[11], &mut value).unwrap());
+                assert!(value.is_empty());
+            }
+        }
+    }
+
+    #[test]
+    fn multi_get() {
+        let dir = tempdir::TempDir::new("sparkey-rs").unwrap();
+        let log = dir.path().join("data.spl");
+        let hash = dir.path().join("data.spi");
+
+        {
+            let mut writer = log::Writer::create(&log, log::CompressionType::Snappy, 64).unwrap();
+            for i in 0..100u32 {
+                writer
+                    .put(&i.to_be_bytes(), &(i * 2).to_be_bytes())
+                    .unwrap();
+            }
+        }
+        hash::Writer::write(&hash, &log, None).unwrap();
+
+        let reader = hash::Reader::open(&hash, &log).unwrap();
+        let keys = (0..150u32).map(|i| i.to_be_bytes()).collect::<Vec<_>>();
+        let expected = (0..150u32)
+            .map(|i| {
+                if i < 100 {
+                    Some(bytes::BytesMut::from(&(i * 2).to_be_bytes()[..]))
+                } else {
+                    None
+                }
+            })
+            .collect::<Vec<_>>();
+
+        let capacity = reader.header().hash_capacity;
+        let slot = |i: usize| {
+            let hash = unsafe { sparkey_sys::hash_key(reader.as_raw(), keys[i].as_ptr(), 4) };
+            hash % capacity
+        };
+
+        for &order in &[
+            hash::ProbeOrder::None,
+            hash::ProbeOrder::Slot,
+            hash::ProbeOrder::LogOffset,
+        ] {
+            assert_eq!(expected, reader.multi_get(&keys, order).unwrap());
+            #[cfg(feature = "rayon")]
+            assert_eq!(expected, reader.par_multi_get(&keys, order).unwrap());
+
+            let mut probed = Vec::new();
+            reader
+                .multi_get_with(&keys, order, |i, value| {
+                    assert_eq!(expected[i].as_ref().map(|v| &v[..]), value);
+                    probed.push(i);
+                })
+                .unwrap();
+
+            match order {
+                hash::ProbeOrder::None => assert_eq!((0..150).collect::<Vec<_>>(), probed),
+                hash::ProbeOrder::Slot => {
+                    assert!(probed.windows(2).all(|w| slot(w[0]) <= slot(w[1])))
+                }
+                // Keys were written in order, and the missing ones come first
+                hash::ProbeOrder::LogOffset => {
+                    assert_eq!((100..150).chain(0..100).collect::<Vec<_>>(), probed)
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn resume_from_position() {
+        for &compression_type in &[log::CompressionType::None, log::CompressionType::Snappy] {
+            let dir = tempdir::TempDir::new("sparkey-rs").unwrap();
+            let log = dir.path().join("data.spl");
+
+            {
+                let mut writer = log::Writer::create(&log, compression_type, 32).unwrap();
+                for i in 0..50u8 {
+                    writer.put(&[i], &vec![i; usize::from(i % 7) * 10]).unwrap();
+                    if i % 10 == 0 {
+                        writer.delete(&[i]).unwrap();
+                    }
+                }
+            }
+
+            let reader = log::Reader::open(&log).unwrap();
+            let all = reader
+                .entries()
+                .unwrap()
+                .map(|e| e.unwrap().key)
+                .collect::<Vec<_>>();
+
+            let mut positions = Vec::new();
+            let mut entries = reader.entries().unwrap();
+            loop {
+                positions.push(entries.position().unwrap());
+                if entries.next().is_none() {
+                    break;
+                }
+            }
+            assert_eq!(all.len() + 1, positions.len());
+
+            let mut keys = reader.keys().unwrap();
+            for &position in &positions[..all.len()] {
+                assert_eq!(position, keys.position().unwrap());
+                keys.next().unwrap().unwrap();
+            }
+
+            for (i, &position) in positions.iter().enumerate() {
+                let rest = reader
+                    .entries_from(position)
+                    .unwrap()
+                    .map(|e| e.unwrap().key)
+                    .collect::<Vec<_>>();
+                assert_eq!(&all[i..], &rest[..]);
+            }
+        }
+    }
+
+    #[test]
+    fn headers() {
+        let dir = tempdir::TempDir::new("sparkey-rs").unwrap();
+        let log = dir.path().join("data.spl");
+        let hash = dir.path().join("data.spi");
+
+        {
+            let mut writer = log::Writer::create(&log, log::CompressionType::Snappy, 1024).unwrap();
+            writer.put(&[1], &[2, 3, 4, 5]).unwrap();
+            writer.put(&[6], &[7, 8, 9, 10]).unwrap();
+            writer.put(&[1], &[2]).unwrap();
+            writer.delete(&[6]).unwrap();
+        }
+        hash::Writer::write(&hash, &log, Some(hash::Type::Murmur3_64)).unwrap();
+
+        let reader = hash::Reader::open(&hash, &log).unwrap();
+
+        let log_header = reader.log_reader().header();
+        assert_eq!(log_header, log::LogHeader::read(&log).unwrap());
+        assert_eq!(3, log_header.num_puts);
+        assert_eq!(1, log_header.num_deletes);
+        assert_eq!(4, log_header.max_value_len);
+        assert_eq!(log::CompressionType::Snappy, log_header.compression_type);
+        assert_eq!(1024, log_header.compression_block_size);
+
+        let hash_header = reader.header();
+        assert_eq!(hash_header, hash::HashHeader::read(&hash).unwrap());
+        assert_eq!(log_header.file_identifier, hash_header.file_identifier);
+        assert_eq!(log_header.data_end, hash_header.data_end);
+        assert_eq!(3, hash_header.num_puts);
+        assert_eq!(1, hash_header.num_entries);
+        assert_eq!(hash::Type::Murmur3_64, hash_header.hash_type);
+        assert!(hash_header.garbage_size > 0);
+
+        assert!(log::LogHeader::read(&hash).is_err());
+        assert!(hash::HashHeader::read(&log).is_err());
+    }
+
+    #[test]
+    fn close_summary() {
+        let dir = tempdir::TempDir::new("sparkey-rs").unwrap();
+        let log = dir.path().join("data.spl");
+
+        let mut writer = log::Writer::create(&log, log::CompressionType::Snappy, 1024).unwrap();
+        writer.put(&[1], &[2, 3, 4, 5]).unwrap();
+        writer.put(&[6], &[7, 8, 9, 10]).unwrap();
+        let created = writer.close().unwrap();
+
+        assert_eq!(2, created.num_puts);
+        assert_eq!(0, created.num_deletes);
+        assert_eq!(
+            log::LogHeader::read(&log).unwrap().data_end,
+            created.data_end
+        );
+        assert!(created.bytes_written > 0);
+
+        let mut writer = log::Writer::append(&log).unwrap();
+        writer.delete(&[1]).unwrap();
+        let appended = writer.close().unwrap();
+
+        assert_eq!(0, appended.num_puts);
+        assert_eq!(1, appended.num_deletes);
+        assert_eq!(created.data_end + appended.bytes_written, appended.data_end);
+    }
+
     #[test]
     fn read_small() {
         use std::io::BufRead;
@@ -78,10 +1325,8 @@ mod test {
 
         for line in io::BufReader::new(csv_file).lines() {
             let line = line.unwrap();
-            let mut parts = line.splitn(2, ",");
-            let key = parts.next().unwrap();
-            let expected = parts.next().unwrap();
-            let actual_bytes = reader.get(&key.as_bytes()).unwrap().unwrap();
+            let (key, expected) = line.split_once(',').unwrap();
+            let actual_bytes = reader.get(key.as_bytes()).unwrap().unwrap();
             let actual = str::from_utf8(&actual_bytes).unwrap();
 
             assert_eq!(expected, actual);
@@ -108,9 +1353,7 @@ mod test {
 
             for line in io::BufReader::new(csv_file).lines() {
                 let line = line.unwrap();
-                let mut parts = line.splitn(2, ",");
-                let key = parts.next().unwrap();
-                let value = parts.next().unwrap();
+                let (key, value) = line.split_once(',').unwrap();
 
                 writer.put(key.as_bytes(), value.as_bytes()).unwrap();
             }
@@ -138,3 +1381,661 @@ mod test {
         }
     }
 }
+
+// Tests of the native readers and writers; the ones that compare against the C library need it too
+#[cfg(all(test, feature = "native"))]
+mod native_test {
+    use super::*;
+
+    extern crate tempdir;
+
+    use std::collections;
+    use std::fs;
+    use std::path;
+
+    // Puts and deletes with overwritten and deleted keys, and values of varying sizes
+    fn mixed_entries() -> Vec<(log::EntryType, Vec<u8>, Vec<u8>)> {
+        let mut entries = Vec::new();
+        for i in 0..200u32 {
+            let key = format!("key{}", i % 150).into_bytes();
+            let value = vec![i as u8; (i as usize * 37) % 300];
+            entries.push((log::EntryType::Put, key, value));
+            if i % 17 == 0 {
+                let key = format!("key{}", i / 2).into_bytes();
+                entries.push((log::EntryType::Delete, key, Vec::new()));
+            }
+        }
+        entries
+    }
+
+    // What `mixed_entries` leaves in an index
+    fn live_entries() -> collections::HashMap<Vec<u8>, Vec<u8>> {
+        let mut live = collections::HashMap::new();
+        for (entry_type, key, value) in mixed_entries() {
+            match entry_type {
+                log::EntryType::Put => live.insert(key, value),
+                log::EntryType::Delete => live.remove(&key),
+            };
+        }
+        live
+    }
+
+    fn write_mixed_log(path: &path::Path, compression_type: log::CompressionType, block_size: u32) {
+        let mut writer = native::log::Writer::create(path, compression_type, block_size).unwrap();
+        for (entry_type, key, value) in mixed_entries() {
+            match entry_type {
+                log::EntryType::Put => writer.put(&key, &value).unwrap(),
+                log::EntryType::Delete => writer.delete(&key).unwrap(),
+            }
+        }
+        writer.close().unwrap();
+    }
+
+    #[cfg(feature = "ffi")]
+    fn write_mixed_log_ffi(
+        path: &path::Path,
+        compression_type: log::CompressionType,
+        block_size: u32,
+    ) {
+        let mut writer = log::Writer::create(path, compression_type, block_size).unwrap();
+        for (entry_type, key, value) in mixed_entries() {
+            match entry_type {
+                log::EntryType::Put => writer.put(&key, &value).unwrap(),
+                log::EntryType::Delete => writer.delete(&key).unwrap(),
+            }
+        }
+        writer.close().unwrap();
+    }
+
+    #[cfg(feature = "ffi")]
+    #[test]
+    fn native_log_reader_matches_ffi() {
+        for &(compression_type, block_size) in &[
+            (log::CompressionType::None, 0),
+            (log::CompressionType::Snappy, 16),
+            (log::CompressionType::Snappy, 1024),
+            (log::CompressionType::Zstd { level: 3 }, 64),
+        ] {
+            let dir = tempdir::TempDir::new("sparkey-rs").unwrap();
+            let path = dir.path().join("data.spl");
+            write_mixed_log_ffi(&path, compression_type, block_size);
+
+            let ffi = log::Reader::open(&path).unwrap();
+            let native = native::log::Reader::open(&path).unwrap();
+
+            assert_eq!(ffi.header().data_end, native.header().data_end);
+            assert_eq!(ffi.header().num_puts, native.header().num_puts);
+            assert_eq!(log::LogHeader::read(&path).unwrap(), native.header());
+
+            let mut ffi_entries = ffi.entries().unwrap();
+            let mut native_entries = native.entries().unwrap();
+            let mut positions = Vec::new();
+            loop {
+                let position = ffi_entries.position().unwrap();
+                assert_eq!(position, native_entries.position().unwrap());
+                positions.push(position);
+
+                match (ffi_entries.next(), native_entries.next()) {
+                    (Some(expected), Some(actual)) => {
+                        let expected = expected.unwrap();
+                        let actual = actual.unwrap();
+                        assert_eq!(expected.entry_type, actual.entry_type);
+                        assert_eq!(expected.key, actual.key);
+                        assert_eq!(expected.value, actual.value);
+                    }
+                    (None, None) => break,
+                    (expected, actual) => panic!("{:?} != {:?}", expected, actual),
+                }
+            }
+
+            for &position in positions.iter().step_by(7) {
+                let expected = ffi
+                    .keys_from(position)
+                    .unwrap()
+                    .collect::<error::Result<Vec<_>>>()
+                    .unwrap();
+                let actual = native
+                    .keys_from(position)
+                    .unwrap()
+                    .collect::<error::Result<Vec<_>>>()
+                    .unwrap();
+                assert_eq!(expected, actual);
+            }
+
+            let expected = ffi.values().unwrap().collect::<error::Result<Vec<_>>>();
+            let actual = native.values().unwrap().collect::<error::Result<Vec<_>>>();
+            assert_eq!(expected.unwrap(), actual.unwrap());
+        }
+    }
+
+    #[cfg(feature = "ffi")]
+    #[test]
+    fn native_hash_reader_matches_ffi() {
+        for &hash_type in &[hash::Type::Murmur3_32, hash::Type::Murmur3_64] {
+            for &(compression_type, block_size) in &[
+                (log::CompressionType::None, 0),
+                (log::CompressionType::Snappy, 64),
+                (log::CompressionType::Zstd { level: 0 }, 64),
+            ] {
+                let dir = tempdir::TempDir::new("sparkey-rs").unwrap();
+                let log = dir.path().join("data.spl");
+                let hash = dir.path().join("data.spi");
+                write_mixed_log_ffi(&log, compression_type, block_size);
+                hash::Writer::write(&hash, &log, Some(hash_type)).unwrap();
+
+                let ffi = hash::Reader::open(&hash, &log).unwrap();
+                let native = native::hash::Reader::open(&hash, &log).unwrap();
+
+                assert_eq!(ffi.header(), native.header());
+
+                for i in 0..200 {
+                    let key = format!("key{}", i);
+                    assert_eq!(
+                        ffi.get(key.as_bytes()).unwrap(),
+                        native.get(key.as_bytes()).unwrap()
+                    );
+                }
+
+                let expected = ffi.entries().unwrap().collect::<error::Result<Vec<_>>>();
+                let actual = native.entries().unwrap().collect::<error::Result<Vec<_>>>();
+                let (expected, actual) = (expected.unwrap(), actual.unwrap());
+                assert_eq!(expected.len(), actual.len());
+                for (expected, actual) in expected.iter().zip(actual.iter()) {
+                    assert_eq!(expected.key, actual.key);
+                    assert_eq!(expected.value, actual.value);
+                }
+            }
+        }
+    }
+
+    #[cfg(feature = "ffi")]
+    #[test]
+    fn native_log_writer_matches_ffi() {
+        for &(compression_type, block_size) in &[
+            (log::CompressionType::None, 0),
+            (log::CompressionType::Snappy, 16),
+            (log::CompressionType::Snappy, 1024),
+            (log::CompressionType::Zstd { level: 3 }, 64),
+        ] {
+            let dir = tempdir::TempDir::new("sparkey-rs").unwrap();
+            let ffi_log = dir.path().join("ffi.spl");
+            let native_log = dir.path().join("native.spl");
+            write_mixed_log_ffi(&ffi_log, compression_type, block_size);
+
+            let mut writer =
+                native::log::Writer::create(&native_log, compression_type, block_size).unwrap();
+            for entry in log::Reader::open(&ffi_log).unwrap().entries().unwrap() {
+                let entry = entry.unwrap();
+                match entry.entry_type {
+                    log::EntryType::Put => writer.put(&entry.key, &entry.value).unwrap(),
+                    log::EntryType::Delete => writer.delete(&entry.key).unwrap(),
+                }
+            }
+            let offset = writer.offset();
+            let summary = writer.close().unwrap();
+            assert!(summary.data_end >= offset);
+
+            let expected = log::LogHeader::read(&ffi_log).unwrap();
+            let actual = log::LogHeader::read(&native_log).unwrap();
+            assert_eq!(expected.num_puts, actual.num_puts);
+            assert_eq!(expected.num_deletes, actual.num_deletes);
+            assert_eq!(expected.put_size, actual.put_size);
+            assert_eq!(expected.max_value_len, actual.max_value_len);
+            assert_eq!(expected.max_entries_per_block, actual.max_entries_per_block);
+
+            if compression_type == log::CompressionType::None {
+                let mut expected = fs::read(&ffi_log).unwrap();
+                let actual = fs::read(&native_log).unwrap();
+                expected[12..16].copy_from_slice(&actual[12..16]);
+                assert_eq!(expected, actual);
+            }
+
+            let expected = log::Reader::open(&ffi_log).unwrap();
+            let actual = log::Reader::open(&native_log).unwrap();
+            for (expected, actual) in expected.entries().unwrap().zip(actual.entries().unwrap()) {
+                let (expected, actual) = (expected.unwrap(), actual.unwrap());
+                assert_eq!(expected.entry_type, actual.entry_type);
+                assert_eq!(expected.key, actual.key);
+                assert_eq!(expected.value, actual.value);
+            }
+        }
+    }
+
+    #[test]
+    fn native_roundtrip() {
+        for &(compression_type, block_size) in &[
+            (log::CompressionType::None, 0),
+            (log::CompressionType::Snappy, 64),
+            (log::CompressionType::Zstd { level: 3 }, 64),
+        ] {
+            let dir = tempdir::TempDir::new("sparkey-rs").unwrap();
+            let log = dir.path().join("data.spl");
+            let hash = dir.path().join("data.spi");
+            write_mixed_log(&log, compression_type, block_size);
+            native::hash::Writer::write(&hash, &log, Default::default()).unwrap();
+
+            let reader = native::hash::Reader::open(&hash, &log).unwrap();
+            let live = live_entries();
+            assert_eq!(live.len() as u64, reader.num_entries());
+            for i in 0..200 {
+                let key = format!("key{}", i).into_bytes();
+                let expected = live.get(&key).map(|value| &value[..]);
+                assert_eq!(expected, reader.get(&key).unwrap().as_deref());
+            }
+
+            let entries = native::log::Reader::open(&log)
+                .unwrap()
+                .entries()
+                .unwrap()
+                .map(|entry| {
+                    entry.map(|entry| (entry.entry_type, entry.key.to_vec(), entry.value.to_vec()))
+                })
+                .collect::<error::Result<Vec<_>>>()
+                .unwrap();
+            assert_eq!(mixed_entries(), entries);
+        }
+    }
+
+    // The C library reads what the native writers write, and the other way around
+    #[cfg(feature = "ffi")]
+    #[test]
+    fn ffi_native_roundtrip() {
+        for &(compression_type, block_size) in &[
+            (log::CompressionType::None, 0),
+            (log::CompressionType::Snappy, 64),
+            (log::CompressionType::Zstd { level: 3 }, 64),
+        ] {
+            let dir = tempdir::TempDir::new("sparkey-rs").unwrap();
+            let ffi_log = dir.path().join("ffi.spl");
+            let ffi_hash = dir.path().join("ffi.spi");
+            let native_log = dir.path().join("native.spl");
+            let native_hash = dir.path().join("native.spi");
+
+            write_mixed_log_ffi(&ffi_log, compression_type, block_size);
+            hash::Writer::write(&ffi_hash, &ffi_log, None).unwrap();
+            write_mixed_log(&native_log, compression_type, block_size);
+            native::hash::Writer::write(&native_hash, &native_log, Default::default()).unwrap();
+
+            let ffi = hash::Reader::open(&native_hash, &native_log).unwrap();
+            let native = native::hash::Reader::open(&ffi_hash, &ffi_log).unwrap();
+            assert_eq!(ffi.num_entries(), native.num_entries());
+
+            let live = live_entries();
+            assert_eq!(live.len() as u64, ffi.num_entries());
+            for i in 0..200 {
+                let key = format!("key{}", i).into_bytes();
+                let expected = live.get(&key).map(|value| &value[..]);
+                assert_eq!(expected, ffi.get(&key).unwrap().as_deref());
+                assert_eq!(expected, native.get(&key).unwrap().as_deref());
+            }
+
+            let expected = log::Reader::open(&native_log)
+                .unwrap()
+                .entries()
+                .unwrap()
+                .map(|entry| entry.map(|entry| (entry.entry_type, entry.key, entry.value)))
+                .collect::<error::Result<Vec<_>>>()
+                .unwrap();
+            let actual = native::log::Reader::open(&ffi_log)
+                .unwrap()
+                .entries()
+                .unwrap()
+                .map(|entry| entry.map(|entry| (entry.entry_type, entry.key, entry.value)))
+                .collect::<error::Result<Vec<_>>>()
+                .unwrap();
+            assert_eq!(expected, actual);
+        }
+    }
+
+    #[test]
+    fn native_log_reader_corrupt_block_size() {
+        let dir = tempdir::TempDir::new("sparkey-rs").unwrap();
+        let path = dir.path().join("data.spl");
+        write_mixed_log(&path, log::CompressionType::Snappy, 64);
+
+        // A compressed size of u64::MAX for the first block
+        let header_size = native::log::Reader::open(&path)
+            .unwrap()
+            .header()
+            .header_size as usize;
+        let mut data = fs::read(&path).unwrap();
+        data[header_size..header_size + 10]
+            .copy_from_slice(&[0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0x01]);
+        fs::write(&path, &data).unwrap();
+
+        let reader = native::log::Reader::open(&path).unwrap();
+        let offset = header_size as u64;
+        match reader.entries().unwrap().next() {
+            Some(Err(error::Error::CorruptBlock { offset: actual })) => assert_eq!(offset, actual),
+            other => panic!("unexpected {:?}", other.map(|r| r.map(|_| ()))),
+        }
+        match reader.split(4) {
+            Err(error::Error::CorruptBlock { offset: actual }) => assert_eq!(offset, actual),
+            other => panic!("unexpected {:?}", other),
+        }
+    }
+
+    #[test]
+    fn native_log_writer_put_reader() {
+        let dir = tempdir::TempDir::new("sparkey-rs").unwrap();
+        let log = dir.path().join("data.spl");
+        let hash = dir.path().join("data.spi");
+        let value = (0..10_000u32).map(|i| i as u8).collect::<Vec<_>>();
+
+        let mut writer =
+            native::log::Writer::create(&log, log::CompressionType::Snappy, 64).unwrap();
+        writer.put(b"small", b"value").unwrap();
+        writer
+            .put_reader(b"large", &value[..], value.len() as u64)
+            .unwrap();
+        assert_eq!(2, writer.num_puts());
+        match writer.put_reader(b"short", &value[..10], 20) {
+            Err(error::Error::UnexpectedEof) => {}
+            r => panic!("unexpected result: {:?}", r),
+        }
+        match writer.put(b"after", b"value") {
+            Err(error::Error::LogWriterPoisoned) => {}
+            r => panic!("unexpected result: {:?}", r),
+        }
+        match writer.close() {
+            Err(error::Error::LogWriterPoisoned) => {}
+            r => panic!("unexpected result: {:?}", r),
+        }
+        // Nothing was flushed before the failure, so the log still looks empty
+        let reader = native::log::Reader::open(&log).unwrap();
+        assert_eq!(0, reader.header().num_puts);
+        assert_eq!(0, reader.entries().unwrap().count());
+
+        let mut writer =
+            native::log::Writer::create(&log, log::CompressionType::Snappy, 64).unwrap();
+        writer.put(b"small", b"value").unwrap();
+        writer
+            .put_reader(b"large", &value[..], value.len() as u64)
+            .unwrap();
+        writer.delete(b"small").unwrap();
+        assert_eq!(1, writer.num_deletes());
+        writer.close().unwrap();
+
+        native::hash::Writer::write(&hash, &log, Default::default()).unwrap();
+        let reader = native::hash::Reader::open(&hash, &log).unwrap();
+        assert_eq!(None, reader.get(b"small").unwrap());
+        assert_eq!(&value[..], &reader.get(b"large").unwrap().unwrap()[..]);
+    }
+
+    #[cfg(feature = "ffi")]
+    #[test]
+    fn native_dictionary_matches_ffi() {
+        let dir = tempdir::TempDir::new("sparkey-rs").unwrap();
+        let log = dir.path().join("data.spl");
+        let native_log = dir.path().join("native.spl");
+        let hash = dir.path().join("data.spi");
+        let samples = super::test::dictionary_samples();
+        let dictionary = log::train_dictionary(&samples, 4096).unwrap();
+        let compression_type = log::CompressionType::Zstd { level: 3 };
+
+        let mut writer =
+            log::Writer::create_with_dictionary(&log, compression_type, 256, &dictionary).unwrap();
+        let mut native_writer = native::log::Writer::create_with_dictionary(
+            &native_log,
+            compression_type,
+            256,
+            &dictionary,
+        )
+        .unwrap();
+        for (i, sample) in samples.iter().enumerate() {
+            writer.put(&(i as u32).to_be_bytes(), sample).unwrap();
+            native_writer
+                .put(&(i as u32).to_be_bytes(), sample)
+                .unwrap();
+        }
+        writer.close().unwrap();
+        native_writer.close().unwrap();
+
+        // Both readers can read the logs written by either writer
+        for path in &[&log, &native_log] {
+            let reader = log::Reader::open(path).unwrap();
+            let native_reader = native::log::Reader::open(path).unwrap();
+            assert_eq!(
+                reader.header().header_size,
+                native_reader.header().header_size
+            );
+            let values = reader
+                .values()
+                .unwrap()
+                .map(|v| v.unwrap().to_vec())
+                .collect::<Vec<_>>();
+            let native_values = native_reader
+                .values()
+                .unwrap()
+                .map(|v| v.unwrap().to_vec())
+                .collect::<Vec<_>>();
+            assert_eq!(samples, values);
+            assert_eq!(samples, native_values);
+        }
+
+        hash::Writer::write(&hash, &native_log, None).unwrap();
+        let reader = native::hash::Reader::open(&hash, &native_log).unwrap();
+        assert_eq!(
+            &samples[5][..],
+            &reader.get(&5u32.to_be_bytes()).unwrap().unwrap()[..]
+        );
+    }
+
+    #[cfg(feature = "ffi")]
+    #[test]
+    fn native_hash_writer_matches_ffi() {
+        for &hash_type in &[hash::Type::Murmur3_32, hash::Type::Murmur3_64] {
+            for &(compression_type, block_size) in &[
+                (log::CompressionType::None, 0),
+                (log::CompressionType::Snappy, 64),
+                (log::CompressionType::Zstd { level: 0 }, 64),
+            ] {
+                let dir = tempdir::TempDir::new("sparkey-rs").unwrap();
+                let log = dir.path().join("data.spl");
+                let ffi_hash = dir.path().join("ffi.spi");
+                let native_hash = dir.path().join("native.spi");
+                write_mixed_log(&log, compression_type, block_size);
+                hash::Writer::write(&ffi_hash, &log, Some(hash_type)).unwrap();
+
+                let options = native::hash::WriterOptions {
+                    hash_type: Some(hash_type),
+                    hash_seed: Some(hash::HashHeader::read(&ffi_hash).unwrap().hash_seed),
+                    ..Default::default()
+                };
+                let header = native::hash::Writer::write(&native_hash, &log, options).unwrap();
+
+                assert_eq!(hash::HashHeader::read(&native_hash).unwrap(), header);
+                assert_eq!(
+                    fs::read(&ffi_hash).unwrap(),
+                    fs::read(&native_hash).unwrap()
+                );
+
+                let options = native::hash::WriterOptions {
+                    capacity_factor: 2.0,
+                    account_garbage: false,
+                    ..options
+                };
+                let header = native::hash::Writer::write(&native_hash, &log, options).unwrap();
+                assert_eq!(0, header.garbage_size);
+                assert!(header.hash_capacity > 2 * header.num_puts);
+
+                let expected = hash::Reader::open(&ffi_hash, &log).unwrap();
+                let actual = hash::Reader::open(&native_hash, &log).unwrap();
+                for i in 0..200 {
+                    let key = format!("key{}", i);
+                    assert_eq!(
+                        expected.get(key.as_bytes()).unwrap(),
+                        actual.get(key.as_bytes()).unwrap()
+                    );
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn native_hash_writer_parallel() {
+        for &(compression_type, block_size) in &[
+            (log::CompressionType::None, 0),
+            (log::CompressionType::Snappy, 64),
+            (log::CompressionType::Zstd { level: 0 }, 64),
+        ] {
+            let dir = tempdir::TempDir::new("sparkey-rs").unwrap();
+            let log = dir.path().join("data.spl");
+            let sequential = dir.path().join("sequential.spi");
+            let parallel = dir.path().join("parallel.spi");
+            write_mixed_log(&log, compression_type, block_size);
+
+            let options = native::hash::WriterOptions {
+                hash_seed: Some(1234),
+                ..Default::default()
+            };
+            native::hash::Writer::write(&sequential, &log, options).unwrap();
+
+            let mut reports = Vec::new();
+            let options = native::hash::WriterOptions {
+                threads: 4,
+                ..options
+            };
+            let header =
+                native::hash::Writer::write_with_progress(&parallel, &log, options, |progress| {
+                    reports.push(progress)
+                })
+                .unwrap();
+
+            assert_eq!(fs::read(&sequential).unwrap(), fs::read(&parallel).unwrap());
+
+            let last = reports.last().unwrap();
+            let log_header = native::log::Reader::open(&log).unwrap().header();
+            assert_eq!(log_header.num_puts + log_header.num_deletes, last.entries);
+            assert_eq!(header.data_end, last.offset);
+            assert_eq!(header.data_end, last.data_end);
+        }
+    }
+
+    #[cfg(feature = "ffi")]
+    #[test]
+    fn hash_writer_threads() {
+        let dir = tempdir::TempDir::new("sparkey-rs").unwrap();
+        let log = dir.path().join("data.spl");
+        let hash = dir.path().join("data.spi");
+        write_mixed_log(&log, log::CompressionType::Zstd { level: 0 }, 64);
+
+        let mut reports = Vec::new();
+        let options = native::hash::WriterOptions {
+            threads: 4,
+            ..Default::default()
+        };
+        let header = hash::Writer::write_with_progress(&hash, &log, options, |progress| {
+            reports.push(progress)
+        })
+        .unwrap();
+        assert_eq!(header, hash::HashHeader::read(&hash).unwrap());
+        assert_eq!(header.data_end, reports.last().unwrap().offset);
+
+        let expected_path = dir.path().join("expected.spi");
+        hash::Writer::write(&expected_path, &log, None).unwrap();
+        let expected = hash::Reader::open(&expected_path, &log).unwrap();
+        let actual = hash::Reader::open(&hash, &log).unwrap();
+        assert_eq!(expected.num_entries(), actual.num_entries());
+        for entry in expected.entries().unwrap() {
+            let entry = entry.unwrap();
+            assert_eq!(Some(entry.value), actual.get(&entry.key).unwrap());
+        }
+    }
+
+    #[test]
+    fn native_hash_writer_external() {
+        for &(compression_type, block_size) in &[
+            (log::CompressionType::None, 0),
+            (log::CompressionType::Snappy, 64),
+        ] {
+            for &capacity_factor in &[1.0, 1.3] {
+                let dir = tempdir::TempDir::new("sparkey-rs").unwrap();
+                let log = dir.path().join("data.spl");
+                let in_memory = dir.path().join("in_memory.spi");
+                let external = dir.path().join("external.spi");
+                write_mixed_log(&log, compression_type, block_size);
+
+                let options = native::hash::WriterOptions {
+                    capacity_factor,
+                    ..Default::default()
+                };
+                let expected = native::hash::Writer::write(&in_memory, &log, options).unwrap();
+
+                let options = native::hash::WriterOptions {
+                    memory_budget: Some(128 << 10),
+                    threads: 2,
+                    ..options
+                };
+                let header = native::hash::Writer::write(&external, &log, options).unwrap();
+
+                let actual = native::hash::Reader::open(&external, &log).unwrap();
+                assert_eq!(actual.header(), header);
+                assert_eq!(expected.hash_capacity, header.hash_capacity);
+                assert_eq!(expected.num_entries, header.num_entries);
+                assert_eq!(expected.garbage_size, header.garbage_size);
+                // No spill files are left behind
+                assert_eq!(3, fs::read_dir(dir.path()).unwrap().count());
+
+                let expected = native::hash::Reader::open(&in_memory, &log).unwrap();
+                for i in 0..200 {
+                    let key = format!("key{}", i);
+                    assert_eq!(
+                        expected.get(key.as_bytes()).unwrap(),
+                        actual.get(key.as_bytes()).unwrap()
+                    );
+                }
+                assert_eq!(
+                    expected.entries().unwrap().count(),
+                    actual.entries().unwrap().count()
+                );
+            }
+        }
+
+        // The slot table of this log takes several times the budget, and more spill files than
+        // fit in the budget, so some partitions have to be split again
+        let dir = tempdir::TempDir::new("sparkey-rs").unwrap();
+        let log = dir.path().join("data.spl");
+        let in_memory = dir.path().join("in_memory.spi");
+        let external = dir.path().join("external.spi");
+        let mut writer = native::log::Writer::create(&log, log::CompressionType::None, 0).unwrap();
+        for i in 0..100_000u32 {
+            writer.put(&(i % 90_000).to_be_bytes(), b"value").unwrap();
+        }
+        writer.close().unwrap();
+
+        let expected = native::hash::Writer::write(&in_memory, &log, Default::default()).unwrap();
+        let options = native::hash::WriterOptions {
+            memory_budget: Some(128 << 10),
+            threads: 2,
+            ..Default::default()
+        };
+        let header = native::hash::Writer::write(&external, &log, options).unwrap();
+        assert_eq!(expected.num_entries, header.num_entries);
+        assert_eq!(expected.garbage_size, header.garbage_size);
+        assert_eq!(3, fs::read_dir(dir.path()).unwrap().count());
+
+        let reader = native::hash::Reader::open(&external, &log).unwrap();
+        for i in (0..90_000u32).step_by(97) {
+            assert_eq!(
+                &b"value"[..],
+                &reader.get(&i.to_be_bytes()).unwrap().unwrap()[..]
+            );
+        }
+
+        for &(memory_budget, threads) in &[(1 << 10, 1), (64 << 10, 8)] {
+            let options = native::hash::WriterOptions {
+                memory_budget: Some(memory_budget),
+                threads,
+                ..Default::default()
+            };
+            match native::hash::Writer::write(&external, &log, options) {
+                Err(error::Error::MemoryBudgetTooSmall { budget, needed }) => {
+                    assert_eq!(memory_budget, budget);
+                    assert!(needed > budget);
+                }
+                r => panic!("unexpected result: {:?}", r),
+            }
+        }
+    }
+}
@@ -0,0 +1,989 @@
+use std::borrow;
+use std::ffi;
+use std::fmt;
+use std::io;
+use std::marker;
+use std::mem;
+use std::os;
+use std::path;
+use std::ptr;
+use std::slice;
+use std::sync;
+
+use sparkey_sys::train_dictionary as train_dictionary_raw;
+use sparkey_sys::*;
+
+use crate::error;
+use crate::log::{
+    dictionary_size, CompressionType, Entry, EntryType, LogHeader, LogPosition, WriterSummary,
+};
+use crate::util;
+
+// Clones share the same underlying reader, which stays open until the last of them is dropped.
+#[derive(Clone, Debug)]
+pub struct Reader(
+    *mut logreader,
+    // Only held to keep the raw reader open
+    #[allow(dead_code)] Option<sync::Arc<dyn fmt::Debug + Send + Sync>>,
+);
+
+// Closes the reader when dropped, along with anything that has to stay around until then
+#[derive(Debug)]
+struct Handle(
+    *mut logreader,
+    #[allow(dead_code)] Option<sync::Arc<dyn fmt::Debug + Send + Sync>>,
+);
+
+#[derive(Debug)]
+pub struct Writer(*mut logwriter, logheader);
+
+#[derive(Debug)]
+pub struct BorrowedEntry<'a> {
+    pub entry_type: EntryType,
+    pub key: borrow::Cow<'a, [u8]>,
+    pub value: borrow::Cow<'a, [u8]>,
+}
+
+pub struct StreamingEntry<'a> {
+    pub entry_type: EntryType,
+    pub key: bytes::BytesMut,
+    pub value: ValueReader<'a>,
+}
+
+pub struct ValueReader<'a> {
+    iter: *mut logiter,
+    reader: *mut logreader,
+    owned: bool,
+    len: u64,
+    chunk: *const u8,
+    chunk_len: usize,
+    chunk_pos: usize,
+    marker: marker::PhantomData<&'a Reader>,
+}
+
+pub struct Entries<'a>(
+    *mut logiter,
+    Reader,
+    Option<*mut hashreader>,
+    marker::PhantomData<&'a Reader>,
+);
+
+pub struct Keys<'a>(
+    *mut logiter,
+    Reader,
+    Option<*mut hashreader>,
+    marker::PhantomData<&'a Reader>,
+);
+
+pub struct Values<'a>(
+    *mut logiter,
+    Reader,
+    Option<*mut hashreader>,
+    marker::PhantomData<&'a Reader>,
+);
+
+pub struct BorrowedEntries<'a>(*mut logiter, &'a Reader, Option<*mut hashreader>);
+
+pub struct StreamingEntries<'a>(*mut logiter, &'a Reader, Option<*mut hashreader>);
+
+impl CompressionType {
+    pub fn from_raw(raw: compression_type) -> Self {
+        match raw {
+            compression_type::COMPRESSION_NONE => CompressionType::None,
+            compression_type::COMPRESSION_SNAPPY => CompressionType::Snappy,
+            compression_type::COMPRESSION_ZSTD => CompressionType::Zstd { level: 0 },
+        }
+    }
+
+    pub fn as_raw(self) -> compression_type {
+        match self {
+            CompressionType::None => compression_type::COMPRESSION_NONE,
+            CompressionType::Snappy => compression_type::COMPRESSION_SNAPPY,
+            CompressionType::Zstd { .. } => compression_type::COMPRESSION_ZSTD,
+        }
+    }
+}
+
+impl EntryType {
+    pub fn from_raw(raw: entry_type) -> Self {
+        match raw {
+            entry_type::ENTRY_PUT => EntryType::Put,
+            entry_type::ENTRY_DELETE => EntryType::Delete,
+        }
+    }
+
+    pub fn as_raw(self) -> entry_type {
+        match self {
+            EntryType::Put => entry_type::ENTRY_PUT,
+            EntryType::Delete => entry_type::ENTRY_DELETE,
+        }
+    }
+}
+
+impl LogHeader {
+    pub fn read<P>(path: P) -> error::Result<Self>
+    where
+        P: AsRef<path::Path>,
+    {
+        let mut raw = mem::MaybeUninit::zeroed();
+        let path = util::path_to_cstring(path)?;
+
+        util::handle(unsafe { load_logheader(raw.as_mut_ptr(), path.as_ptr()) })?;
+
+        Ok(Self::from_raw(unsafe { &raw.assume_init() }))
+    }
+
+    pub fn from_raw(raw: &logheader) -> Self {
+        LogHeader {
+            major_version: raw.major_version,
+            minor_version: raw.minor_version,
+            file_identifier: raw.file_identifier,
+            num_puts: raw.num_puts,
+            num_deletes: raw.num_deletes,
+            put_size: raw.put_size,
+            delete_size: raw.delete_size,
+            data_end: raw.data_end,
+            max_key_len: raw.max_key_len,
+            max_value_len: raw.max_value_len,
+            compression_type: CompressionType::from_raw(raw.compression_type),
+            compression_block_size: raw.compression_block_size,
+            header_size: raw.header_size,
+            max_entries_per_block: raw.max_entries_per_block,
+        }
+    }
+}
+
+// Trains a zstd dictionary of at most `max_size` bytes from sample values, for use with
+// `Writer::create_with_dictionary`.
+pub fn train_dictionary<T>(samples: &[T], max_size: usize) -> error::Result<Vec<u8>>
+where
+    T: AsRef<[u8]>,
+{
+    use std::convert::TryFrom;
+
+    let mut buf = Vec::new();
+    let mut sizes = Vec::with_capacity(samples.len());
+    for sample in samples {
+        let sample = sample.as_ref();
+        buf.extend_from_slice(sample);
+        sizes.push(sample.len());
+    }
+    let num_samples = os::raw::c_uint::try_from(sizes.len()).map_err(|_| {
+        error::Error::DictionaryTrainingFailed {
+            reason: "too many samples".to_owned(),
+        }
+    })?;
+
+    let mut dictionary = vec![0; max_size];
+    let mut size = max_size;
+    let reason = unsafe {
+        train_dictionary_raw(
+            dictionary.as_mut_ptr(),
+            &mut size,
+            buf.as_ptr(),
+            sizes.as_ptr(),
+            num_samples,
+        )
+    };
+    if !reason.is_null() {
+        let reason = unsafe { ffi::CStr::from_ptr(reason) }
+            .to_string_lossy()
+            .into_owned();
+        return Err(error::Error::DictionaryTrainingFailed { reason });
+    }
+
+    dictionary.truncate(size);
+    Ok(dictionary)
+}
+
+impl Writer {
+    #[allow(clippy::cast_possible_wrap)]
+    pub fn create<P>(
+        path: P,
+        compression_type: CompressionType,
+        compression_block_size: u32,
+    ) -> error::Result<Self>
+    where
+        P: AsRef<path::Path>,
+    {
+        let mut raw = ptr::null_mut();
+        let path = util::path_to_cstring(path)?;
+
+        util::handle(unsafe {
+            logwriter_create(
+                &mut raw,
+                path.as_ptr(),
+                compression_type.as_raw(),
+                compression_block_size as os::raw::c_int,
+            )
+        })?;
+
+        if let CompressionType::Zstd { level } = compression_type {
+            unsafe { logwriter_set_compression_level(raw, level) };
+        }
+
+        Ok(unsafe { Self::from_raw(raw) })
+    }
+
+    // Like `create`, but compresses every block with a shared dictionary (see
+    // `train_dictionary`), which is stored in the log header.  Only zstd supports dictionaries.
+    #[allow(clippy::cast_possible_wrap)]
+    pub fn create_with_dictionary<P>(
+        path: P,
+        compression_type: CompressionType,
+        compression_block_size: u32,
+        dictionary: &[u8],
+    ) -> error::Result<Self>
+    where
+        P: AsRef<path::Path>,
+    {
+        let level = match compression_type {
+            CompressionType::Zstd { level } => level,
+            _ => return Err(error::Error::InvalidCompressionType),
+        };
+        let dictionary_size = dictionary_size(dictionary)?;
+
+        let mut raw = ptr::null_mut();
+        let path = util::path_to_cstring(path)?;
+
+        util::handle(unsafe {
+            logwriter_create_dictionary(
+                &mut raw,
+                path.as_ptr(),
+                compression_block_size as os::raw::c_int,
+                dictionary.as_ptr(),
+                dictionary_size,
+            )
+        })?;
+
+        unsafe { logwriter_set_compression_level(raw, level) };
+
+        Ok(unsafe { Self::from_raw(raw) })
+    }
+
+    pub fn append<P>(path: P) -> error::Result<Self>
+    where
+        P: AsRef<path::Path>,
+    {
+        let mut raw = ptr::null_mut();
+        let path = util::path_to_cstring(path)?;
+
+        util::handle(unsafe { logwriter_append(&mut raw, path.as_ptr()) })?;
+
+        Ok(unsafe { Self::from_raw(raw) })
+    }
+
+    /// # Safety
+    ///
+    /// `raw` must be an open log writer that is not owned by anything else.
+    pub unsafe fn from_raw(raw: *mut logwriter) -> Self {
+        Self(raw, *logwriter_header(raw))
+    }
+
+    pub fn as_raw(&self) -> *mut logwriter {
+        self.0
+    }
+
+    pub fn put(&mut self, key: &[u8], value: &[u8]) -> error::Result<()> {
+        util::handle(unsafe {
+            logwriter_put(
+                self.0,
+                key.len() as u64,
+                key.as_ptr(),
+                value.len() as u64,
+                value.as_ptr(),
+            )
+        })
+    }
+
+    pub fn delete(&mut self, key: &[u8]) -> error::Result<()> {
+        util::handle(unsafe { logwriter_delete(self.0, key.len() as u64, key.as_ptr()) })
+    }
+
+    pub fn flush(&mut self) -> error::Result<()> {
+        util::handle(unsafe { logwriter_flush(self.0) })
+    }
+
+    pub fn close(mut self) -> error::Result<WriterSummary> {
+        let result = self.try_close();
+        // The C library keeps the writer open if closing fails, and retrying would most likely
+        // fail the same way, so release it without flushing
+        if result.is_err() {
+            unsafe { logwriter_discard(&mut self.0) };
+        }
+        result
+    }
+
+    fn try_close(&mut self) -> error::Result<WriterSummary> {
+        self.flush()?;
+
+        let initial = &self.1;
+        let header = unsafe { *logwriter_header(self.0) };
+        let summary = WriterSummary {
+            num_puts: header.num_puts - initial.num_puts,
+            num_deletes: header.num_deletes - initial.num_deletes,
+            put_size: header.put_size - initial.put_size,
+            delete_size: header.delete_size - initial.delete_size,
+            bytes_written: header.data_end - initial.data_end,
+            data_end: header.data_end,
+        };
+
+        util::handle(unsafe { logwriter_close(&mut self.0) })?;
+
+        Ok(summary)
+    }
+}
+
+impl Drop for Writer {
+    fn drop(&mut self) {
+        if self.0.is_null() {
+            return;
+        }
+
+        if let Err(e) = util::handle(unsafe { logwriter_close(&mut self.0) }) {
+            ::log::error!("failed to close log writer: {}", e);
+            unsafe { logwriter_discard(&mut self.0) };
+        }
+    }
+}
+
+unsafe impl Send for Writer {}
+
+impl Reader {
+    pub fn open<P>(path: P) -> error::Result<Self>
+    where
+        P: AsRef<path::Path>,
+    {
+        Self::open_guarded(path, None)
+    }
+
+    // Like `open`, but keeps `guard` alive until the reader and everything created from it is gone
+    pub(crate) fn open_guarded<P>(
+        path: P,
+        guard: Option<sync::Arc<dyn fmt::Debug + Send + Sync>>,
+    ) -> error::Result<Self>
+    where
+        P: AsRef<path::Path>,
+    {
+        let mut raw = ptr::null_mut();
+        let path = util::path_to_cstring(path)?;
+
+        util::handle(unsafe { logreader_open(&mut raw, path.as_ptr()) })?;
+
+        Ok(Self(raw, Some(sync::Arc::new(Handle(raw, guard)))))
+    }
+
+    /// # Safety
+    ///
+    /// `raw` must be an open log reader that outlives the returned value, its clones and any
+    /// iterators created from them.
+    pub unsafe fn from_raw(raw: *mut logreader) -> Self {
+        Self(raw, None)
+    }
+
+    // Keeps `owner` alive for as long as any handle to `raw` exists, for readers that are owned
+    // by something else, like the log reader of a hash reader
+    pub(crate) unsafe fn with_owner(
+        raw: *mut logreader,
+        owner: sync::Arc<dyn fmt::Debug + Send + Sync>,
+    ) -> Self {
+        Self(raw, Some(owner))
+    }
+
+    pub fn as_raw(&self) -> *mut logreader {
+        self.0
+    }
+
+    pub fn header(&self) -> LogHeader {
+        LogHeader::from_raw(unsafe { &*logreader_header(self.0) })
+    }
+
+    pub fn max_key_len(&self) -> u64 {
+        unsafe { logreader_maxkeylen(self.0) }
+    }
+
+    pub fn max_value_len(&self) -> u64 {
+        unsafe { logreader_maxvaluelen(self.0) }
+    }
+
+    #[allow(clippy::cast_possible_wrap, clippy::cast_sign_loss)]
+    pub fn compression_block_size(&self) -> u32 {
+        unsafe { logreader_get_compression_blocksize(self.0) as u32 }
+    }
+
+    pub fn compression_type(&self) -> CompressionType {
+        unsafe { CompressionType::from_raw(logreader_get_compression_type(self.0)) }
+    }
+
+    pub fn entries(&self) -> error::Result<Entries<'_>> {
+        self.clone().into_entries()
+    }
+
+    pub fn keys(&self) -> error::Result<Keys<'_>> {
+        self.clone().into_keys()
+    }
+
+    pub fn values(&self) -> error::Result<Values<'_>> {
+        self.clone().into_values()
+    }
+
+    // Like `entries`, but the iterator keeps the reader open, so it can be returned from
+    // functions or moved to other threads
+    pub fn into_entries(self) -> error::Result<Entries<'static>> {
+        let mut raw = ptr::null_mut();
+
+        util::handle(unsafe { logiter_create(&mut raw, self.0) })?;
+
+        Ok(Entries(raw, self, None, marker::PhantomData))
+    }
+
+    pub fn into_keys(self) -> error::Result<Keys<'static>> {
+        let mut raw = ptr::null_mut();
+
+        util::handle(unsafe { logiter_create(&mut raw, self.0) })?;
+
+        Ok(Keys(raw, self, None, marker::PhantomData))
+    }
+
+    pub fn into_values(self) -> error::Result<Values<'static>> {
+        let mut raw = ptr::null_mut();
+
+        util::handle(unsafe { logiter_create(&mut raw, self.0) })?;
+
+        Ok(Values(raw, self, None, marker::PhantomData))
+    }
+
+    pub fn into_entries_from(self, position: LogPosition) -> error::Result<Entries<'static>> {
+        Ok(Entries(
+            self.iter_at(position)?,
+            self,
+            None,
+            marker::PhantomData,
+        ))
+    }
+
+    pub fn borrowed_entries(&self) -> error::Result<BorrowedEntries<'_>> {
+        let mut raw = ptr::null_mut();
+
+        util::handle(unsafe { logiter_create(&mut raw, self.0) })?;
+
+        Ok(BorrowedEntries(raw, self, None))
+    }
+
+    pub fn entries_from(&self, position: LogPosition) -> error::Result<Entries<'_>> {
+        Ok(Entries(
+            self.iter_at(position)?,
+            self.clone(),
+            None,
+            marker::PhantomData,
+        ))
+    }
+
+    pub fn keys_from(&self, position: LogPosition) -> error::Result<Keys<'_>> {
+        Ok(Keys(
+            self.iter_at(position)?,
+            self.clone(),
+            None,
+            marker::PhantomData,
+        ))
+    }
+
+    pub fn values_from(&self, position: LogPosition) -> error::Result<Values<'_>> {
+        Ok(Values(
+            self.iter_at(position)?,
+            self.clone(),
+            None,
+            marker::PhantomData,
+        ))
+    }
+
+    #[allow(clippy::cast_possible_wrap)]
+    fn iter_at(&self, position: LogPosition) -> error::Result<*mut logiter> {
+        let header_size = unsafe { logreader_header_size(self.0) };
+        let data_end = unsafe { logreader_data_end(self.0) };
+
+        if position.offset < header_size
+            || position.offset > data_end
+            || (position.offset == data_end && position.entry_index != 0)
+            || position.entry_index > os::raw::c_int::MAX as u32
+        {
+            return Err(error::Error::InvalidLogPosition { position });
+        }
+
+        let mut raw = ptr::null_mut();
+
+        util::handle(unsafe { logiter_create(&mut raw, self.0) })?;
+
+        let result =
+            util::handle(unsafe { logiter_seek(raw, self.0, position.offset) }).and_then(|()| {
+                util::handle(unsafe {
+                    logiter_skip(raw, self.0, position.entry_index as os::raw::c_int)
+                })
+            });
+
+        if let Err(e) = result {
+            unsafe { logiter_close(&mut raw) };
+            return Err(e);
+        }
+
+        Ok(raw)
+    }
+
+    pub fn streaming_entries(&self) -> error::Result<StreamingEntries<'_>> {
+        let mut raw = ptr::null_mut();
+
+        util::handle(unsafe { logiter_create(&mut raw, self.0) })?;
+
+        Ok(StreamingEntries(raw, self, None))
+    }
+}
+
+impl Drop for Handle {
+    fn drop(&mut self) {
+        unsafe { logreader_close(&mut self.0) }
+    }
+}
+
+unsafe impl Send for Reader {}
+
+unsafe impl Sync for Reader {}
+
+unsafe impl Send for Handle {}
+
+unsafe impl Sync for Handle {}
+
+impl<'a> Entries<'a> {
+    /// # Safety
+    ///
+    /// `raw` must be an iterator created for `reader` that is not owned by anything else, and
+    /// `hash` must be an open hash reader for `reader`.
+    pub unsafe fn from_raw(
+        raw: *mut logiter,
+        reader: &'a Reader,
+        hash: Option<*mut hashreader>,
+    ) -> Entries<'a> {
+        Entries(raw, reader.clone(), hash, marker::PhantomData)
+    }
+
+    /// # Safety
+    ///
+    /// Like `from_raw`, but the iterator owns a handle to `reader` and so can outlive the
+    /// original.
+    pub unsafe fn from_raw_owned(
+        raw: *mut logiter,
+        reader: Reader,
+        hash: Option<*mut hashreader>,
+    ) -> Entries<'static> {
+        Entries(raw, reader, hash, marker::PhantomData)
+    }
+
+    pub fn as_raw(&self) -> *mut logiter {
+        self.0
+    }
+
+    #[allow(clippy::cast_possible_wrap)]
+    pub fn skip(&mut self, count: u32) -> error::Result<()> {
+        util::handle(unsafe { logiter_skip(self.0, (self.1).0, count as os::raw::c_int) })
+    }
+
+    pub fn position(&mut self) -> error::Result<LogPosition> {
+        tell(self.0, (self.1).0)
+    }
+
+    fn try_next(&mut self) -> error::Result<Option<Entry>> {
+        if let Some(hash) = self.2 {
+            util::handle(unsafe { logiter_hashnext(self.0, hash) })?;
+        } else {
+            util::handle(unsafe { logiter_next(self.0, (self.1).0) })?;
+        }
+
+        match unsafe { logiter_state(self.0) } {
+            iter_state::ITER_ACTIVE => {
+                let entry_type = EntryType::from_raw(unsafe { logiter_type(self.0) });
+                let key = util::read_key(self.0, (self.1).0)?;
+                let value = util::read_value(self.0, (self.1).0)?;
+
+                Ok(Some(Entry {
+                    entry_type,
+                    key,
+                    value,
+                }))
+            }
+            _ => Ok(None),
+        }
+    }
+}
+
+#[allow(clippy::cast_sign_loss)]
+fn tell(iter: *mut logiter, reader: *mut logreader) -> error::Result<LogPosition> {
+    let mut offset = 0;
+    let mut entry_index = 0;
+
+    util::handle(unsafe { logiter_tell(iter, reader, &mut offset, &mut entry_index) })?;
+
+    Ok(LogPosition {
+        offset,
+        entry_index: entry_index as u32,
+    })
+}
+
+impl<'a> Iterator for Entries<'a> {
+    type Item = error::Result<Entry>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.try_next().transpose()
+    }
+}
+
+impl<'a> Drop for Entries<'a> {
+    fn drop(&mut self) {
+        unsafe { logiter_close(&mut self.0) }
+    }
+}
+
+unsafe impl<'a> Send for Entries<'a> {}
+
+impl<'a> Keys<'a> {
+    /// # Safety
+    ///
+    /// `raw` must be an iterator created for `reader` that is not owned by anything else, and
+    /// `hash` must be an open hash reader for `reader`.
+    pub unsafe fn from_raw(
+        raw: *mut logiter,
+        reader: &'a Reader,
+        hash: Option<*mut hashreader>,
+    ) -> Keys<'a> {
+        Keys(raw, reader.clone(), hash, marker::PhantomData)
+    }
+
+    /// # Safety
+    ///
+    /// Like `from_raw`, but the iterator owns a handle to `reader` and so can outlive the
+    /// original.
+    pub unsafe fn from_raw_owned(
+        raw: *mut logiter,
+        reader: Reader,
+        hash: Option<*mut hashreader>,
+    ) -> Keys<'static> {
+        Keys(raw, reader, hash, marker::PhantomData)
+    }
+
+    pub fn as_raw(&self) -> *mut logiter {
+        self.0
+    }
+
+    #[allow(clippy::cast_possible_wrap, clippy::cast_sign_loss)]
+    pub fn skip(&mut self, count: u32) -> error::Result<()> {
+        util::handle(unsafe { logiter_skip(self.0, (self.1).0, count as os::raw::c_int) })
+    }
+
+    pub fn position(&mut self) -> error::Result<LogPosition> {
+        tell(self.0, (self.1).0)
+    }
+
+    fn try_next(&mut self) -> error::Result<Option<bytes::BytesMut>> {
+        if let Some(hash) = self.2 {
+            util::handle(unsafe { logiter_hashnext(self.0, hash) })?;
+        } else {
+            util::handle(unsafe { logiter_next(self.0, (self.1).0) })?;
+        }
+
+        match unsafe { logiter_state(self.0) } {
+            iter_state::ITER_ACTIVE => {
+                let key = util::read_key(self.0, (self.1).0)?;
+
+                Ok(Some(key))
+            }
+            _ => Ok(None),
+        }
+    }
+}
+
+impl<'a> Iterator for Keys<'a> {
+    type Item = error::Result<bytes::BytesMut>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.try_next().transpose()
+    }
+}
+
+impl<'a> Drop for Keys<'a> {
+    fn drop(&mut self) {
+        unsafe { logiter_close(&mut self.0) }
+    }
+}
+
+unsafe impl<'a> Send for Keys<'a> {}
+
+impl<'a> Values<'a> {
+    /// # Safety
+    ///
+    /// `raw` must be an iterator created for `reader` that is not owned by anything else, and
+    /// `hash` must be an open hash reader for `reader`.
+    pub unsafe fn from_raw(
+        raw: *mut logiter,
+        reader: &'a Reader,
+        hash: Option<*mut hashreader>,
+    ) -> Values<'a> {
+        Values(raw, reader.clone(), hash, marker::PhantomData)
+    }
+
+    /// # Safety
+    ///
+    /// Like `from_raw`, but the iterator owns a handle to `reader` and so can outlive the
+    /// original.
+    pub unsafe fn from_raw_owned(
+        raw: *mut logiter,
+        reader: Reader,
+        hash: Option<*mut hashreader>,
+    ) -> Values<'static> {
+        Values(raw, reader, hash, marker::PhantomData)
+    }
+
+    pub fn as_raw(&self) -> *mut logiter {
+        self.0
+    }
+
+    #[allow(clippy::cast_possible_wrap)]
+    pub fn skip(&mut self, count: u32) -> error::Result<()> {
+        util::handle(unsafe { logiter_skip(self.0, (self.1).0, count as os::raw::c_int) })
+    }
+
+    pub fn position(&mut self) -> error::Result<LogPosition> {
+        tell(self.0, (self.1).0)
+    }
+
+    fn try_next(&mut self) -> error::Result<Option<bytes::BytesMut>> {
+        if let Some(hash) = self.2 {
+            util::handle(unsafe { logiter_hashnext(self.0, hash) })?;
+        } else {
+            util::handle(unsafe { logiter_next(self.0, (self.1).0) })?;
+        }
+
+        match unsafe { logiter_state(self.0) } {
+            iter_state::ITER_ACTIVE => {
+                let value = util::read_value(self.0, (self.1).0)?;
+
+                Ok(Some(value))
+            }
+            _ => Ok(None),
+        }
+    }
+}
+
+impl<'a> Iterator for Values<'a> {
+    type Item = error::Result<bytes::BytesMut>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.try_next().transpose()
+    }
+}
+
+impl<'a> Drop for Values<'a> {
+    fn drop(&mut self) {
+        unsafe { logiter_close(&mut self.0) }
+    }
+}
+
+unsafe impl<'a> Send for Values<'a> {}
+
+impl<'a> BorrowedEntries<'a> {
+    /// # Safety
+    ///
+    /// `raw` must be an iterator created for `reader` that is not owned by anything else, and
+    /// `hash` must be an open hash reader for `reader`.
+    pub unsafe fn from_raw(
+        raw: *mut logiter,
+        reader: &'a Reader,
+        hash: Option<*mut hashreader>,
+    ) -> BorrowedEntries<'a> {
+        BorrowedEntries(raw, reader, hash)
+    }
+
+    pub fn as_raw(&self) -> *mut logiter {
+        self.0
+    }
+
+    #[allow(clippy::cast_possible_wrap)]
+    pub fn skip(&mut self, count: u32) -> error::Result<()> {
+        util::handle(unsafe { logiter_skip(self.0, (self.1).0, count as os::raw::c_int) })
+    }
+
+    pub fn position(&mut self) -> error::Result<LogPosition> {
+        tell(self.0, (self.1).0)
+    }
+
+    fn try_next(&mut self) -> error::Result<Option<BorrowedEntry<'a>>> {
+        if let Some(hash) = self.2 {
+            util::handle(unsafe { logiter_hashnext(self.0, hash) })?;
+        } else {
+            util::handle(unsafe { logiter_next(self.0, (self.1).0) })?;
+        }
+
+        match unsafe { logiter_state(self.0) } {
+            iter_state::ITER_ACTIVE => {
+                let entry_type = EntryType::from_raw(unsafe { logiter_type(self.0) });
+                let key = unsafe { util::borrow_key(self.0, (self.1).0)? };
+                let value = unsafe { util::borrow_value(self.0, (self.1).0)? };
+
+                Ok(Some(BorrowedEntry {
+                    entry_type,
+                    key,
+                    value,
+                }))
+            }
+            _ => Ok(None),
+        }
+    }
+}
+
+impl<'a> Iterator for BorrowedEntries<'a> {
+    type Item = error::Result<BorrowedEntry<'a>>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.try_next().transpose()
+    }
+}
+
+impl<'a> Drop for BorrowedEntries<'a> {
+    fn drop(&mut self) {
+        unsafe { logiter_close(&mut self.0) }
+    }
+}
+
+unsafe impl<'a> Send for BorrowedEntries<'a> {}
+
+impl<'a> StreamingEntries<'a> {
+    /// # Safety
+    ///
+    /// `raw` must be an iterator created for `reader` that is not owned by anything else, and
+    /// `hash` must be an open hash reader for `reader`.
+    pub unsafe fn from_raw(
+        raw: *mut logiter,
+        reader: &'a Reader,
+        hash: Option<*mut hashreader>,
+    ) -> StreamingEntries<'a> {
+        StreamingEntries(raw, reader, hash)
+    }
+
+    pub fn as_raw(&self) -> *mut logiter {
+        self.0
+    }
+
+    #[allow(clippy::cast_possible_wrap)]
+    pub fn skip(&mut self, count: u32) -> error::Result<()> {
+        util::handle(unsafe { logiter_skip(self.0, (self.1).0, count as os::raw::c_int) })
+    }
+
+    pub fn position(&mut self) -> error::Result<LogPosition> {
+        tell(self.0, (self.1).0)
+    }
+
+    pub fn next_entry(&mut self) -> error::Result<Option<StreamingEntry<'_>>> {
+        if let Some(hash) = self.2 {
+            util::handle(unsafe { logiter_hashnext(self.0, hash) })?;
+        } else {
+            util::handle(unsafe { logiter_next(self.0, (self.1).0) })?;
+        }
+
+        match unsafe { logiter_state(self.0) } {
+            iter_state::ITER_ACTIVE => {
+                let entry_type = EntryType::from_raw(unsafe { logiter_type(self.0) });
+                let key = util::read_key(self.0, (self.1).0)?;
+                let value = unsafe { ValueReader::new(self.0, self.1, false) };
+
+                Ok(Some(StreamingEntry {
+                    entry_type,
+                    key,
+                    value,
+                }))
+            }
+            _ => Ok(None),
+        }
+    }
+}
+
+impl<'a> Drop for StreamingEntries<'a> {
+    fn drop(&mut self) {
+        unsafe { logiter_close(&mut self.0) }
+    }
+}
+
+unsafe impl<'a> Send for StreamingEntries<'a> {}
+
+impl<'a> ValueReader<'a> {
+    // `iter` must be positioned at an active entry of `reader`; it is closed on drop if `owned`.
+    pub(crate) unsafe fn new(iter: *mut logiter, reader: &'a Reader, owned: bool) -> Self {
+        ValueReader {
+            iter,
+            reader: reader.0,
+            owned,
+            len: logiter_valuelen(iter),
+            chunk: ptr::null(),
+            chunk_len: 0,
+            chunk_pos: 0,
+            marker: marker::PhantomData,
+        }
+    }
+
+    pub fn len(&self) -> u64 {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+}
+
+impl<'a> io::Read for ValueReader<'a> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        use std::io::BufRead;
+
+        let available = self.fill_buf()?;
+        let len = available.len().min(buf.len());
+        buf[..len].copy_from_slice(&available[..len]);
+        self.consume(len);
+
+        Ok(len)
+    }
+}
+
+impl<'a> io::BufRead for ValueReader<'a> {
+    fn fill_buf(&mut self) -> io::Result<&[u8]> {
+        use std::convert::TryFrom;
+
+        if self.chunk_pos == self.chunk_len {
+            let mut data = ptr::null_mut();
+            let mut len = 0;
+
+            util::handle(unsafe {
+                logiter_valuechunk(self.iter, self.reader, u64::MAX, &mut data, &mut len)
+            })?;
+
+            self.chunk = data;
+            self.chunk_len = usize::try_from(len).unwrap();
+            self.chunk_pos = 0;
+        }
+
+        if self.chunk_len == 0 {
+            Ok(&[])
+        } else {
+            // The chunk stays valid until the iterator moves, which only happens through `self`
+            Ok(unsafe { &slice::from_raw_parts(self.chunk, self.chunk_len)[self.chunk_pos..] })
+        }
+    }
+
+    fn consume(&mut self, amt: usize) {
+        self.chunk_pos = (self.chunk_pos + amt).min(self.chunk_len);
+    }
+}
+
+impl<'a> Drop for ValueReader<'a> {
+    fn drop(&mut self) {
+        if self.owned {
+            unsafe { logiter_close(&mut self.iter) }
+        }
+    }
+}
+
+unsafe impl<'a> Send for ValueReader<'a> {}
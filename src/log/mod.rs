@@ -0,0 +1,141 @@
+use std::fmt;
+use std::mem;
+
+#[cfg(any(feature = "ffi", feature = "native"))]
+use crate::error;
+
+// The parts that are backed by the C library
+#[cfg(feature = "ffi")]
+mod ffi;
+
+#[cfg(feature = "ffi")]
+pub use self::ffi::*;
+
+// Dictionary logs store a u32 dictionary size after the 84 byte header, followed by the
+// dictionary, and the header size including the dictionary has to fit in a u32
+#[cfg(any(feature = "ffi", feature = "native"))]
+pub(crate) const DICTIONARY_OFFSET: u32 = 88;
+#[cfg(any(feature = "ffi", feature = "native"))]
+pub(crate) const MAX_DICTIONARY_SIZE: u32 = u32::MAX - DICTIONARY_OFFSET;
+
+#[derive(Clone, Copy, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
+pub enum CompressionType {
+    None,
+    Snappy,
+    // The level is only used when writing; it is not stored in the log, so readers report 0,
+    // which zstd treats as its default level.
+    Zstd { level: i32 },
+}
+
+#[derive(Clone, Copy, Debug, Eq, Ord, PartialEq, PartialOrd)]
+pub enum EntryType {
+    Put,
+    Delete,
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct LogHeader {
+    pub major_version: u32,
+    pub minor_version: u32,
+    pub file_identifier: u32,
+    pub num_puts: u64,
+    pub num_deletes: u64,
+    pub put_size: u64,
+    pub delete_size: u64,
+    pub data_end: u64,
+    pub max_key_len: u64,
+    pub max_value_len: u64,
+    pub compression_type: CompressionType,
+    pub compression_block_size: u32,
+    pub header_size: u32,
+    pub max_entries_per_block: u32,
+}
+
+#[derive(Clone, Copy, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
+pub struct LogPosition {
+    pub offset: u64,
+    pub entry_index: u32,
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct WriterSummary {
+    pub num_puts: u64,
+    pub num_deletes: u64,
+    pub put_size: u64,
+    pub delete_size: u64,
+    pub bytes_written: u64,
+    pub data_end: u64,
+}
+
+#[derive(Debug)]
+pub struct Entry {
+    pub entry_type: EntryType,
+    pub key: bytes::BytesMut,
+    pub value: bytes::BytesMut,
+}
+
+// The size of `dictionary` as stored in the log header
+#[cfg(any(feature = "ffi", feature = "native"))]
+pub(crate) fn dictionary_size(dictionary: &[u8]) -> error::Result<u32> {
+    match dictionary.len() {
+        size if size <= MAX_DICTIONARY_SIZE as usize => Ok(size as u32),
+        size => Err(error::Error::DictionaryTooLarge {
+            size: size as u64,
+            max: u64::from(MAX_DICTIONARY_SIZE),
+        }),
+    }
+}
+
+// Offsets that split `start..end` into about `count` ranges that can be read independently,
+// including the start and the end.  `next_range(position, min)` walks from `position`, which starts
+// a range, to the first offset at or after `min` that can start one, or `end` if there is none.
+#[cfg(any(feature = "native", feature = "rayon"))]
+pub(crate) fn split<F>(
+    start: u64,
+    end: u64,
+    count: u64,
+    mut next_range: F,
+) -> error::Result<Vec<u64>>
+where
+    F: FnMut(u64, u64) -> error::Result<u64>,
+{
+    let step = ((end - start) / count.max(1)).max(1);
+    let mut bounds = vec![start];
+    let mut position = start;
+
+    loop {
+        position = next_range(position, position + step)?;
+        if position >= end {
+            break;
+        }
+        bounds.push(position);
+    }
+
+    bounds.push(end);
+    Ok(bounds)
+}
+
+impl CompressionType {
+    // The zstd level to write with, if this is zstd
+    pub fn level(&self) -> Option<i32> {
+        match *self {
+            CompressionType::Zstd { level } => Some(level),
+            _ => None,
+        }
+    }
+
+    // Whether both use the same algorithm, whatever their zstd levels are
+    pub fn same_algorithm(&self, other: &Self) -> bool {
+        mem::discriminant(self) == mem::discriminant(other)
+    }
+}
+
+impl fmt::Display for CompressionType {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            CompressionType::None => f.write_str("none"),
+            CompressionType::Snappy => f.write_str("snappy"),
+            CompressionType::Zstd { .. } => f.write_str("zstd"),
+        }
+    }
+}
@@ -0,0 +1,113 @@
+pub fn hash_32(data: &[u8], seed: u32) -> u32 {
+    const C1: u32 = 0xcc9e_2d51;
+    const C2: u32 = 0x1b87_3593;
+
+    let mut h1 = seed;
+    let mut blocks = data.chunks_exact(4);
+
+    for block in &mut blocks {
+        let mut k1 = u32::from_le_bytes([block[0], block[1], block[2], block[3]]);
+
+        k1 = k1.wrapping_mul(C1).rotate_left(15).wrapping_mul(C2);
+
+        h1 ^= k1;
+        h1 = h1.rotate_left(13).wrapping_mul(5).wrapping_add(0xe654_6b64);
+    }
+
+    let tail = blocks.remainder();
+    if !tail.is_empty() {
+        let mut k1 = 0;
+        for (i, &byte) in tail.iter().enumerate() {
+            k1 ^= u32::from(byte) << (8 * i);
+        }
+
+        h1 ^= k1.wrapping_mul(C1).rotate_left(15).wrapping_mul(C2);
+    }
+
+    // The reference implementation takes the length as an int
+    h1 ^= data.len() as u32;
+
+    fmix32(h1)
+}
+
+pub fn hash_64(data: &[u8], seed: u32) -> u64 {
+    const C1: u64 = 0x87c3_7b91_1142_53d5;
+    const C2: u64 = 0x4cf5_ad43_2745_937f;
+
+    let mut h1 = u64::from(seed);
+    let mut h2 = u64::from(seed);
+    let mut blocks = data.chunks_exact(16);
+
+    for block in &mut blocks {
+        let mut k1 = read_u64(&block[..8]);
+        let mut k2 = read_u64(&block[8..]);
+
+        k1 = k1.wrapping_mul(C1).rotate_left(31).wrapping_mul(C2);
+        h1 ^= k1;
+        h1 = h1
+            .rotate_left(27)
+            .wrapping_add(h2)
+            .wrapping_mul(5)
+            .wrapping_add(0x52dc_e729);
+
+        k2 = k2.wrapping_mul(C2).rotate_left(33).wrapping_mul(C1);
+        h2 ^= k2;
+        h2 = h2
+            .rotate_left(31)
+            .wrapping_add(h1)
+            .wrapping_mul(5)
+            .wrapping_add(0x3849_5ab5);
+    }
+
+    let tail = blocks.remainder();
+    if tail.len() > 8 {
+        let mut k2 = 0;
+        for (i, &byte) in tail[8..].iter().enumerate() {
+            k2 ^= u64::from(byte) << (8 * i);
+        }
+        h2 ^= k2.wrapping_mul(C2).rotate_left(33).wrapping_mul(C1);
+    }
+    if !tail.is_empty() {
+        let mut k1 = 0;
+        for (i, &byte) in tail.iter().take(8).enumerate() {
+            k1 ^= u64::from(byte) << (8 * i);
+        }
+        h1 ^= k1.wrapping_mul(C1).rotate_left(31).wrapping_mul(C2);
+    }
+
+    // The reference implementation takes the length as an int
+    h1 ^= u64::from(data.len() as u32);
+    h2 ^= u64::from(data.len() as u32);
+
+    h1 = h1.wrapping_add(h2);
+    h2 = h2.wrapping_add(h1);
+
+    h1 = fmix64(h1);
+    h2 = fmix64(h2);
+
+    h1.wrapping_add(h2)
+}
+
+fn read_u64(data: &[u8]) -> u64 {
+    let mut buf = [0; 8];
+    buf.copy_from_slice(data);
+    u64::from_le_bytes(buf)
+}
+
+fn fmix32(mut h: u32) -> u32 {
+    h ^= h >> 16;
+    h = h.wrapping_mul(0x85eb_ca6b);
+    h ^= h >> 13;
+    h = h.wrapping_mul(0xc2b2_ae35);
+    h ^= h >> 16;
+    h
+}
+
+fn fmix64(mut k: u64) -> u64 {
+    k ^= k >> 33;
+    k = k.wrapping_mul(0xff51_afd7_ed55_8ccd);
+    k ^= k >> 33;
+    k = k.wrapping_mul(0xc4ce_b9fe_1a85_ec53);
+    k ^= k >> 33;
+    k
+}
@@ -0,0 +1,1254 @@
+use std::collections;
+use std::fs;
+use std::io;
+use std::io::{Read, Seek, Write};
+use std::mem;
+use std::panic;
+use std::path;
+use std::sync;
+use std::sync::mpsc;
+use std::thread;
+
+use crate::error;
+use crate::hash;
+use crate::log;
+use crate::native;
+
+pub(crate) const MAGIC_NUMBER: u32 = 0x9a11_318f;
+pub(crate) const MAJOR_VERSION: u32 = 1;
+pub(crate) const MINOR_VERSION: u32 = 1;
+pub(crate) const HEADER_SIZE: u32 = 112;
+
+// Log bytes per range when hashing on several threads
+const RANGE_SIZE: u64 = 16 << 20;
+// Minimum ranges per thread, so that small logs are still spread out
+const RANGES_PER_THREAD: u64 = 4;
+// Bytes of keys and entries that a hashing thread hands over at a time, at most
+const BATCH_SIZE: u64 = 4 << 20;
+const MIN_BATCH_SIZE: u64 = 4 << 10;
+// Entries between progress reports
+const PROGRESS_INTERVAL: u64 = 1 << 16;
+// Most spill files open at a time when building with a memory budget, well below the usual limit
+// of 1024 open files; partitions that don't fit the budget are split again in another pass
+const MAX_FAN_OUT: u64 = 256;
+// Size of the buffer for each file in an external build
+const MIN_BUFFER_SIZE: u64 = 4 << 10;
+const MAX_BUFFER_SIZE: u64 = 1 << 20;
+// Spill records are an entry type, hash, address and key length, followed by the key
+const RECORD_HEADER_SIZE: usize = 25;
+// Live entries of a partition are sorted as a wanted slot, address and hash
+const LIVE_ENTRY_SIZE: u64 = 24;
+
+// Clones share the same mmap
+#[derive(Clone, Debug)]
+pub struct Reader(
+    sync::Arc<memmap::Mmap>,
+    hash::HashHeader,
+    native::log::Reader,
+);
+
+pub struct Writer;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct WriterOptions {
+    pub capacity_factor: f64,
+    pub hash_type: Option<hash::Type>,
+    pub hash_seed: Option<u32>,
+    pub account_garbage: bool,
+    // Threads that read and hash the keys of the log; only inserting into the table is
+    // sequential, so the result is the same for any number of threads
+    pub threads: usize,
+    // Builds the index one range of hash slots at a time, spilling the keys to temporary files
+    // next to the index, instead of holding the whole slot table in memory.  The file buffers,
+    // the key batches of the threads and the slot table of each range are kept within this many
+    // bytes, and ranges that turn out too big for it are split again in another pass over their
+    // spill file.  Fails with `MemoryBudgetTooSmall` if the buffers don't fit, or the keys that
+    // want a single slot don't.  The log reader's mmap and decompression buffers aren't counted,
+    // and neither are the few entries that wrap around the end of the table.  The index is still
+    // a standard one, but its slots can be laid out differently.
+    pub memory_budget: Option<u64>,
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct Progress {
+    // Log entries indexed so far
+    pub entries: u64,
+    // Log offset of the last entry indexed, out of `data_end`
+    pub offset: u64,
+    pub data_end: u64,
+}
+
+// Keys from a range of the log, hashed by a worker thread
+#[derive(Default)]
+struct Batch {
+    keys: Vec<u8>,
+    // Entry type, hash, address and the end of the key in `keys`
+    entries: Vec<(log::EntryType, u64, u64, usize)>,
+}
+
+// A range of wanted slots in an external build, and the file its entries are spilled to
+struct Partition {
+    start: u64,
+    end: u64,
+    path: path::PathBuf,
+    records: u64,
+    puts: u64,
+}
+
+// Spill files that split a range of wanted slots into partitions
+struct Spills {
+    partitions: Vec<Partition>,
+    files: Vec<io::BufWriter<fs::File>>,
+    slots_per_partition: u64,
+}
+
+// Displacement and collision statistics, gathered one slot at a time
+#[derive(Default)]
+struct Stats {
+    max_displacement: u64,
+    total_displacement: u64,
+    num_collisions: u64,
+    prev_hash: Option<u64>,
+    first: Option<(u64, u64)>,
+    last: (u64, u64),
+}
+
+// Removes temporary files when dropped
+struct TempFiles(Vec<path::PathBuf>);
+
+struct Table {
+    data: Vec<u8>,
+    capacity: u64,
+    hash_size: usize,
+    address_size: usize,
+}
+
+pub fn parse_header(data: &[u8]) -> error::Result<hash::HashHeader> {
+    if data.len() < HEADER_SIZE as usize {
+        return Err(error::Error::HashTooSmall);
+    }
+
+    let mut pos = 0;
+
+    if native::read_u32(data, &mut pos) != MAGIC_NUMBER {
+        return Err(error::Error::WrongHashMagicNumber);
+    }
+
+    let major_version = native::read_u32(data, &mut pos);
+    if major_version != MAJOR_VERSION {
+        return Err(error::Error::WrongHashMajorVersion);
+    }
+
+    let minor_version = native::read_u32(data, &mut pos);
+    if minor_version > MINOR_VERSION {
+        return Err(error::Error::UnsupportedHashMinorVersion);
+    }
+
+    let file_identifier = native::read_u32(data, &mut pos);
+    let hash_seed = native::read_u32(data, &mut pos);
+    let data_end = native::read_u64(data, &mut pos);
+    let max_key_len = native::read_u64(data, &mut pos);
+    let max_value_len = native::read_u64(data, &mut pos);
+    let num_puts = native::read_u64(data, &mut pos);
+    let garbage_size = native::read_u64(data, &mut pos);
+    let num_entries = native::read_u64(data, &mut pos);
+    let address_size = native::read_u32(data, &mut pos);
+    let hash_type = match native::read_u32(data, &mut pos) {
+        4 => hash::Type::Murmur3_32,
+        8 => hash::Type::Murmur3_64,
+        _ => return Err(error::Error::HashHeaderCorrupt),
+    };
+    let hash_capacity = native::read_u64(data, &mut pos);
+    let max_displacement = native::read_u64(data, &mut pos);
+    let entry_block_bits = native::read_u32(data, &mut pos);
+    let num_collisions = native::read_u64(data, &mut pos);
+    let total_displacement = native::read_u64(data, &mut pos);
+
+    if (address_size != 4 && address_size != 8)
+        || entry_block_bits >= 32
+        || num_entries > num_puts
+        || max_displacement > num_entries
+        || num_collisions > num_entries
+    {
+        return Err(error::Error::HashHeaderCorrupt);
+    }
+
+    Ok(hash::HashHeader {
+        major_version,
+        minor_version,
+        file_identifier,
+        hash_seed,
+        header_size: HEADER_SIZE,
+        data_end,
+        max_key_len,
+        max_value_len,
+        num_puts,
+        garbage_size,
+        num_entries,
+        address_size,
+        hash_type,
+        hash_capacity,
+        max_displacement,
+        total_displacement,
+        num_collisions,
+        entry_block_bits,
+    })
+}
+
+pub fn write_header<W>(header: &hash::HashHeader, mut out: W) -> error::Result<()>
+where
+    W: io::Write,
+{
+    let mut buf = Vec::with_capacity(HEADER_SIZE as usize);
+    buf.extend_from_slice(&MAGIC_NUMBER.to_le_bytes());
+    buf.extend_from_slice(&MAJOR_VERSION.to_le_bytes());
+    buf.extend_from_slice(&MINOR_VERSION.to_le_bytes());
+    buf.extend_from_slice(&header.file_identifier.to_le_bytes());
+    buf.extend_from_slice(&header.hash_seed.to_le_bytes());
+    buf.extend_from_slice(&header.data_end.to_le_bytes());
+    buf.extend_from_slice(&header.max_key_len.to_le_bytes());
+    buf.extend_from_slice(&header.max_value_len.to_le_bytes());
+    buf.extend_from_slice(&header.num_puts.to_le_bytes());
+    buf.extend_from_slice(&header.garbage_size.to_le_bytes());
+    buf.extend_from_slice(&header.num_entries.to_le_bytes());
+    buf.extend_from_slice(&header.address_size.to_le_bytes());
+    buf.extend_from_slice(&hash_size(header.hash_type).to_le_bytes());
+    buf.extend_from_slice(&header.hash_capacity.to_le_bytes());
+    buf.extend_from_slice(&header.max_displacement.to_le_bytes());
+    buf.extend_from_slice(&header.entry_block_bits.to_le_bytes());
+    buf.extend_from_slice(&header.num_collisions.to_le_bytes());
+    buf.extend_from_slice(&header.total_displacement.to_le_bytes());
+
+    out.write_all(&buf).map_err(error::Error::IO)
+}
+
+pub(crate) fn hash_size(hash_type: hash::Type) -> u32 {
+    match hash_type {
+        hash::Type::Murmur3_32 => 4,
+        hash::Type::Murmur3_64 => 8,
+    }
+}
+
+impl Reader {
+    pub fn open<P1, P2>(hash_path: P1, log_path: P2) -> error::Result<Self>
+    where
+        P1: AsRef<path::Path>,
+        P2: AsRef<path::Path>,
+    {
+        let file = fs::File::open(hash_path).map_err(error::Error::IO)?;
+        let mmap = unsafe { memmap::Mmap::map(&file) }.map_err(error::Error::IO)?;
+        let header = parse_header(&mmap)?;
+        let log_reader = native::log::Reader::open(log_path)?;
+        let log_header = log_reader.header();
+
+        if header.file_identifier != log_header.file_identifier {
+            return Err(error::Error::FileIdentifierMismatch);
+        }
+
+        if header.data_end > log_header.data_end
+            || header.max_key_len > log_header.max_key_len
+            || header.max_value_len > log_header.max_value_len
+        {
+            return Err(error::Error::HashHeaderCorrupt);
+        }
+
+        let slot_size = u64::from(hash_size(header.hash_type) + header.address_size);
+        let table_size = header
+            .hash_capacity
+            .checked_mul(slot_size)
+            .and_then(|size| size.checked_add(u64::from(header.header_size)));
+        match table_size {
+            Some(size) if size <= mmap.len() as u64 => {}
+            _ => return Err(error::Error::HashTooSmall),
+        }
+
+        Ok(Reader(sync::Arc::new(mmap), header, log_reader))
+    }
+
+    pub fn log_reader(&self) -> &native::log::Reader {
+        &self.2
+    }
+
+    pub fn header(&self) -> hash::HashHeader {
+        self.1
+    }
+
+    pub fn get(&self, key: &[u8]) -> error::Result<Option<bytes::BytesMut>> {
+        let hash = self.hash(key);
+        let mask = (1u64 << self.1.entry_block_bits) - 1;
+        let mut result = None;
+
+        self.probe(hash, |hash2, address| {
+            if hash2 != hash {
+                return Ok(false);
+            }
+
+            result = self.2.lookup(
+                address >> self.1.entry_block_bits,
+                (address & mask) as u32,
+                key,
+            )?;
+            Ok(result.is_some())
+        })?;
+
+        Ok(result)
+    }
+
+    pub fn entries(&self) -> error::Result<native::log::Entries<'_>> {
+        Ok(native::log::Entries::hashed(self))
+    }
+
+    pub fn keys(&self) -> error::Result<native::log::Keys<'_>> {
+        Ok(native::log::Keys::hashed(self))
+    }
+
+    pub fn values(&self) -> error::Result<native::log::Values<'_>> {
+        Ok(native::log::Values::hashed(self))
+    }
+
+    pub fn num_entries(&self) -> u64 {
+        self.1.num_entries
+    }
+
+    pub fn num_collisions(&self) -> u64 {
+        self.1.num_collisions
+    }
+
+    pub(crate) fn contains_address(&self, key: &[u8], address: u64) -> bool {
+        self.probe(self.hash(key), |_, address2| Ok(address2 == address))
+            .unwrap_or(false)
+    }
+
+    fn hash(&self, key: &[u8]) -> u64 {
+        hash::hash_key(self.1.hash_type, self.1.hash_seed, key)
+    }
+
+    fn slot(&self, slot: u64) -> (u64, u64) {
+        let hash_size = hash_size(self.1.hash_type) as usize;
+        let slot_size = hash_size + self.1.address_size as usize;
+        let mut pos = self.1.header_size as usize + slot as usize * slot_size;
+
+        let hash = match hash_size {
+            4 => u64::from(native::read_u32(&self.0, &mut pos)),
+            _ => native::read_u64(&self.0, &mut pos),
+        };
+        let address = match self.1.address_size {
+            4 => u64::from(native::read_u32(&self.0, &mut pos)),
+            _ => native::read_u64(&self.0, &mut pos),
+        };
+
+        (hash, address)
+    }
+
+    // Robin hood probing: a key can't be further from its wanted slot than the entries we pass
+    fn probe<F>(&self, hash: u64, mut matches: F) -> error::Result<bool>
+    where
+        F: FnMut(u64, u64) -> error::Result<bool>,
+    {
+        let capacity = self.1.hash_capacity;
+        if capacity == 0 {
+            return Ok(false);
+        }
+
+        let mut slot = hash % capacity;
+        let mut displacement = 0;
+
+        while displacement < capacity {
+            let (hash2, address) = self.slot(slot);
+            if address == 0 {
+                return Ok(false);
+            }
+
+            if matches(hash2, address)? {
+                return Ok(true);
+            }
+
+            let other_displacement = (capacity + slot - hash2 % capacity) % capacity;
+            if displacement > other_displacement {
+                return Ok(false);
+            }
+
+            displacement += 1;
+            slot += 1;
+            if slot >= capacity {
+                slot = 0;
+            }
+        }
+
+        Ok(false)
+    }
+}
+
+impl Default for WriterOptions {
+    fn default() -> Self {
+        WriterOptions {
+            capacity_factor: 1.3,
+            hash_type: None,
+            hash_seed: None,
+            account_garbage: true,
+            threads: 1,
+            memory_budget: None,
+        }
+    }
+}
+
+impl Writer {
+    pub fn write<P1, P2>(
+        hash_path: P1,
+        log_path: P2,
+        options: WriterOptions,
+    ) -> error::Result<hash::HashHeader>
+    where
+        P1: AsRef<path::Path>,
+        P2: AsRef<path::Path>,
+    {
+        Self::write_with_progress(hash_path, log_path, options, |_| {})
+    }
+
+    // Like `write`, but calls `progress` every so often while indexing, and once at the end
+    pub fn write_with_progress<P1, P2, F>(
+        hash_path: P1,
+        log_path: P2,
+        options: WriterOptions,
+        progress: F,
+    ) -> error::Result<hash::HashHeader>
+    where
+        P1: AsRef<path::Path>,
+        P2: AsRef<path::Path>,
+        F: FnMut(Progress),
+    {
+        let factor = options.capacity_factor;
+        if !(factor.is_finite() && factor >= 1.0) {
+            return Err(error::Error::InvalidCapacityFactor { factor });
+        }
+
+        let log_reader = native::log::Reader::open(log_path)?;
+        let log_header = log_reader.header();
+
+        let hash_capacity = 1 | (log_header.num_puts as f64 * factor) as u64;
+        let entry_block_bits = 32 - log_header.max_entries_per_block.leading_zeros();
+        let address_size = if log_header.data_end < 1 << (32 - entry_block_bits) {
+            4
+        } else {
+            8
+        };
+        let hash_type = options.hash_type.unwrap_or(if hash_capacity >= 1 << 23 {
+            hash::Type::Murmur3_64
+        } else {
+            hash::Type::Murmur3_32
+        });
+        let hash_seed = options.hash_seed.unwrap_or_else(native::random_u32);
+
+        let mut header = hash::HashHeader {
+            major_version: MAJOR_VERSION,
+            minor_version: MINOR_VERSION,
+            file_identifier: log_header.file_identifier,
+            hash_seed,
+            header_size: HEADER_SIZE,
+            data_end: log_header.data_end,
+            max_key_len: log_header.max_key_len,
+            max_value_len: log_header.max_value_len,
+            num_puts: log_header.num_puts,
+            garbage_size: 0,
+            num_entries: 0,
+            address_size,
+            hash_type,
+            hash_capacity,
+            max_displacement: 0,
+            total_displacement: 0,
+            num_collisions: 0,
+            entry_block_bits,
+        };
+        let hash_path = hash_path.as_ref();
+        if let Some(memory_budget) = options.memory_budget {
+            write_external(
+                hash_path,
+                &log_reader,
+                &mut header,
+                &options,
+                memory_budget,
+                progress,
+            )?;
+            return Ok(header);
+        }
+
+        let mut table = Table::new(&header);
+        scan_hashed(
+            &log_reader,
+            header,
+            options.threads,
+            BATCH_SIZE,
+            progress,
+            |entry_type, hash, key, address| {
+                let garbage =
+                    table.apply(&mut header, &log_reader, entry_type, hash, key, address)?;
+                if options.account_garbage {
+                    header.garbage_size += garbage;
+                }
+                Ok(())
+            },
+        )?;
+
+        table.calculate_displacement(&mut header);
+
+        // Remove the old file first, so that readers that still have it mapped aren't affected
+        match fs::remove_file(hash_path) {
+            Ok(()) => {}
+            Err(ref e) if e.kind() == io::ErrorKind::NotFound => {}
+            Err(e) => return Err(error::Error::IO(e)),
+        }
+
+        let mut file = io::BufWriter::new(fs::File::create(hash_path).map_err(error::Error::IO)?);
+        write_header(&header, &mut file)?;
+        file.write_all(&table.data).map_err(error::Error::IO)?;
+        file.flush().map_err(error::Error::IO)?;
+
+        Ok(header)
+    }
+}
+
+// Passes the hashed entries of the log to `f` in log order, and reports progress along the way
+fn scan_hashed<P, F>(
+    log_reader: &native::log::Reader,
+    header: hash::HashHeader,
+    threads: usize,
+    batch_size: u64,
+    mut progress: P,
+    mut f: F,
+) -> error::Result<()>
+where
+    P: FnMut(Progress),
+    F: FnMut(log::EntryType, u64, &[u8], u64) -> error::Result<()>,
+{
+    let entry_block_bits = header.entry_block_bits;
+    let mut entries = 0;
+
+    let mut g = |entry_type, hash, key: &[u8], address| {
+        f(entry_type, hash, key, address)?;
+
+        entries += 1;
+        if entries % PROGRESS_INTERVAL == 0 {
+            progress(Progress {
+                entries,
+                offset: address >> entry_block_bits,
+                data_end: header.data_end,
+            });
+        }
+        Ok(())
+    };
+
+    if threads > 1 {
+        scan_parallel(
+            log_reader,
+            threads,
+            batch_size,
+            |key| hash::hash_key(header.hash_type, header.hash_seed, key),
+            entry_block_bits,
+            g,
+        )?;
+    } else {
+        log_reader.scan(|entry_type, key, position| {
+            let address = (position.offset << entry_block_bits) | u64::from(position.entry_index);
+            g(
+                entry_type,
+                hash::hash_key(header.hash_type, header.hash_seed, key),
+                key,
+                address,
+            )
+        })?;
+    }
+
+    progress(Progress {
+        entries,
+        offset: header.data_end,
+        data_end: header.data_end,
+    });
+
+    Ok(())
+}
+
+// Reads and hashes ranges of the log on `threads` threads, and passes the entries to `f` in log
+// order.  The workers hand over their keys in batches of about `batch_size` bytes, and each one
+// stops once it has a batch queued that `f` hasn't got to yet, so that at most two batches per
+// thread and the one being passed to `f` are held at a time.
+fn scan_parallel<H, F>(
+    log_reader: &native::log::Reader,
+    threads: usize,
+    batch_size: u64,
+    hash: H,
+    entry_block_bits: u32,
+    mut f: F,
+) -> error::Result<()>
+where
+    H: Fn(&[u8]) -> u64 + Sync,
+    F: FnMut(log::EntryType, u64, &[u8], u64) -> error::Result<()>,
+{
+    let log_header = log_reader.header();
+    let len = log_header.data_end - u64::from(log_header.header_size);
+    let count = (threads as u64 * RANGES_PER_THREAD).max(len / RANGE_SIZE);
+    let bounds = log_reader.split(count)?;
+    let ranges = bounds.windows(2).map(|w| (w[0], w[1])).collect::<Vec<_>>();
+    let max_key_len = log_header.max_key_len;
+
+    let hash_range = |start, end, batches: mpsc::SyncSender<Batch>| -> error::Result<()> {
+        let mut batch = Batch::with_capacity(batch_size, max_key_len);
+        log_reader.scan_range(start, end, |entry_type, key, position| {
+            if batch.is_full(key.len()) {
+                batches
+                    .send(mem::take(&mut batch))
+                    .map_err(|_| error::Error::IO(io::ErrorKind::BrokenPipe.into()))?;
+                batch = Batch::with_capacity(batch_size, max_key_len);
+            }
+
+            let address = (position.offset << entry_block_bits) | u64::from(position.entry_index);
+            batch.keys.extend_from_slice(key);
+            batch
+                .entries
+                .push((entry_type, hash(key), address, batch.keys.len()));
+            Ok(())
+        })?;
+        batches
+            .send(batch)
+            .map_err(|_| error::Error::IO(io::ErrorKind::BrokenPipe.into()))
+    };
+    let hash_range = &hash_range;
+
+    let mut apply = |batch: Batch| -> error::Result<()> {
+        let mut key_start = 0;
+        for (entry_type, hash, address, key_end) in batch.entries {
+            f(entry_type, hash, &batch.keys[key_start..key_end], address)?;
+            key_start = key_end;
+        }
+        Ok(())
+    };
+
+    thread::scope(|scope| {
+        let spawn = |&(start, end): &(u64, u64)| {
+            let (sender, receiver) = mpsc::sync_channel(1);
+            let worker = scope.spawn(move || hash_range(start, end, sender));
+            (worker, receiver)
+        };
+
+        let mut ranges = ranges.iter();
+        let mut workers = ranges
+            .by_ref()
+            .take(threads)
+            .map(spawn)
+            .collect::<collections::VecDeque<_>>();
+        let mut result = Ok(());
+
+        while let Some((worker, receiver)) = workers.pop_front() {
+            if result.is_ok() {
+                result = receiver.iter().try_for_each(&mut apply);
+            }
+            // Workers that are still sending stop once their batches can't be received any more
+            drop(receiver);
+
+            let worker_result = worker.join().unwrap_or_else(|e| panic::resume_unwind(e));
+            if result.is_ok() {
+                result = worker_result;
+                workers.extend(ranges.next().map(spawn));
+            }
+        }
+
+        result
+    })
+}
+
+// Builds the index by partitioning the slot table into ranges of wanted slots.  The entries are
+// spilled to a file per partition first, and partitions that are too big to resolve within the
+// budget are split up with another pass over their file.  Each partition is then resolved on its
+// own, since all entries for a key end up in the same one, and its live entries are laid out in
+// order of their wanted slots, which is what robin hood probing would give too.
+fn write_external<F>(
+    hash_path: &path::Path,
+    log_reader: &native::log::Reader,
+    header: &mut hash::HashHeader,
+    options: &WriterOptions,
+    budget: u64,
+    progress: F,
+) -> error::Result<()>
+where
+    F: FnMut(Progress),
+{
+    let capacity = header.hash_capacity;
+    let hash_size = hash_size(header.hash_type) as usize;
+    let address_size = header.address_size as usize;
+    let slot_size = hash_size + address_size;
+    let max_key_len = header.max_key_len;
+    let too_small = |needed| error::Error::MemoryBudgetTooSmall { budget, needed };
+
+    // Resolving a partition takes its share of the slot table and its live entries, next to a
+    // buffer for its spill file and one for the index, and a key.  Splitting one takes the same
+    // buffers, and at least two spill files.
+    let resolve_memory = |puts: u64| {
+        (1 | (puts as f64 * options.capacity_factor) as u64) * slot_size as u64
+            + puts * LIVE_ENTRY_SIZE
+    };
+    let buffer_size = (budget / 16).clamp(MIN_BUFFER_SIZE, MAX_BUFFER_SIZE);
+    let resolve_overhead = 2 * buffer_size + max_key_len;
+    if budget < resolve_overhead + 2 * MIN_BUFFER_SIZE {
+        return Err(too_small(resolve_overhead + 2 * MIN_BUFFER_SIZE));
+    }
+    let resolve_budget = budget - resolve_overhead;
+
+    // While the log is scanned on several threads, each one holds up to two batches of keys, and
+    // one more is being spilled
+    let threads = options.threads as u64;
+    let (batch_size, scan_memory) = if threads > 1 {
+        let batches = 2 * threads + 1;
+        let batch_size = (budget / 4 / batches).min(BATCH_SIZE);
+        if batch_size < MIN_BATCH_SIZE {
+            return Err(too_small(4 * batches * (MIN_BATCH_SIZE + max_key_len)));
+        }
+        (batch_size, batches * (batch_size + max_key_len))
+    } else {
+        (BATCH_SIZE, 0)
+    };
+    let spill_memory = budget.saturating_sub(scan_memory);
+    let fan_out = resolve_memory(header.num_puts)
+        .div_ceil(resolve_budget)
+        .clamp(1, MAX_FAN_OUT)
+        .min(spill_memory / MIN_BUFFER_SIZE);
+    if fan_out == 0 {
+        return Err(too_small(scan_memory + MIN_BUFFER_SIZE));
+    }
+
+    let mut temp_files = TempFiles(Vec::new());
+    let mut spills = Spills::create(
+        &mut temp_files,
+        hash_path,
+        (0, capacity),
+        fan_out,
+        spill_memory / fan_out,
+    )?;
+    scan_hashed(
+        log_reader,
+        *header,
+        options.threads,
+        batch_size,
+        progress,
+        |entry_type, hash, key, address| {
+            spills.push(hash % capacity, entry_type, hash, key, address)
+        },
+    )?;
+    let mut pending = spills.finish()?;
+    pending.reverse();
+
+    let out_path = temp_files.path(hash_path, ".tmp");
+    let file = fs::OpenOptions::new()
+        .read(true)
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .open(&out_path)
+        .map_err(error::Error::IO)?;
+    let mut out = io::BufWriter::with_capacity(buffer_size as usize, file);
+    // The header is written again at the end, once the statistics are known
+    write_header(header, &mut out)?;
+
+    let zeros = vec![0; slot_size];
+    let mut slot_buf = vec![0; slot_size];
+    let mut key = Vec::new();
+    let mut written = 0;
+    // The next free slot
+    let mut cursor = 0;
+    // Entries that run past the end of the table, and wrap around to its start
+    let mut wrapped = Vec::new();
+
+    while let Some(partition) = pending.pop() {
+        let mut spill = io::BufReader::with_capacity(
+            buffer_size as usize,
+            fs::File::open(&partition.path).map_err(error::Error::IO)?,
+        );
+
+        let needed = resolve_memory(partition.puts);
+        if needed > resolve_budget {
+            // Not even splitting helps when all of the keys want the same slot
+            if partition.end - partition.start < 2 {
+                return Err(too_small(resolve_overhead + needed));
+            }
+
+            let fan_out = needed
+                .div_ceil(resolve_budget)
+                .clamp(2, MAX_FAN_OUT)
+                .min(resolve_budget / MIN_BUFFER_SIZE);
+            let mut spills = Spills::create(
+                &mut temp_files,
+                hash_path,
+                (partition.start, partition.end),
+                fan_out,
+                resolve_budget / fan_out,
+            )?;
+            for _ in 0..partition.records {
+                let (entry_type, hash, address) = read_record(&mut spill, &mut key)?;
+                spills.push(hash % capacity, entry_type, hash, &key, address)?;
+            }
+            drop(spill);
+            fs::remove_file(&partition.path).map_err(error::Error::IO)?;
+            pending.extend(spills.finish()?.into_iter().rev());
+            continue;
+        }
+
+        let mut local = hash::HashHeader {
+            hash_capacity: 1 | (partition.puts as f64 * options.capacity_factor) as u64,
+            num_entries: 0,
+            ..*header
+        };
+        let mut table = Table::new(&local);
+        for _ in 0..partition.records {
+            let (entry_type, hash, address) = read_record(&mut spill, &mut key)?;
+            let garbage = table.apply(&mut local, log_reader, entry_type, hash, &key, address)?;
+            if options.account_garbage {
+                header.garbage_size += garbage;
+            }
+        }
+        drop(spill);
+        fs::remove_file(&partition.path).map_err(error::Error::IO)?;
+        header.num_entries += local.num_entries;
+
+        let mut live = Vec::with_capacity(local.num_entries as usize);
+        live.extend(
+            (0..table.capacity)
+                .map(|slot| table.get(slot))
+                .filter(|&(_, address)| address != 0)
+                .map(|(hash, address)| (hash % capacity, address, hash)),
+        );
+        drop(table);
+        live.sort_unstable();
+
+        for (wanted, address, hash) in live {
+            let slot = wanted.max(cursor);
+            cursor = slot + 1;
+            if slot >= capacity {
+                wrapped.push((hash, address));
+                continue;
+            }
+
+            for _ in written..slot {
+                out.write_all(&zeros).map_err(error::Error::IO)?;
+            }
+            encode_slot(&mut slot_buf, hash_size, address_size, hash, address);
+            out.write_all(&slot_buf).map_err(error::Error::IO)?;
+            written = slot + 1;
+        }
+    }
+    for _ in written..capacity {
+        out.write_all(&zeros).map_err(error::Error::IO)?;
+    }
+    let mut out = out
+        .into_inner()
+        .map_err(|e| error::Error::IO(e.into_error()))?;
+
+    // Wrapped entries want slots before all others in the table, so they go first, and push the
+    // entries at the start of the table ahead of them until there is room
+    let mut pending = collections::VecDeque::from(wrapped);
+    let mut slot = 0;
+    while let Some(&next) = pending.front() {
+        let pos = u64::from(HEADER_SIZE) + slot * slot_size as u64;
+        out.seek(io::SeekFrom::Start(pos))
+            .map_err(error::Error::IO)?;
+        out.read_exact(&mut slot_buf).map_err(error::Error::IO)?;
+        let existing = decode_slot(&slot_buf, hash_size, address_size);
+        if existing.1 != 0 {
+            pending.push_back(existing);
+        }
+
+        pending.pop_front();
+        encode_slot(&mut slot_buf, hash_size, address_size, next.0, next.1);
+        out.seek(io::SeekFrom::Start(pos))
+            .map_err(error::Error::IO)?;
+        out.write_all(&slot_buf).map_err(error::Error::IO)?;
+        slot += 1;
+    }
+
+    out.seek(io::SeekFrom::Start(u64::from(HEADER_SIZE)))
+        .map_err(error::Error::IO)?;
+    let mut reader = io::BufReader::with_capacity(buffer_size as usize, &mut out);
+    let mut stats = Stats::default();
+    for slot in 0..capacity {
+        reader.read_exact(&mut slot_buf).map_err(error::Error::IO)?;
+        let (hash, address) = decode_slot(&slot_buf, hash_size, address_size);
+        stats.add(capacity, slot, hash, address);
+    }
+    drop(reader);
+    stats.finish(header);
+
+    out.seek(io::SeekFrom::Start(0)).map_err(error::Error::IO)?;
+    write_header(header, &mut out)?;
+    drop(out);
+
+    fs::rename(&out_path, hash_path).map_err(error::Error::IO)?;
+
+    Ok(())
+}
+
+// Reads a spill record, and its key into `key`
+fn read_record<R>(spill: &mut R, key: &mut Vec<u8>) -> error::Result<(log::EntryType, u64, u64)>
+where
+    R: io::Read,
+{
+    let mut record = [0; RECORD_HEADER_SIZE];
+    spill.read_exact(&mut record).map_err(error::Error::IO)?;
+    let entry_type = match record[0] {
+        0 => log::EntryType::Put,
+        _ => log::EntryType::Delete,
+    };
+    let mut pos = 1;
+    let hash = native::read_u64(&record, &mut pos);
+    let address = native::read_u64(&record, &mut pos);
+    let key_len = native::read_u64(&record, &mut pos);
+    key.resize(key_len as usize, 0);
+    spill.read_exact(key).map_err(error::Error::IO)?;
+
+    Ok((entry_type, hash, address))
+}
+
+fn decode_slot(data: &[u8], hash_size: usize, address_size: usize) -> (u64, u64) {
+    let mut pos = 0;
+    let hash = match hash_size {
+        4 => u64::from(native::read_u32(data, &mut pos)),
+        _ => native::read_u64(data, &mut pos),
+    };
+    let address = match address_size {
+        4 => u64::from(native::read_u32(data, &mut pos)),
+        _ => native::read_u64(data, &mut pos),
+    };
+    (hash, address)
+}
+
+fn encode_slot(data: &mut [u8], hash_size: usize, address_size: usize, hash: u64, address: u64) {
+    let (hash_buf, rest) = data.split_at_mut(hash_size);
+    hash_buf.copy_from_slice(&hash.to_le_bytes()[..hash_size]);
+    rest[..address_size].copy_from_slice(&address.to_le_bytes()[..address_size]);
+}
+
+fn entry_size(key_len: u64, value_len: u64) -> u64 {
+    key_len + value_len + native::vlq_len(key_len + 1) + native::vlq_len(value_len)
+}
+
+impl Table {
+    fn new(header: &hash::HashHeader) -> Self {
+        let hash_size = hash_size(header.hash_type) as usize;
+        let address_size = header.address_size as usize;
+        let slot_size = hash_size + address_size;
+
+        Table {
+            data: vec![0; header.hash_capacity as usize * slot_size],
+            capacity: header.hash_capacity,
+            hash_size,
+            address_size,
+        }
+    }
+
+    fn get(&self, slot: u64) -> (u64, u64) {
+        let pos = slot as usize * (self.hash_size + self.address_size);
+        decode_slot(&self.data[pos..], self.hash_size, self.address_size)
+    }
+
+    fn set(&mut self, slot: u64, hash: u64, address: u64) {
+        let pos = slot as usize * (self.hash_size + self.address_size);
+        encode_slot(
+            &mut self.data[pos..],
+            self.hash_size,
+            self.address_size,
+            hash,
+            address,
+        );
+    }
+
+    fn displacement(&self, slot: u64, hash: u64) -> u64 {
+        (self.capacity + slot - hash % self.capacity) % self.capacity
+    }
+
+    fn decode(header: &hash::HashHeader, address: u64) -> (u64, u32) {
+        let mask = (1u64 << header.entry_block_bits) - 1;
+        (address >> header.entry_block_bits, (address & mask) as u32)
+    }
+
+    // Returns the garbage that the entry makes: the entry it replaces or deletes, and the delete
+    // itself
+    fn apply(
+        &mut self,
+        header: &mut hash::HashHeader,
+        log_reader: &native::log::Reader,
+        entry_type: log::EntryType,
+        hash: u64,
+        key: &[u8],
+        address: u64,
+    ) -> error::Result<u64> {
+        match entry_type {
+            log::EntryType::Put => self.put(header, log_reader, hash, key, address),
+            log::EntryType::Delete => {
+                let delete_size = 1 + native::vlq_len(key.len() as u64) + key.len() as u64;
+                Ok(delete_size + self.delete(header, log_reader, hash, key)?)
+            }
+        }
+    }
+
+    // Returns the size of the entry that got replaced, if any
+    fn put(
+        &mut self,
+        header: &mut hash::HashHeader,
+        log_reader: &native::log::Reader,
+        mut hash: u64,
+        key: &[u8],
+        mut address: u64,
+    ) -> error::Result<u64> {
+        let mut slot = hash % self.capacity;
+        let mut displacement = 0;
+        let mut might_be_collision = true;
+
+        loop {
+            let (hash2, address2) = self.get(slot);
+            if address2 == 0 {
+                self.set(slot, hash, address);
+                header.num_entries += 1;
+                return Ok(0);
+            }
+
+            if might_be_collision && hash == hash2 {
+                let (offset, entry_index) = Self::decode(header, address2);
+                match log_reader.key_at(offset, entry_index)? {
+                    (log::EntryType::Put, key2, value_len2) => {
+                        if key2 == key {
+                            self.set(slot, hash, address);
+                            return Ok(entry_size(key2.len() as u64, value_len2));
+                        }
+                    }
+                    _ => return Err(error::Error::Internal),
+                }
+            }
+
+            let other_displacement = self.displacement(slot, hash2);
+            if displacement > other_displacement {
+                // Steal the slot, and move the other one
+                self.set(slot, hash, address);
+                hash = hash2;
+                address = address2;
+                displacement = other_displacement;
+                might_be_collision = false;
+            }
+
+            displacement += 1;
+            slot += 1;
+            if slot >= self.capacity {
+                slot = 0;
+            }
+        }
+    }
+
+    // Returns the size of the entry that got deleted, if any
+    fn delete(
+        &mut self,
+        header: &mut hash::HashHeader,
+        log_reader: &native::log::Reader,
+        hash: u64,
+        key: &[u8],
+    ) -> error::Result<u64> {
+        let mut slot = hash % self.capacity;
+        let mut displacement = 0;
+
+        while displacement < self.capacity {
+            let (hash2, address2) = self.get(slot);
+            if address2 == 0 {
+                return Ok(0);
+            }
+
+            if hash == hash2 {
+                let (offset, entry_index) = Self::decode(header, address2);
+                match log_reader.key_at(offset, entry_index)? {
+                    (log::EntryType::Put, key2, value_len2) => {
+                        if key2 == key {
+                            self.remove(slot);
+                            header.num_entries -= 1;
+                            return Ok(entry_size(key2.len() as u64, value_len2));
+                        }
+                    }
+                    _ => return Err(error::Error::Internal),
+                }
+            }
+
+            if displacement > self.displacement(slot, hash2) {
+                return Ok(0);
+            }
+
+            displacement += 1;
+            slot += 1;
+            if slot >= self.capacity {
+                slot = 0;
+            }
+        }
+
+        Ok(0)
+    }
+
+    // Backward shift deletion: pull displaced entries one step closer to their wanted slot
+    fn remove(&mut self, mut slot: u64) {
+        loop {
+            let next_slot = (slot + 1) % self.capacity;
+            let (hash, address) = self.get(next_slot);
+            if address == 0 || hash % self.capacity == next_slot {
+                break;
+            }
+
+            self.set(slot, hash, address);
+            slot = next_slot;
+        }
+
+        self.set(slot, 0, 0);
+    }
+
+    fn calculate_displacement(&self, header: &mut hash::HashHeader) {
+        let mut stats = Stats::default();
+        for slot in 0..self.capacity {
+            let (hash, address) = self.get(slot);
+            stats.add(self.capacity, slot, hash, address);
+        }
+        stats.finish(header);
+    }
+}
+
+impl Batch {
+    // Takes up to `size` bytes, and more only by the length of a key
+    fn with_capacity(size: u64, max_key_len: u64) -> Self {
+        let entry_size = mem::size_of::<(log::EntryType, u64, u64, usize)>() as u64;
+        Batch {
+            keys: Vec::with_capacity((size / 2 + max_key_len) as usize),
+            entries: Vec::with_capacity((size / 2 / entry_size).max(1) as usize),
+        }
+    }
+
+    fn is_full(&self, key_len: usize) -> bool {
+        !self.entries.is_empty()
+            && (self.entries.len() == self.entries.capacity()
+                || self.keys.len() + key_len > self.keys.capacity())
+    }
+}
+
+impl Spills {
+    // Splits the slots from `start` to `end` into up to `fan_out` partitions, and buffers up to
+    // `memory` bytes of spill records in total
+    fn create(
+        temp_files: &mut TempFiles,
+        hash_path: &path::Path,
+        (start, end): (u64, u64),
+        fan_out: u64,
+        memory: u64,
+    ) -> error::Result<Self> {
+        let slots_per_partition = (end - start).div_ceil(fan_out);
+        let buffer_size = (memory / fan_out).min(MAX_BUFFER_SIZE) as usize;
+        let mut spills = Spills {
+            partitions: Vec::new(),
+            files: Vec::new(),
+            slots_per_partition,
+        };
+
+        let mut partition_start = start;
+        while partition_start < end {
+            let suffix = format!(".{}.tmp", temp_files.0.len());
+            let path = temp_files.path(hash_path, &suffix);
+            let file = fs::File::create(&path).map_err(error::Error::IO)?;
+            spills
+                .files
+                .push(io::BufWriter::with_capacity(buffer_size, file));
+
+            let partition_end = (partition_start + slots_per_partition).min(end);
+            spills.partitions.push(Partition {
+                start: partition_start,
+                end: partition_end,
+                path,
+                records: 0,
+                puts: 0,
+            });
+            partition_start = partition_end;
+        }
+
+        Ok(spills)
+    }
+
+    fn push(
+        &mut self,
+        wanted: u64,
+        entry_type: log::EntryType,
+        hash: u64,
+        key: &[u8],
+        address: u64,
+    ) -> error::Result<()> {
+        let i = ((wanted - self.partitions[0].start) / self.slots_per_partition) as usize;
+        let partition = &mut self.partitions[i];
+        partition.records += 1;
+        if entry_type == log::EntryType::Put {
+            partition.puts += 1;
+        }
+
+        let mut record = [0; RECORD_HEADER_SIZE];
+        record[0] = entry_type as u8;
+        record[1..9].copy_from_slice(&hash.to_le_bytes());
+        record[9..17].copy_from_slice(&address.to_le_bytes());
+        record[17..25].copy_from_slice(&(key.len() as u64).to_le_bytes());
+
+        let file = &mut self.files[i];
+        file.write_all(&record).map_err(error::Error::IO)?;
+        file.write_all(key).map_err(error::Error::IO)
+    }
+
+    fn finish(self) -> error::Result<Vec<Partition>> {
+        for file in self.files {
+            file.into_inner()
+                .map_err(|e| error::Error::IO(e.into_error()))?;
+        }
+        Ok(self.partitions)
+    }
+}
+
+impl Stats {
+    fn add(&mut self, capacity: u64, slot: u64, hash: u64, address: u64) {
+        if self.prev_hash == Some(hash) {
+            self.num_collisions += 1;
+        }
+
+        if address != 0 {
+            let displacement = (capacity + slot - hash % capacity) % capacity;
+            self.total_displacement += displacement;
+            self.max_displacement = self.max_displacement.max(displacement);
+            self.prev_hash = Some(hash);
+        } else {
+            self.prev_hash = None;
+        }
+
+        self.first.get_or_insert((hash, address));
+        self.last = (hash, address);
+    }
+
+    fn finish(mut self, header: &mut hash::HashHeader) {
+        if let Some(first) = self.first {
+            let last = self.last;
+            if first.1 != 0 && last.1 != 0 && first.0 == last.0 {
+                self.num_collisions += 1;
+            }
+        }
+
+        header.max_displacement = self.max_displacement;
+        header.total_displacement = self.total_displacement;
+        header.num_collisions = self.num_collisions;
+    }
+}
+
+impl TempFiles {
+    fn path(&mut self, base: &path::Path, suffix: &str) -> path::PathBuf {
+        let mut name = base.as_os_str().to_owned();
+        name.push(suffix);
+        let path = path::PathBuf::from(name);
+        self.0.push(path.clone());
+        path
+    }
+}
+
+impl Drop for TempFiles {
+    fn drop(&mut self) {
+        for path in &self.0 {
+            let _ = fs::remove_file(path);
+        }
+    }
+}
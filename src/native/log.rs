@@ -0,0 +1,1209 @@
+use std::convert::TryFrom;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::io::{Read, Seek, Write};
+use std::path;
+use std::sync;
+
+use crate::error;
+use crate::log;
+use crate::native;
+
+pub(crate) const MAGIC_NUMBER: u32 = 0x49b3_9c95;
+pub(crate) const MAJOR_VERSION: u32 = 1;
+pub(crate) const MINOR_VERSION: u32 = 2;
+pub(crate) const MINOR_VERSION_ZSTD: u32 = 1;
+pub(crate) const MINOR_VERSION_DICTIONARY: u32 = 2;
+pub(crate) const HEADER_SIZE: u32 = 84;
+// Decompression buffers that a reader keeps around for lookups and iterators; more concurrent
+// users than this allocate their own
+const MAX_POOLED_SCRATCH: usize = 64;
+
+// Clones share the same mmap and scratch pool
+#[derive(Clone, Debug)]
+pub struct Reader(
+    sync::Arc<memmap::Mmap>,
+    log::LogHeader,
+    sync::Arc<sync::Mutex<Vec<Scratch>>>,
+);
+
+pub struct Writer {
+    file: io::BufWriter<fs::File>,
+    header: log::LogHeader,
+    initial: log::LogHeader,
+    offset: u64,
+    block: Vec<u8>,
+    compressed: Vec<u8>,
+    encoder: snap::raw::Encoder,
+    zstd: Option<zstd::bulk::Compressor<'static>>,
+    entry_count: u32,
+    flushed: bool,
+    closed: bool,
+    // Set when a write failed part way through, after which the log can't be trusted
+    poisoned: bool,
+}
+
+pub struct Entries<'a>(Iter<'a>);
+
+pub struct Keys<'a>(Iter<'a>);
+
+pub struct Values<'a>(Iter<'a>);
+
+// The block buffer and decompressors of an iterator, which are expensive to set up for every
+// lookup when the log has a dictionary
+#[derive(Default)]
+struct Scratch {
+    buf: Vec<u8>,
+    snappy: snap::raw::Decoder,
+    zstd: Option<zstd::bulk::Decompressor<'static>>,
+}
+
+struct Iter<'a> {
+    reader: &'a Reader,
+    hash: Option<&'a native::hash::Reader>,
+    scratch: Scratch,
+    block_position: u64,
+    next_block_position: u64,
+    block_offset: usize,
+    block_len: usize,
+    entry_count: u32,
+}
+
+pub fn parse_header(data: &[u8]) -> error::Result<log::LogHeader> {
+    if data.len() < HEADER_SIZE as usize {
+        return Err(error::Error::LogTooSmall);
+    }
+
+    let mut pos = 0;
+
+    if native::read_u32(data, &mut pos) != MAGIC_NUMBER {
+        return Err(error::Error::WrongLogMagicNumber);
+    }
+
+    let major_version = native::read_u32(data, &mut pos);
+    if major_version != MAJOR_VERSION {
+        return Err(error::Error::WrongLogMajorVersion);
+    }
+
+    let minor_version = native::read_u32(data, &mut pos);
+    if minor_version > MINOR_VERSION {
+        return Err(error::Error::UnsupportedLogMinorVersion);
+    }
+
+    let file_identifier = native::read_u32(data, &mut pos);
+    let num_puts = native::read_u64(data, &mut pos);
+    let num_deletes = native::read_u64(data, &mut pos);
+    let data_end = native::read_u64(data, &mut pos);
+    let max_key_len = native::read_u64(data, &mut pos);
+    let max_value_len = native::read_u64(data, &mut pos);
+    let delete_size = native::read_u64(data, &mut pos);
+    let compression_type = match native::read_u32(data, &mut pos) {
+        0 => log::CompressionType::None,
+        1 => log::CompressionType::Snappy,
+        2 => log::CompressionType::Zstd { level: 0 },
+        _ => return Err(error::Error::LogHeaderCorrupt),
+    };
+    let compression_block_size = native::read_u32(data, &mut pos);
+    let put_size = native::read_u64(data, &mut pos);
+    let max_entries_per_block = native::read_u32(data, &mut pos);
+
+    let header_size = if minor_version >= MINOR_VERSION_DICTIONARY {
+        if data.len() < log::DICTIONARY_OFFSET as usize {
+            return Err(error::Error::LogTooSmall);
+        }
+        if !compression_type.same_algorithm(&log::CompressionType::Zstd { level: 0 }) {
+            return Err(error::Error::LogHeaderCorrupt);
+        }
+        native::read_u32(data, &mut pos)
+            .checked_add(log::DICTIONARY_OFFSET)
+            .ok_or(error::Error::LogHeaderCorrupt)?
+    } else {
+        HEADER_SIZE
+    };
+
+    if data_end < u64::from(header_size) || num_puts > data_end || num_deletes > data_end {
+        return Err(error::Error::LogHeaderCorrupt);
+    }
+
+    Ok(log::LogHeader {
+        major_version,
+        minor_version,
+        file_identifier,
+        num_puts,
+        num_deletes,
+        put_size,
+        delete_size,
+        data_end,
+        max_key_len,
+        max_value_len,
+        compression_type,
+        compression_block_size,
+        header_size,
+        max_entries_per_block,
+    })
+}
+
+pub fn write_header<W>(header: &log::LogHeader, mut out: W) -> error::Result<()>
+where
+    W: io::Write,
+{
+    let compression_type: u32 = match header.compression_type {
+        log::CompressionType::None => 0,
+        log::CompressionType::Snappy => 1,
+        log::CompressionType::Zstd { .. } => 2,
+    };
+
+    let mut buf = Vec::with_capacity(HEADER_SIZE as usize);
+    buf.extend_from_slice(&MAGIC_NUMBER.to_le_bytes());
+    buf.extend_from_slice(&MAJOR_VERSION.to_le_bytes());
+    buf.extend_from_slice(&header.minor_version.to_le_bytes());
+    buf.extend_from_slice(&header.file_identifier.to_le_bytes());
+    buf.extend_from_slice(&header.num_puts.to_le_bytes());
+    buf.extend_from_slice(&header.num_deletes.to_le_bytes());
+    buf.extend_from_slice(&header.data_end.to_le_bytes());
+    buf.extend_from_slice(&header.max_key_len.to_le_bytes());
+    buf.extend_from_slice(&header.max_value_len.to_le_bytes());
+    buf.extend_from_slice(&header.delete_size.to_le_bytes());
+    buf.extend_from_slice(&compression_type.to_le_bytes());
+    buf.extend_from_slice(&header.compression_block_size.to_le_bytes());
+    buf.extend_from_slice(&header.put_size.to_le_bytes());
+    buf.extend_from_slice(&header.max_entries_per_block.to_le_bytes());
+
+    out.write_all(&buf).map_err(error::Error::IO)
+}
+
+// Reads the compressed size of the block at `position`, and returns where the block ends
+fn block_end(data: &[u8], pos: &mut usize, position: u64) -> error::Result<usize> {
+    usize::try_from(native::read_vlq(data, pos)?)
+        .ok()
+        .and_then(|size| pos.checked_add(size))
+        .ok_or(error::Error::CorruptBlock { offset: position })
+}
+
+impl Reader {
+    pub fn open<P>(path: P) -> error::Result<Self>
+    where
+        P: AsRef<path::Path>,
+    {
+        let file = fs::File::open(path).map_err(error::Error::IO)?;
+        let mmap = unsafe { memmap::Mmap::map(&file) }.map_err(error::Error::IO)?;
+        let header = parse_header(&mmap)?;
+
+        if header.data_end > mmap.len() as u64 {
+            return Err(error::Error::LogTooSmall);
+        }
+
+        if header.compression_type != log::CompressionType::None
+            && header.compression_block_size < 10
+        {
+            return Err(error::Error::InvalidCompressionBlockSize);
+        }
+
+        Ok(Reader(
+            sync::Arc::new(mmap),
+            header,
+            sync::Arc::new(sync::Mutex::new(Vec::new())),
+        ))
+    }
+
+    pub fn header(&self) -> log::LogHeader {
+        self.1
+    }
+
+    pub fn max_key_len(&self) -> u64 {
+        self.1.max_key_len
+    }
+
+    pub fn max_value_len(&self) -> u64 {
+        self.1.max_value_len
+    }
+
+    pub fn compression_block_size(&self) -> u32 {
+        self.1.compression_block_size
+    }
+
+    pub fn compression_type(&self) -> log::CompressionType {
+        self.1.compression_type
+    }
+
+    pub fn entries(&self) -> error::Result<Entries<'_>> {
+        Ok(Entries(Iter::new(self, None)))
+    }
+
+    pub fn keys(&self) -> error::Result<Keys<'_>> {
+        Ok(Keys(Iter::new(self, None)))
+    }
+
+    pub fn values(&self) -> error::Result<Values<'_>> {
+        Ok(Values(Iter::new(self, None)))
+    }
+
+    pub fn entries_from(&self, position: log::LogPosition) -> error::Result<Entries<'_>> {
+        Ok(Entries(Iter::at(self, position)?))
+    }
+
+    pub fn keys_from(&self, position: log::LogPosition) -> error::Result<Keys<'_>> {
+        Ok(Keys(Iter::at(self, position)?))
+    }
+
+    pub fn values_from(&self, position: log::LogPosition) -> error::Result<Values<'_>> {
+        Ok(Values(Iter::at(self, position)?))
+    }
+
+    // The shared compression dictionary of dictionary logs
+    pub(crate) fn dictionary(&self) -> Option<&[u8]> {
+        if self.1.minor_version >= MINOR_VERSION_DICTIONARY {
+            Some(&self.0[log::DICTIONARY_OFFSET as usize..self.1.header_size as usize])
+        } else {
+            None
+        }
+    }
+
+    pub(crate) fn data(&self) -> &[u8] {
+        &self.0[..self.1.data_end as usize]
+    }
+
+    pub(crate) fn lookup(
+        &self,
+        offset: u64,
+        entry_index: u32,
+        key: &[u8],
+    ) -> error::Result<Option<bytes::BytesMut>> {
+        let mut iter = self.iter_entry(offset, entry_index)?;
+
+        match iter.next_header()? {
+            Some((log::EntryType::Put, key_len, value_len)) => {
+                if key_len != key.len() as u64 {
+                    return Ok(None);
+                }
+
+                let mut actual_key = bytes::BytesMut::new();
+                iter.read_bytes(key_len, Some(&mut actual_key))?;
+                if actual_key != key {
+                    return Ok(None);
+                }
+
+                let mut value = bytes::BytesMut::new();
+                iter.read_bytes(value_len, Some(&mut value))?;
+                Ok(Some(value))
+            }
+            _ => Err(error::Error::Internal),
+        }
+    }
+
+    // Reads the key of the entry at the given address, along with its type and value length
+    pub(crate) fn key_at(
+        &self,
+        offset: u64,
+        entry_index: u32,
+    ) -> error::Result<(log::EntryType, bytes::BytesMut, u64)> {
+        let mut iter = self.iter_entry(offset, entry_index)?;
+
+        match iter.next_header()? {
+            Some((entry_type, key_len, value_len)) => {
+                let mut key = bytes::BytesMut::new();
+                iter.read_bytes(key_len, Some(&mut key))?;
+                Ok((entry_type, key, value_len))
+            }
+            None => Err(error::Error::Internal),
+        }
+    }
+
+    // Calls `f` with the type, key and position of every entry in the log
+    pub(crate) fn scan<F>(&self, f: F) -> error::Result<()>
+    where
+        F: FnMut(log::EntryType, &[u8], log::LogPosition) -> error::Result<()>,
+    {
+        self.scan_range(u64::from(self.1.header_size), self.1.data_end, f)
+    }
+
+    // Like `scan`, but only for the entries in `start..end`.  `start` must be a block boundary, or
+    // an entry boundary for uncompressed logs.
+    pub(crate) fn scan_range<F>(&self, start: u64, end: u64, mut f: F) -> error::Result<()>
+    where
+        F: FnMut(log::EntryType, &[u8], log::LogPosition) -> error::Result<()>,
+    {
+        let mut iter = Iter::new(self, None);
+        let mut key = bytes::BytesMut::new();
+        iter.next_block_position = start;
+
+        while iter.position().offset < end {
+            let (entry_type, key_len, value_len) = match iter.next_header()? {
+                Some(header) => header,
+                None => break,
+            };
+            let position = log::LogPosition {
+                offset: iter.block_position,
+                entry_index: iter.entry_count - 1,
+            };
+
+            key.clear();
+            iter.read_bytes(key_len, Some(&mut key))?;
+            f(entry_type, &key, position)?;
+            iter.read_bytes(value_len, None)?;
+        }
+
+        Ok(())
+    }
+
+    // Offsets that split the log into about `count` ranges for `scan_range`, including the start
+    // and the end
+    pub(crate) fn split(&self, count: u64) -> error::Result<Vec<u64>> {
+        let start = u64::from(self.1.header_size);
+        log::split(start, self.1.data_end, count, |position, min| {
+            self.next_range(position, min)
+        })
+    }
+
+    // Like `sparkey_logreader_next_range`: the first offset at or after `min` where a range can
+    // start, walking from `position`, which has to be one, or `data_end` if there is none
+    fn next_range(&self, mut position: u64, min: u64) -> error::Result<u64> {
+        let data = self.data();
+        let end = self.1.data_end;
+
+        if self.1.compression_type == log::CompressionType::None {
+            // Entry boundaries can only be found by walking the entries
+            while position < min && position < end {
+                let mut pos = usize::try_from(position).unwrap();
+                let a = native::read_vlq(data, &mut pos)?;
+                let b = native::read_vlq(data, &mut pos)?;
+                let len = if a == 0 {
+                    Some(b)
+                } else {
+                    (a - 1).checked_add(b)
+                };
+                position = len
+                    .and_then(|len| len.checked_add(pos as u64))
+                    .filter(|&position| position <= end)
+                    .ok_or(error::Error::UnexpectedEof)?;
+            }
+            return Ok(position.min(end));
+        }
+
+        // Entries can span several blocks, but the writer only fills a block up completely when
+        // the entry continues in the next one (or happens to end right there), so only blocks
+        // after a partially filled one can start a range
+        let block_size = self.1.compression_block_size as usize;
+        let mut starts_entry = true;
+        while position < end {
+            if position >= min && starts_entry {
+                return Ok(position);
+            }
+            let mut pos = usize::try_from(position).unwrap();
+            let block_end = block_end(data, &mut pos, position)?;
+            let compressed = data
+                .get(pos..block_end)
+                .ok_or(error::Error::UnexpectedEof)?;
+            let len = if self.1.compression_type == log::CompressionType::Snappy {
+                snap::raw::decompress_len(compressed).ok()
+            } else {
+                zstd::zstd_safe::get_frame_content_size(compressed)
+                    .ok()
+                    .flatten()
+                    .map(|len| len as usize)
+            };
+            starts_entry = len.is_some_and(|len| len < block_size);
+            position = block_end as u64;
+        }
+
+        Ok(end)
+    }
+
+    fn iter_entry(&self, offset: u64, entry_index: u32) -> error::Result<Iter<'_>> {
+        if offset < u64::from(self.1.header_size) || offset >= self.1.data_end {
+            return Err(error::Error::UnexpectedEof);
+        }
+
+        let mut iter = Iter::new(self, None);
+        iter.load_block(offset)?;
+        iter.skip(entry_index)?;
+
+        Ok(iter)
+    }
+
+    fn pool(&self) -> sync::MutexGuard<'_, Vec<Scratch>> {
+        match self.2.lock() {
+            Ok(pool) => pool,
+            Err(poisoned) => poisoned.into_inner(),
+        }
+    }
+}
+
+impl Writer {
+    pub fn create<P>(
+        path: P,
+        compression_type: log::CompressionType,
+        compression_block_size: u32,
+    ) -> error::Result<Self>
+    where
+        P: AsRef<path::Path>,
+    {
+        Self::create_inner(
+            path.as_ref(),
+            compression_type,
+            compression_block_size,
+            None,
+        )
+    }
+
+    pub fn create_with_dictionary<P>(
+        path: P,
+        compression_type: log::CompressionType,
+        compression_block_size: u32,
+        dictionary: &[u8],
+    ) -> error::Result<Self>
+    where
+        P: AsRef<path::Path>,
+    {
+        match compression_type {
+            log::CompressionType::Zstd { .. } => Self::create_inner(
+                path.as_ref(),
+                compression_type,
+                compression_block_size,
+                Some(dictionary),
+            ),
+            _ => Err(error::Error::InvalidCompressionType),
+        }
+    }
+
+    fn create_inner(
+        path: &path::Path,
+        compression_type: log::CompressionType,
+        compression_block_size: u32,
+        dictionary: Option<&[u8]>,
+    ) -> error::Result<Self> {
+        let header_size = match dictionary {
+            Some(dictionary) => log::dictionary_size(dictionary)? + log::DICTIONARY_OFFSET,
+            None => HEADER_SIZE,
+        };
+
+        let compression_block_size = match compression_type {
+            log::CompressionType::None => 0,
+            _ if compression_block_size < 10 => {
+                return Err(error::Error::InvalidCompressionBlockSize);
+            }
+            _ => compression_block_size,
+        };
+
+        // Remove the old file first, so that readers that still have it mapped aren't affected
+        match fs::remove_file(path) {
+            Ok(()) => {}
+            Err(ref e) if e.kind() == io::ErrorKind::NotFound => {}
+            Err(e) => return Err(error::Error::IO(e)),
+        }
+
+        let header = log::LogHeader {
+            major_version: MAJOR_VERSION,
+            // Only use newer versions when needed, so the rest stays readable by older readers
+            minor_version: match compression_type {
+                _ if dictionary.is_some() => MINOR_VERSION_DICTIONARY,
+                log::CompressionType::Zstd { .. } => MINOR_VERSION_ZSTD,
+                _ => 0,
+            },
+            file_identifier: native::random_u32(),
+            num_puts: 0,
+            num_deletes: 0,
+            put_size: 0,
+            delete_size: 0,
+            data_end: u64::from(header_size),
+            max_key_len: 0,
+            max_value_len: 0,
+            compression_type,
+            compression_block_size,
+            header_size,
+            max_entries_per_block: 0,
+        };
+
+        let mut file = fs::File::create(path).map_err(error::Error::IO)?;
+        write_header(&header, &mut file)?;
+        if let Some(dictionary) = dictionary {
+            file.write_all(&(dictionary.len() as u32).to_le_bytes())
+                .and_then(|()| file.write_all(dictionary))
+                .map_err(error::Error::IO)?;
+        }
+
+        Self::new(file, header, compression_type, dictionary)
+    }
+
+    pub fn append<P>(path: P) -> error::Result<Self>
+    where
+        P: AsRef<path::Path>,
+    {
+        let mut file = fs::OpenOptions::new()
+            .read(true)
+            .write(true)
+            .open(path)
+            .map_err(error::Error::IO)?;
+
+        let mut buf = Vec::with_capacity(log::DICTIONARY_OFFSET as usize);
+        (&mut file)
+            .take(u64::from(log::DICTIONARY_OFFSET))
+            .read_to_end(&mut buf)
+            .map_err(error::Error::IO)?;
+        let header = parse_header(&buf)?;
+
+        let dictionary = if header.minor_version >= MINOR_VERSION_DICTIONARY {
+            let mut dictionary = vec![0; (header.header_size - log::DICTIONARY_OFFSET) as usize];
+            file.read_exact(&mut dictionary).map_err(error::Error::IO)?;
+            Some(dictionary)
+        } else {
+            None
+        };
+
+        if header.compression_type != log::CompressionType::None
+            && header.compression_block_size < 10
+        {
+            return Err(error::Error::InvalidCompressionBlockSize);
+        }
+
+        file.seek(io::SeekFrom::Start(header.data_end))
+            .map_err(error::Error::IO)?;
+
+        Self::new(file, header, header.compression_type, dictionary.as_deref())
+    }
+
+    fn new(
+        file: fs::File,
+        header: log::LogHeader,
+        compression_type: log::CompressionType,
+        dictionary: Option<&[u8]>,
+    ) -> error::Result<Self> {
+        let zstd = match compression_type {
+            log::CompressionType::Zstd { level } => {
+                let zstd = match dictionary {
+                    Some(dictionary) => zstd::bulk::Compressor::with_dictionary(level, dictionary),
+                    None => zstd::bulk::Compressor::new(level),
+                };
+                Some(zstd.map_err(error::Error::IO)?)
+            }
+            _ => None,
+        };
+
+        Ok(Writer {
+            file: io::BufWriter::with_capacity(1024 * 1024, file),
+            header,
+            initial: header,
+            offset: header.data_end,
+            block: Vec::with_capacity(header.compression_block_size as usize),
+            compressed: Vec::new(),
+            encoder: snap::raw::Encoder::new(),
+            zstd,
+            entry_count: 0,
+            flushed: false,
+            closed: false,
+            poisoned: false,
+        })
+    }
+
+    // The header as it will be written on the next flush
+    pub fn header(&self) -> log::LogHeader {
+        log::LogHeader {
+            data_end: self.offset,
+            ..self.header
+        }
+    }
+
+    // Number of bytes written to the log so far, not counting a partially filled compression block
+    pub fn offset(&self) -> u64 {
+        self.offset
+    }
+
+    pub fn num_puts(&self) -> u64 {
+        self.header.num_puts
+    }
+
+    pub fn num_deletes(&self) -> u64 {
+        self.header.num_deletes
+    }
+
+    pub fn put(&mut self, key: &[u8], value: &[u8]) -> error::Result<()> {
+        self.put_reader(key, value, value.len() as u64)
+    }
+
+    // Streams exactly `len` bytes of value from `value`. If reading fails, the log is left with a
+    // partially written entry, so the writer refuses any further writes and should be discarded.
+    pub fn put_reader<R>(&mut self, key: &[u8], value: R, len: u64) -> error::Result<()>
+    where
+        R: io::Read,
+    {
+        let key_len = key.len() as u64;
+        let size = self.poison_on_error(|writer| writer.add(key_len + 1, len, key, value, len))?;
+
+        self.header.num_puts += 1;
+        self.header.put_size += size;
+        self.header.max_key_len = self.header.max_key_len.max(key_len);
+        self.header.max_value_len = self.header.max_value_len.max(len);
+
+        Ok(())
+    }
+
+    pub fn delete(&mut self, key: &[u8]) -> error::Result<()> {
+        let key_len = key.len() as u64;
+        let size = self.poison_on_error(|writer| writer.add(0, key_len, &[], key, key_len))?;
+
+        self.header.num_deletes += 1;
+        self.header.delete_size += size;
+
+        Ok(())
+    }
+
+    pub fn flush(&mut self) -> error::Result<()> {
+        self.poison_on_error(Self::flush_inner)
+    }
+
+    pub fn close(mut self) -> error::Result<log::WriterSummary> {
+        self.closed = true;
+        self.flush()?;
+
+        let initial = &self.initial;
+        let header = &self.header;
+        Ok(log::WriterSummary {
+            num_puts: header.num_puts - initial.num_puts,
+            num_deletes: header.num_deletes - initial.num_deletes,
+            put_size: header.put_size - initial.put_size,
+            delete_size: header.delete_size - initial.delete_size,
+            bytes_written: header.data_end - initial.data_end,
+            data_end: header.data_end,
+        })
+    }
+
+    fn poison_on_error<A, F>(&mut self, f: F) -> error::Result<A>
+    where
+        F: FnOnce(&mut Self) -> error::Result<A>,
+    {
+        if self.poisoned {
+            return Err(error::Error::LogWriterPoisoned);
+        }
+
+        let result = f(self);
+        self.poisoned = result.is_err();
+        result
+    }
+
+    fn flush_inner(&mut self) -> error::Result<()> {
+        if !self.block.is_empty() {
+            self.flush_block()?;
+        }
+
+        self.header.data_end = self.offset;
+
+        let header = self.header;
+        let offset = self.offset;
+        self.file.flush().map_err(error::Error::IO)?;
+        let file = self.file.get_mut();
+        file.seek(io::SeekFrom::Start(0))
+            .map_err(error::Error::IO)?;
+        write_header(&header, &mut *file)?;
+        file.seek(io::SeekFrom::Start(offset))
+            .map_err(error::Error::IO)?;
+
+        Ok(())
+    }
+
+    fn add<R>(&mut self, a: u64, b: u64, data1: &[u8], data2: R, len2: u64) -> error::Result<u64>
+    where
+        R: io::Read,
+    {
+        let mut buf_a = [0; 10];
+        let mut buf_b = [0; 10];
+        let len_a = native::write_vlq(&mut buf_a, a);
+        let len_b = native::write_vlq(&mut buf_b, b);
+        let header_len = (len_a + len_b) as u64;
+        let size = header_len + data1.len() as u64 + len2;
+
+        match self.header.compression_type {
+            log::CompressionType::None => {
+                self.write(&buf_a[..len_a])?;
+                self.write(&buf_b[..len_b])?;
+                self.write(data1)?;
+
+                let copied =
+                    io::copy(&mut data2.take(len2), &mut self.file).map_err(error::Error::IO)?;
+                self.offset += copied;
+                if copied != len2 {
+                    return Err(error::Error::UnexpectedEof);
+                }
+            }
+            _ => {
+                let block_size = u64::from(self.header.compression_block_size);
+                let remaining = block_size - self.block.len() as u64;
+                // Start a new block unless the entry wouldn't fit in one anyway
+                if remaining < header_len || (size <= block_size && size > remaining) {
+                    self.flush_block()?;
+                }
+
+                self.entry_count += 1;
+                self.flushed = false;
+                self.add_block(&buf_a[..len_a], len_a as u64)?;
+                self.add_block(&buf_b[..len_b], len_b as u64)?;
+                self.add_block(data1, data1.len() as u64)?;
+                self.add_block(data2, len2)?;
+
+                // Entries that span several blocks get their last block to themselves
+                if self.flushed && !self.block.is_empty() {
+                    self.flush_block()?;
+                }
+            }
+        }
+
+        Ok(size)
+    }
+
+    fn add_block<R>(&mut self, mut data: R, mut len: u64) -> error::Result<()>
+    where
+        R: io::Read,
+    {
+        loop {
+            let start = self.block.len();
+            let remaining = self.header.compression_block_size as usize - start;
+            let chunk = usize::try_from(len.min(remaining as u64)).unwrap();
+
+            self.block.resize(start + chunk, 0);
+            if let Err(e) = data.read_exact(&mut self.block[start..]) {
+                self.block.truncate(start);
+                return Err(match e.kind() {
+                    io::ErrorKind::UnexpectedEof => error::Error::UnexpectedEof,
+                    _ => error::Error::IO(e),
+                });
+            }
+            len -= chunk as u64;
+
+            if len == 0 {
+                return Ok(());
+            }
+            self.flush_block()?;
+        }
+    }
+
+    fn flush_block(&mut self) -> error::Result<()> {
+        self.flushed = true;
+        self.header.max_entries_per_block = self.header.max_entries_per_block.max(self.entry_count);
+        self.entry_count = 0;
+
+        let compressed_len = match self.zstd {
+            Some(ref mut zstd) => {
+                self.compressed
+                    .resize(zstd::zstd_safe::compress_bound(self.block.len()), 0);
+                zstd.compress_to_buffer(&self.block, &mut self.compressed[..])
+                    .map_err(error::Error::IO)?
+            }
+            None => {
+                self.compressed
+                    .resize(snap::raw::max_compress_len(self.block.len()), 0);
+                self.encoder
+                    .compress(&self.block, &mut self.compressed)
+                    .map_err(|_| error::Error::Internal)?
+            }
+        };
+
+        let mut buf = [0; 10];
+        let len = native::write_vlq(&mut buf, compressed_len as u64);
+        self.write(&buf[..len])?;
+        self.file
+            .write_all(&self.compressed[..compressed_len])
+            .map_err(error::Error::IO)?;
+        self.offset += compressed_len as u64;
+        self.block.clear();
+
+        Ok(())
+    }
+
+    fn write(&mut self, data: &[u8]) -> error::Result<()> {
+        self.file.write_all(data).map_err(error::Error::IO)?;
+        self.offset += data.len() as u64;
+        Ok(())
+    }
+}
+
+impl Drop for Writer {
+    fn drop(&mut self) {
+        // A poisoned log keeps the header of the last successful flush, which leaves out the
+        // partially written entry
+        if self.closed || self.poisoned {
+            return;
+        }
+
+        if let Err(e) = self.flush() {
+            ::log::error!("failed to close log writer: {}", e);
+        }
+    }
+}
+
+impl<'a> Iter<'a> {
+    fn new(reader: &'a Reader, hash: Option<&'a native::hash::Reader>) -> Self {
+        Iter {
+            reader,
+            hash,
+            scratch: reader.pool().pop().unwrap_or_default(),
+            block_position: 0,
+            next_block_position: u64::from(reader.1.header_size),
+            block_offset: 0,
+            block_len: 0,
+            entry_count: 0,
+        }
+    }
+
+    fn at(reader: &'a Reader, position: log::LogPosition) -> error::Result<Self> {
+        let header = &reader.1;
+        let mut iter = Iter::new(reader, None);
+
+        if position.offset < u64::from(header.header_size)
+            || position.offset > header.data_end
+            || (position.offset == header.data_end && position.entry_index != 0)
+        {
+            return Err(error::Error::InvalidLogPosition { position });
+        }
+
+        if position.offset == header.data_end {
+            iter.next_block_position = header.data_end;
+            return Ok(iter);
+        }
+
+        iter.load_block(position.offset)?;
+        iter.skip(position.entry_index)?;
+
+        Ok(iter)
+    }
+
+    fn data(&self) -> &[u8] {
+        match self.reader.1.compression_type {
+            log::CompressionType::None => {
+                &self.reader.data()[self.block_position as usize..][..self.block_len]
+            }
+            _ => &self.scratch.buf[..self.block_len],
+        }
+    }
+
+    fn load_block(&mut self, position: u64) -> error::Result<()> {
+        let data_end = self.reader.1.data_end;
+
+        match self.reader.1.compression_type {
+            log::CompressionType::None => {
+                self.next_block_position = data_end;
+                self.block_len = usize::try_from(data_end - position).unwrap();
+            }
+            compression_type => {
+                let data = self.reader.data();
+                let mut pos = usize::try_from(position).unwrap();
+                let block_end = block_end(data, &mut pos, position)?;
+                let compressed = data
+                    .get(pos..block_end)
+                    .ok_or(error::Error::UnexpectedEof)?;
+                let block_size = self.reader.1.compression_block_size as usize;
+                let corrupt = error::Error::CorruptBlock { offset: position };
+
+                let len = if compression_type == log::CompressionType::Snappy {
+                    let len = snap::raw::decompress_len(compressed).map_err(|_| corrupt)?;
+                    if len > block_size {
+                        return Err(error::Error::CorruptBlock { offset: position });
+                    }
+
+                    self.scratch.buf.resize(len, 0);
+                    self.scratch
+                        .snappy
+                        .decompress(compressed, &mut self.scratch.buf)
+                        .map_err(|_| error::Error::CorruptBlock { offset: position })?
+                } else {
+                    if self.scratch.zstd.is_none() {
+                        let zstd = match self.reader.dictionary() {
+                            Some(dictionary) => {
+                                zstd::bulk::Decompressor::with_dictionary(dictionary)
+                            }
+                            None => zstd::bulk::Decompressor::new(),
+                        };
+                        self.scratch.zstd = Some(zstd.map_err(error::Error::IO)?);
+                    }
+
+                    self.scratch.buf.resize(block_size, 0);
+                    self.scratch
+                        .zstd
+                        .as_mut()
+                        .unwrap()
+                        .decompress_to_buffer(compressed, &mut self.scratch.buf[..])
+                        .map_err(|_| corrupt)?
+                };
+
+                self.next_block_position = block_end as u64;
+                self.block_len = len;
+            }
+        }
+
+        self.block_position = position;
+        self.block_offset = 0;
+        self.entry_count = 0;
+
+        Ok(())
+    }
+
+    fn ensure_available(&mut self) -> error::Result<bool> {
+        if self.block_offset < self.block_len {
+            return Ok(true);
+        }
+
+        if self.next_block_position >= self.reader.1.data_end {
+            self.block_offset = 0;
+            self.block_len = 0;
+            return Ok(false);
+        }
+
+        self.load_block(self.next_block_position)?;
+
+        Ok(true)
+    }
+
+    fn read_bytes(
+        &mut self,
+        mut len: u64,
+        mut out: Option<&mut bytes::BytesMut>,
+    ) -> error::Result<()> {
+        if let Some(ref mut out) = out {
+            out.reserve(usize::try_from(len).unwrap());
+        }
+
+        while len > 0 {
+            if !self.ensure_available()? {
+                return Err(error::Error::UnexpectedEof);
+            }
+
+            let available = (self.block_len - self.block_offset) as u64;
+            let chunk = usize::try_from(len.min(available)).unwrap();
+
+            if let Some(ref mut out) = out {
+                let start = self.block_offset;
+                out.extend_from_slice(&self.data()[start..start + chunk]);
+            }
+
+            self.block_offset += chunk;
+            len -= chunk as u64;
+        }
+
+        Ok(())
+    }
+
+    fn next_header(&mut self) -> error::Result<Option<(log::EntryType, u64, u64)>> {
+        if !self.ensure_available()? {
+            return Ok(None);
+        }
+
+        if self.reader.1.compression_type == log::CompressionType::None {
+            self.block_position += self.block_offset as u64;
+            self.block_len -= self.block_offset;
+            self.block_offset = 0;
+            self.entry_count = 0;
+        }
+
+        self.entry_count += 1;
+
+        let mut pos = self.block_offset;
+        let a = native::read_vlq(self.data(), &mut pos)?;
+        let b = native::read_vlq(self.data(), &mut pos)?;
+        self.block_offset = pos;
+
+        if a == 0 {
+            Ok(Some((log::EntryType::Delete, b, 0)))
+        } else {
+            Ok(Some((log::EntryType::Put, a - 1, b)))
+        }
+    }
+
+    fn skip(&mut self, count: u32) -> error::Result<()> {
+        for _ in 0..count {
+            match self.next_header()? {
+                Some((_, key_len, value_len)) => {
+                    self.read_bytes(key_len, None)?;
+                    self.read_bytes(value_len, None)?;
+                }
+                None => break,
+            }
+        }
+
+        Ok(())
+    }
+
+    fn next_entry(
+        &mut self,
+        key: Option<&mut bytes::BytesMut>,
+        value: Option<&mut bytes::BytesMut>,
+    ) -> error::Result<Option<log::EntryType>> {
+        let hash = match self.hash {
+            Some(hash) => hash,
+            None => {
+                return match self.next_header()? {
+                    Some((entry_type, key_len, value_len)) => {
+                        self.read_bytes(key_len, key)?;
+                        self.read_bytes(value_len, value)?;
+                        Ok(Some(entry_type))
+                    }
+                    None => Ok(None),
+                };
+            }
+        };
+
+        // Only yield the puts that are still live in the hash index, like `logiter_hashnext`
+        let mut entry_key = bytes::BytesMut::new();
+        loop {
+            let (entry_type, key_len, value_len) = match self.next_header()? {
+                Some(header) => header,
+                None => return Ok(None),
+            };
+
+            if entry_type != log::EntryType::Put {
+                self.read_bytes(key_len, None)?;
+                continue;
+            }
+
+            let address = (self.block_position << hash.header().entry_block_bits)
+                | u64::from(self.entry_count - 1);
+
+            entry_key.clear();
+            self.read_bytes(key_len, Some(&mut entry_key))?;
+
+            if !hash.contains_address(&entry_key, address) {
+                self.read_bytes(value_len, None)?;
+                continue;
+            }
+
+            self.read_bytes(value_len, value)?;
+            if let Some(key) = key {
+                *key = entry_key;
+            }
+
+            return Ok(Some(entry_type));
+        }
+    }
+
+    fn position(&self) -> log::LogPosition {
+        if self.block_offset >= self.block_len {
+            log::LogPosition {
+                offset: self.next_block_position.min(self.reader.1.data_end),
+                entry_index: 0,
+            }
+        } else if self.reader.1.compression_type == log::CompressionType::None {
+            log::LogPosition {
+                offset: self.block_position + self.block_offset as u64,
+                entry_index: 0,
+            }
+        } else {
+            log::LogPosition {
+                offset: self.block_position,
+                entry_index: self.entry_count,
+            }
+        }
+    }
+}
+
+impl<'a> Drop for Iter<'a> {
+    fn drop(&mut self) {
+        let mut pool = self.reader.pool();
+        if pool.len() < MAX_POOLED_SCRATCH {
+            pool.push(std::mem::take(&mut self.scratch));
+        }
+    }
+}
+
+impl fmt::Debug for Scratch {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Scratch")
+            .field("buf", &self.buf.len())
+            .finish()
+    }
+}
+
+impl<'a> Entries<'a> {
+    pub(crate) fn hashed(hash: &'a native::hash::Reader) -> Self {
+        Entries(Iter::new(hash.log_reader(), Some(hash)))
+    }
+
+    pub fn skip(&mut self, count: u32) -> error::Result<()> {
+        self.0.skip(count)
+    }
+
+    pub fn position(&mut self) -> error::Result<log::LogPosition> {
+        Ok(self.0.position())
+    }
+
+    fn try_next(&mut self) -> error::Result<Option<log::Entry>> {
+        let mut key = bytes::BytesMut::new();
+        let mut value = bytes::BytesMut::new();
+
+        match self.0.next_entry(Some(&mut key), Some(&mut value))? {
+            Some(entry_type) => Ok(Some(log::Entry {
+                entry_type,
+                key,
+                value,
+            })),
+            None => Ok(None),
+        }
+    }
+}
+
+impl<'a> Iterator for Entries<'a> {
+    type Item = error::Result<log::Entry>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.try_next().transpose()
+    }
+}
+
+impl<'a> Keys<'a> {
+    pub(crate) fn hashed(hash: &'a native::hash::Reader) -> Self {
+        Keys(Iter::new(hash.log_reader(), Some(hash)))
+    }
+
+    pub fn skip(&mut self, count: u32) -> error::Result<()> {
+        self.0.skip(count)
+    }
+
+    pub fn position(&mut self) -> error::Result<log::LogPosition> {
+        Ok(self.0.position())
+    }
+
+    fn try_next(&mut self) -> error::Result<Option<bytes::BytesMut>> {
+        let mut key = bytes::BytesMut::new();
+
+        match self.0.next_entry(Some(&mut key), None)? {
+            Some(_) => Ok(Some(key)),
+            None => Ok(None),
+        }
+    }
+}
+
+impl<'a> Iterator for Keys<'a> {
+    type Item = error::Result<bytes::BytesMut>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.try_next().transpose()
+    }
+}
+
+impl<'a> Values<'a> {
+    pub(crate) fn hashed(hash: &'a native::hash::Reader) -> Self {
+        Values(Iter::new(hash.log_reader(), Some(hash)))
+    }
+
+    pub fn skip(&mut self, count: u32) -> error::Result<()> {
+        self.0.skip(count)
+    }
+
+    pub fn position(&mut self) -> error::Result<log::LogPosition> {
+        Ok(self.0.position())
+    }
+
+    fn try_next(&mut self) -> error::Result<Option<bytes::BytesMut>> {
+        let mut value = bytes::BytesMut::new();
+
+        match self.0.next_entry(None, Some(&mut value))? {
+            Some(_) => Ok(Some(value)),
+            None => Ok(None),
+        }
+    }
+}
+
+impl<'a> Iterator for Values<'a> {
+    type Item = error::Result<bytes::BytesMut>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.try_next().transpose()
+    }
+}
@@ -0,0 +1,65 @@
+pub mod hash;
+pub mod log;
+
+use std::collections::hash_map;
+use std::hash::{BuildHasher, Hasher};
+
+use crate::error;
+
+pub(crate) fn read_u32(data: &[u8], pos: &mut usize) -> u32 {
+    let mut buf = [0; 4];
+    buf.copy_from_slice(&data[*pos..*pos + 4]);
+    *pos += 4;
+    u32::from_le_bytes(buf)
+}
+
+pub(crate) fn read_u64(data: &[u8], pos: &mut usize) -> u64 {
+    let mut buf = [0; 8];
+    buf.copy_from_slice(&data[*pos..*pos + 8]);
+    *pos += 8;
+    u64::from_le_bytes(buf)
+}
+
+pub(crate) fn read_vlq(data: &[u8], pos: &mut usize) -> error::Result<u64> {
+    let mut result = 0;
+    let mut shift = 0;
+
+    loop {
+        let byte = *data.get(*pos).ok_or(error::Error::UnexpectedEof)?;
+        *pos += 1;
+
+        if shift >= 64 {
+            return Err(error::Error::UnexpectedEof);
+        }
+        result |= u64::from(byte & 0x7f) << shift;
+
+        if byte & 0x80 == 0 {
+            return Ok(result);
+        }
+        shift += 7;
+    }
+}
+
+pub(crate) fn vlq_len(mut value: u64) -> u64 {
+    let mut len = 1;
+    while value >= 0x80 {
+        value >>= 7;
+        len += 1;
+    }
+    len
+}
+
+pub(crate) fn write_vlq(buf: &mut [u8; 10], mut value: u64) -> usize {
+    let mut len = 0;
+    while value >= 0x80 {
+        buf[len] = (value & 0x7f) as u8 | 0x80;
+        value >>= 7;
+        len += 1;
+    }
+    buf[len] = value as u8;
+    len + 1
+}
+
+pub(crate) fn random_u32() -> u32 {
+    hash_map::RandomState::new().build_hasher().finish() as u32
+}
@@ -0,0 +1,236 @@
+use rayon::prelude::*;
+use sparkey_sys::*;
+
+use crate::error;
+use crate::hash;
+use crate::log;
+use crate::util;
+
+// Ranges per thread when splitting a log, so that threads that finish early can steal work
+const RANGES_PER_THREAD: u64 = 16;
+// Hash slots per partition when iterating in hash order
+const SLOTS_PER_PARTITION: u64 = 1 << 16;
+// Keys per task in `par_multi_get`, so that small batches aren't spread too thin
+const MIN_KEYS_PER_TASK: usize = 256;
+
+// Iterates the entries of a log from a block boundary up to the next range
+struct Range {
+    entries: Option<log::Entries<'static>>,
+    error: Option<error::Error>,
+    end: u64,
+}
+
+// Iterates the entries that a partition of the hash table slots point to
+struct Slots {
+    reader: hash::Reader,
+    entries: Option<log::Entries<'static>>,
+    error: Option<error::Error>,
+    addresses: std::vec::IntoIter<u64>,
+    // The block of the last entry read and the index of the entry after it
+    current: Option<(u64, u64)>,
+}
+
+impl log::Reader {
+    // Splits the log into ranges at block boundaries (or entry boundaries for uncompressed
+    // logs) and iterates them in parallel.  Entries within a range are in log order.
+    pub fn par_entries(
+        &self,
+    ) -> error::Result<impl ParallelIterator<Item = error::Result<log::Entry>>> {
+        let bounds = self.split(rayon::current_num_threads() as u64 * RANGES_PER_THREAD)?;
+        let reader = self.clone();
+
+        Ok(bounds
+            .windows(2)
+            .map(|w| (w[0], w[1]))
+            .collect::<Vec<_>>()
+            .into_par_iter()
+            .flat_map_iter(move |(start, end)| Range::new(&reader, start, end)))
+    }
+
+    // Offsets that split the log into about `count` ranges, including the start and the end
+    fn split(&self, count: u64) -> error::Result<Vec<u64>> {
+        let header = self.header();
+        let start = u64::from(header.header_size);
+        log::split(start, header.data_end, count, |position, min| {
+            let mut next = position;
+            util::handle(unsafe { logreader_next_range(self.as_raw(), position, min, &mut next) })?;
+            Ok(next)
+        })
+    }
+}
+
+impl hash::Reader {
+    // Partitions the slot table and iterates the partitions in parallel.  Only live entries
+    // are returned, in log order within each partition.
+    pub fn par_entries(&self) -> impl ParallelIterator<Item = error::Result<log::Entry>> {
+        let capacity = self.header().hash_capacity;
+        let partitions = capacity.div_ceil(SLOTS_PER_PARTITION);
+        let reader = self.clone();
+
+        (0..partitions).into_par_iter().flat_map_iter(move |i| {
+            let start = i * SLOTS_PER_PARTITION;
+            let end = (start + SLOTS_PER_PARTITION).min(capacity);
+            Slots::new(reader.clone(), start, end)
+        })
+    }
+
+    // Like `multi_get`, but splits the keys over the rayon pool.  Each task looks up keys that are
+    // next to each other in the given order.
+    pub fn par_multi_get<K>(
+        &self,
+        keys: &[K],
+        order: hash::ProbeOrder,
+    ) -> error::Result<Vec<Option<bytes::BytesMut>>>
+    where
+        K: AsRef<[u8]> + Sync,
+    {
+        let order = self.probe_order(keys, order);
+        let tasks = rayon::current_num_threads() as u64 * RANGES_PER_THREAD;
+        let chunk_size = (order.len() / tasks as usize).max(MIN_KEYS_PER_TASK);
+
+        let chunks = order
+            .par_chunks(chunk_size)
+            .map(|chunk| {
+                let mut lookup = self.lookup()?;
+                chunk
+                    .iter()
+                    .map(|&(i, hash)| {
+                        let value = lookup.get_hashed(keys[i].as_ref(), hash)?;
+                        Ok((i, value.map(bytes::BytesMut::from)))
+                    })
+                    .collect::<error::Result<Vec<_>>>()
+            })
+            .collect::<error::Result<Vec<_>>>()?;
+
+        let mut result = vec![None; keys.len()];
+        for (i, value) in chunks.into_iter().flatten() {
+            result[i] = value;
+        }
+
+        Ok(result)
+    }
+}
+
+impl Range {
+    fn new(reader: &log::Reader, start: u64, end: u64) -> Self {
+        let position = log::LogPosition {
+            offset: start,
+            entry_index: 0,
+        };
+
+        match reader.clone().into_entries_from(position) {
+            Ok(entries) => Range {
+                entries: Some(entries),
+                error: None,
+                end,
+            },
+            Err(e) => Range {
+                entries: None,
+                error: Some(e),
+                end,
+            },
+        }
+    }
+
+    fn try_next(&mut self) -> error::Result<Option<log::Entry>> {
+        let entries = match self.entries {
+            Some(ref mut entries) => entries,
+            None => return Ok(None),
+        };
+
+        if entries.position()?.offset >= self.end {
+            return Ok(None);
+        }
+
+        entries.next().transpose()
+    }
+}
+
+impl Iterator for Range {
+    type Item = error::Result<log::Entry>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if let Some(e) = self.error.take() {
+            return Some(Err(e));
+        }
+
+        let result = self.try_next();
+        if !matches!(result, Ok(Some(_))) {
+            self.entries = None;
+        }
+        result.transpose()
+    }
+}
+
+impl Slots {
+    fn new(reader: hash::Reader, start: u64, end: u64) -> Self {
+        let mut addresses = (start..end)
+            .map(|slot| unsafe { hash_address(reader.as_raw(), slot) })
+            .filter(|&address| address != 0)
+            .collect::<Vec<_>>();
+        // Reading in log order lets consecutive entries of a block share its decompression
+        addresses.sort_unstable();
+
+        let (entries, error) = match reader.log_reader().clone().into_entries() {
+            Ok(entries) => (Some(entries), None),
+            Err(e) => (None, Some(e)),
+        };
+
+        Slots {
+            reader,
+            entries,
+            error,
+            addresses: addresses.into_iter(),
+            current: None,
+        }
+    }
+
+    #[allow(clippy::cast_possible_wrap)]
+    fn try_next(&mut self) -> error::Result<Option<log::Entry>> {
+        let entries = match self.entries {
+            Some(ref mut entries) => entries,
+            None => return Ok(None),
+        };
+        let address = match self.addresses.next() {
+            Some(address) => address,
+            None => return Ok(None),
+        };
+
+        let bits = self.reader.header().entry_block_bits;
+        let block = address >> bits;
+        let index = address & ((1 << bits) - 1);
+
+        match self.current {
+            Some((current, next)) if current == block && index >= next => {
+                entries.skip((index - next) as u32)?;
+            }
+            _ => {
+                let log_reader = self.reader.log_reader().as_raw();
+                util::handle(unsafe { logiter_seek(entries.as_raw(), log_reader, block) })?;
+                entries.skip(index as u32)?;
+            }
+        }
+        self.current = Some((block, index + 1));
+
+        match entries.next() {
+            Some(result) => result.map(Some),
+            None => Err(error::Error::UnexpectedEof),
+        }
+    }
+}
+
+impl Iterator for Slots {
+    type Item = error::Result<log::Entry>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if let Some(e) = self.error.take() {
+            return Some(Err(e));
+        }
+
+        let result = self.try_next();
+        if result.is_err() {
+            self.entries = None;
+        }
+        result.transpose()
+    }
+}
@@ -0,0 +1,283 @@
+use std::path;
+use std::sync;
+use std::thread;
+use std::time;
+
+use crate::error;
+use crate::hash;
+use crate::store;
+
+// Serves the current generation of a store root (see `store::Store::publish`), and swaps in new
+// generations as they are published.  A background thread waits for `CURRENT` to change, using
+// inotify where it's available and polling otherwise.
+//
+// A new generation is opened and validated before it's swapped in, and lookups that are already
+// running keep using the reader that they started with.  Old readers are closed, and their
+// generation can be collected, once the last `reader()` borrowed from them is dropped.  The watcher
+// stops once every clone of the `ReloadingReader` is dropped.
+#[derive(Clone)]
+pub struct ReloadingReader(sync::Arc<Shared>);
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct ReloadOptions {
+    // How often the watcher checks `CURRENT` when it isn't notified of changes
+    pub poll_interval: time::Duration,
+    // Poll even where inotify is available, e.g. for network file systems
+    pub force_polling: bool,
+}
+
+#[derive(Debug)]
+pub enum ReloadEvent<'a> {
+    Reloaded { generation: u64, previous: u64 },
+    // The new generation couldn't be opened or didn't validate, so the previous one is still
+    // being served.  The watcher reports each failing generation once.
+    Failed { error: &'a error::Error },
+}
+
+type Callback = Box<dyn Fn(ReloadEvent<'_>) + Send + Sync>;
+
+struct Shared {
+    root: path::PathBuf,
+    current: sync::RwLock<(u64, sync::Arc<hash::Reader>)>,
+    // Serializes reloads, so that generations are only ever swapped in going forwards
+    reloading: sync::Mutex<()>,
+    callback: Option<Callback>,
+}
+
+enum Watcher {
+    #[cfg(target_os = "linux")]
+    Inotify(libc::c_int),
+    Polling,
+}
+
+impl Default for ReloadOptions {
+    fn default() -> Self {
+        ReloadOptions {
+            poll_interval: time::Duration::from_secs(1),
+            force_polling: false,
+        }
+    }
+}
+
+impl ReloadingReader {
+    pub fn open<P>(root: P, options: ReloadOptions) -> error::Result<Self>
+    where
+        P: AsRef<path::Path>,
+    {
+        Self::open_inner(root.as_ref(), options, None)
+    }
+
+    // Like `open`, but calls `callback` from the reloading thread after every reload attempt that
+    // found a new generation
+    pub fn open_with_callback<P, F>(
+        root: P,
+        options: ReloadOptions,
+        callback: F,
+    ) -> error::Result<Self>
+    where
+        P: AsRef<path::Path>,
+        F: Fn(ReloadEvent<'_>) + Send + Sync + 'static,
+    {
+        Self::open_inner(root.as_ref(), options, Some(Box::new(callback)))
+    }
+
+    // The reader for the generation that is being served right now; it stays valid after newer
+    // generations are swapped in
+    pub fn reader(&self) -> sync::Arc<hash::Reader> {
+        self.0.current().1
+    }
+
+    pub fn generation(&self) -> u64 {
+        self.0.current().0
+    }
+
+    pub fn get(&self, key: &[u8]) -> error::Result<Option<bytes::BytesMut>> {
+        self.reader().get(key)
+    }
+
+    // Swaps in the current generation right away if it's newer than the one being served, rather
+    // than waiting for the watcher, and returns whether it did
+    pub fn reload(&self) -> error::Result<bool> {
+        self.0.reload()
+    }
+
+    fn open_inner(
+        root: &path::Path,
+        options: ReloadOptions,
+        callback: Option<Callback>,
+    ) -> error::Result<Self> {
+        let current = open_current(root)?;
+        let shared = sync::Arc::new(Shared {
+            root: root.to_path_buf(),
+            current: sync::RwLock::new((current.0, sync::Arc::new(current.1))),
+            reloading: sync::Mutex::new(()),
+            callback,
+        });
+
+        let watcher = Watcher::new(root, options.force_polling);
+        let weak = sync::Arc::downgrade(&shared);
+        thread::Builder::new()
+            .name("sparkey-reload".to_owned())
+            .spawn(move || watch(&weak, &watcher, options.poll_interval))
+            .map_err(error::Error::IO)?;
+
+        Ok(ReloadingReader(shared))
+    }
+}
+
+impl Shared {
+    fn current(&self) -> (u64, sync::Arc<hash::Reader>) {
+        let current = match self.current.read() {
+            Ok(current) => current,
+            Err(poisoned) => poisoned.into_inner(),
+        };
+        (current.0, current.1.clone())
+    }
+
+    fn reload(&self) -> error::Result<bool> {
+        let _reloading = match self.reloading.lock() {
+            Ok(guard) => guard,
+            Err(poisoned) => poisoned.into_inner(),
+        };
+        let previous = self.current().0;
+
+        let result = store::current_generation(&self.root).and_then(|latest| {
+            if latest > previous {
+                open_current(&self.root).map(Some)
+            } else {
+                Ok(None)
+            }
+        });
+
+        match result {
+            // `CURRENT` may have moved on after it was read, but never backwards
+            Ok(Some((generation, reader))) if generation > previous => {
+                let old = {
+                    let mut current = match self.current.write() {
+                        Ok(current) => current,
+                        Err(poisoned) => poisoned.into_inner(),
+                    };
+                    std::mem::replace(&mut *current, (generation, sync::Arc::new(reader)))
+                };
+                // Close the old reader outside of the lock, if nothing else holds it
+                drop(old);
+
+                self.notify(ReloadEvent::Reloaded {
+                    generation,
+                    previous,
+                });
+                Ok(true)
+            }
+            Ok(_) => Ok(false),
+            Err(error) => {
+                self.notify(ReloadEvent::Failed { error: &error });
+                Err(error)
+            }
+        }
+    }
+
+    fn notify(&self, event: ReloadEvent<'_>) {
+        if let Some(ref callback) = self.callback {
+            callback(event);
+        }
+    }
+}
+
+impl Watcher {
+    #[cfg(target_os = "linux")]
+    fn new(root: &path::Path, force_polling: bool) -> Self {
+        if force_polling {
+            return Watcher::Polling;
+        }
+        let path = match crate::util::path_to_cstring(root) {
+            Ok(path) => path,
+            Err(_) => return Watcher::Polling,
+        };
+
+        unsafe {
+            let fd = libc::inotify_init1(libc::IN_NONBLOCK | libc::IN_CLOEXEC);
+            if fd < 0 {
+                return Watcher::Polling;
+            }
+            // `publish` renames `CURRENT` into place
+            let mask = libc::IN_MOVED_TO | libc::IN_CLOSE_WRITE;
+            if libc::inotify_add_watch(fd, path.as_ptr(), mask) < 0 {
+                libc::close(fd);
+                return Watcher::Polling;
+            }
+            Watcher::Inotify(fd)
+        }
+    }
+
+    #[cfg(not(target_os = "linux"))]
+    fn new(_root: &path::Path, _force_polling: bool) -> Self {
+        Watcher::Polling
+    }
+
+    // Returns when something in the root directory changed, or after `interval`
+    fn wait(&self, interval: time::Duration) {
+        match *self {
+            #[cfg(target_os = "linux")]
+            Watcher::Inotify(fd) => unsafe {
+                let mut pollfd = libc::pollfd {
+                    fd,
+                    events: libc::POLLIN,
+                    revents: 0,
+                };
+                let timeout = interval.as_millis().min(libc::c_int::MAX as u128) as libc::c_int;
+                if libc::poll(&mut pollfd, 1, timeout) > 0 {
+                    // Only the fact that something changed matters, not the events themselves
+                    let mut buf = [0u8; 4096];
+                    while libc::read(fd, buf.as_mut_ptr() as *mut libc::c_void, buf.len()) > 0 {}
+                }
+            },
+            Watcher::Polling => thread::sleep(interval),
+        }
+    }
+}
+
+impl Drop for Watcher {
+    fn drop(&mut self) {
+        match *self {
+            #[cfg(target_os = "linux")]
+            Watcher::Inotify(fd) => unsafe {
+                libc::close(fd);
+            },
+            Watcher::Polling => {}
+        }
+    }
+}
+
+fn watch(shared: &sync::Weak<Shared>, watcher: &Watcher, interval: time::Duration) {
+    // What `CURRENT` said when the last reload failed, so that the failure is only reported once
+    let mut failed = None;
+
+    loop {
+        watcher.wait(interval);
+
+        let shared = match shared.upgrade() {
+            Some(shared) => shared,
+            None => return,
+        };
+        let latest = store::current_generation(&shared.root).ok();
+        if failed == Some(latest) {
+            continue;
+        }
+
+        failed = match shared.reload() {
+            Ok(_) => None,
+            Err(_) => Some(latest),
+        };
+    }
+}
+
+// Opens the current generation, and checks that its index covers the whole log, in case it was
+// changed after it was published
+fn open_current(root: &path::Path) -> error::Result<(u64, hash::Reader)> {
+    let store = store::Store::open_current(root)?;
+    let reader = store.reader()?;
+    store.check_index()?;
+
+    // `open_current` always returns a published generation
+    Ok((store.generation().unwrap_or(0), reader))
+}
@@ -0,0 +1,505 @@
+use std::ffi;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::io::Write;
+use std::os::unix::io::AsRawFd;
+use std::path;
+use std::process;
+use std::sync;
+use std::time;
+
+use crate::error;
+use crate::hash;
+use crate::log;
+
+// Names of the pointer to the current generation, and of the directories in a store root
+const CURRENT: &str = "CURRENT";
+const GENERATION_PREFIX: &str = "gen-";
+const BUILD_PREFIX: &str = ".build-";
+// File that publishers lock while they choose a generation number and flip `CURRENT`
+const PUBLISH_LOCK: &str = "LOCK";
+// Base name of the files in a generation directory
+const BASE_NAME: &str = "data";
+// Times to re-read `CURRENT` if its generation is collected while being opened
+const OPEN_ATTEMPTS: usize = 10;
+
+// A log (.spl) and its index (.spi) next to each other, named after a base path.
+//
+// Stores can also live in generation directories under a root directory, with a `CURRENT` file
+// that names the generation that readers should use.  Generations are built under a temporary
+// name, and `publish` moves them into place and flips `CURRENT` with a rename, so readers never
+// see half a generation.  `CURRENT` only ever moves to newer generations.
+#[derive(Clone, Debug)]
+pub struct Store {
+    log_path: path::PathBuf,
+    index_path: path::PathBuf,
+    generation: Option<sync::Arc<Generation>>,
+}
+
+#[derive(Debug)]
+struct Generation {
+    root: path::PathBuf,
+    // `None` while the generation is being built
+    number: Option<u64>,
+    // A shared lock on the generation directory, which keeps it from being collected until this
+    // and every reader that was opened from the store are dropped
+    #[allow(dead_code)]
+    lock: fs::File,
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct StoreOptions {
+    pub compression_type: log::CompressionType,
+    pub compression_block_size: u32,
+    // `None` picks the hash type from the number of entries
+    pub hash_type: Option<hash::Type>,
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum IndexStatus {
+    // The index covers the whole log
+    Fresh,
+    // The log has been appended to since the index was written, so the entries in the last
+    // `unindexed_bytes` of the log aren't visible to readers
+    Stale { unindexed_bytes: u64 },
+    // The index was written for another log
+    Mismatched,
+    Missing,
+}
+
+impl Default for StoreOptions {
+    fn default() -> Self {
+        StoreOptions {
+            compression_type: log::CompressionType::None,
+            compression_block_size: 4096,
+            hash_type: None,
+        }
+    }
+}
+
+impl Store {
+    // `base` may be the path without an extension, or the path of either file
+    pub fn open<P>(base: P) -> error::Result<Self>
+    where
+        P: AsRef<path::Path>,
+    {
+        let store = Self::at(base);
+        // Fail early if there is no log
+        log::LogHeader::read(&store.log_path)?;
+        Ok(store)
+    }
+
+    // Creates an empty log and indexes it, replacing any existing files
+    pub fn create<P>(base: P, options: StoreOptions) -> error::Result<Self>
+    where
+        P: AsRef<path::Path>,
+    {
+        let store = Self::at(base);
+
+        log::Writer::create(
+            &store.log_path,
+            options.compression_type,
+            options.compression_block_size,
+        )?
+        .close()?;
+        hash::Writer::write(&store.index_path, &store.log_path, options.hash_type)?;
+
+        Ok(store)
+    }
+
+    // Starts building a new generation under `root`, which `open_current` will return once it's
+    // published
+    pub fn create_generation<P>(root: P, options: StoreOptions) -> error::Result<Self>
+    where
+        P: AsRef<path::Path>,
+    {
+        let root = root.as_ref();
+        fs::create_dir_all(root).map_err(error::Error::IO)?;
+
+        let mut attempts = 0;
+        let (dir, lock) = loop {
+            let nanos = time::SystemTime::now()
+                .duration_since(time::UNIX_EPOCH)
+                .map_or(0, |d| d.subsec_nanos());
+            let dir = root.join(format!("{}{}-{}", BUILD_PREFIX, process::id(), nanos));
+            fs::create_dir(&dir).map_err(error::Error::IO)?;
+
+            // `collect_garbage` may take the build for an abandoned one before it's locked, and
+            // it only removes builds while it holds their lock
+            match lock(&dir) {
+                Ok(lock) if dir.exists() => break (dir, lock),
+                Ok(_) => {}
+                Err(error::Error::IO(ref e)) if e.kind() == io::ErrorKind::NotFound => {}
+                Err(e) => return Err(e),
+            }
+            attempts += 1;
+            if attempts >= OPEN_ATTEMPTS {
+                return Err(error::Error::IO(io::ErrorKind::NotFound.into()));
+            }
+        };
+
+        let mut store = Self::create(dir.join(BASE_NAME), options)?;
+        store.generation = Some(sync::Arc::new(Generation {
+            root: root.to_path_buf(),
+            number: None,
+            lock,
+        }));
+
+        Ok(store)
+    }
+
+    // Opens the generation that `CURRENT` points to
+    pub fn open_current<P>(root: P) -> error::Result<Self>
+    where
+        P: AsRef<path::Path>,
+    {
+        let root = root.as_ref();
+        let mut attempts = 0;
+
+        loop {
+            let number = current_generation(root)?;
+            match Self::open_generation(root, number) {
+                // The generation was collected after a newer one got published
+                Err(error::Error::IO(ref e))
+                    if e.kind() == io::ErrorKind::NotFound && attempts < OPEN_ATTEMPTS =>
+                {
+                    attempts += 1;
+                }
+                result => return result,
+            }
+        }
+    }
+
+    // Syncs a generation from `create_generation` to disk, and makes it the current one, with a
+    // number above every generation that was published before.  The index has to cover the whole
+    // log; if it doesn't, the build is removed and the error is returned.  Old generations that
+    // nothing has open any more are collected.
+    pub fn publish(self) -> error::Result<Self> {
+        let (root, build_dir) = match (&self.generation, self.log_path.parent()) {
+            (Some(generation), Some(build_dir)) if generation.number.is_none() => {
+                (generation.root.clone(), build_dir.to_path_buf())
+            }
+            _ => return Err(error::Error::NotPublishable),
+        };
+
+        if let Err(e) = self.check_index() {
+            self.discard();
+            return Err(e);
+        }
+
+        for path in &[&self.log_path, &self.index_path] {
+            fs::File::open(path)
+                .and_then(|file| file.sync_all())
+                .map_err(error::Error::IO)?;
+        }
+        sync_dir(&build_dir)?;
+
+        let publish_lock = lock_publishing(&root)?;
+        let current = match current_generation(&root) {
+            Ok(current) => Some(current),
+            Err(error::Error::IO(ref e)) if e.kind() == io::ErrorKind::NotFound => None,
+            Err(e) => return Err(e),
+        };
+        let number = latest_generation(&root)?.max(current).map_or(1, |n| n + 1);
+        let dir = root.join(format!("{}{}", GENERATION_PREFIX, number));
+        fs::rename(&build_dir, &dir).map_err(error::Error::IO)?;
+
+        // Lock the new generation before releasing the build lock, so it can't be collected
+        let store = sync_dir(&root).and_then(|()| Self::open_generation(&root, number));
+        drop(self);
+
+        // Nothing refers to the new generation until `CURRENT` does, so it is removed again if
+        // that fails
+        let store = match store.and_then(|store| set_current(&root, number).map(|()| store)) {
+            Ok(store) => store,
+            Err(e) => {
+                let _ = fs::remove_dir_all(&dir);
+                return Err(e);
+            }
+        };
+        sync_dir(&root)?;
+        drop(publish_lock);
+
+        Self::collect_garbage(&root)?;
+
+        Ok(store)
+    }
+
+    // Removes a generation from `create_generation` instead of publishing it
+    pub(crate) fn discard(self) {
+        let build_dir = match self.generation {
+            Some(ref generation) if generation.number.is_none() => self.log_path.parent(),
+            _ => None,
+        };
+        if let Some(build_dir) = build_dir.map(path::Path::to_path_buf) {
+            drop(self);
+            let _ = fs::remove_dir_all(build_dir);
+        }
+    }
+
+    // Removes the generations before the current one that nothing has open, the ones after it
+    // that a publish failed to make current, and the builds that were dropped or abandoned by a
+    // crash before they were published, and returns how many were removed.  Readers that were
+    // opened from a store hold on to their generation.
+    pub fn collect_garbage<P>(root: P) -> error::Result<usize>
+    where
+        P: AsRef<path::Path>,
+    {
+        let root = root.as_ref();
+        // A publish in progress has a generation after the current one too
+        let _publish_lock = lock_publishing(root)?;
+        let current = match current_generation(root) {
+            Ok(current) => Some(current),
+            Err(error::Error::IO(ref e)) if e.kind() == io::ErrorKind::NotFound => None,
+            Err(e) => return Err(e),
+        };
+        let mut collected = 0;
+
+        let old = generations(root)?
+            .into_iter()
+            .filter(|&(number, _)| current.is_some_and(|current| number != current))
+            .map(|(_, dir)| dir);
+        for dir in old.chain(builds(root)?) {
+            let file = match fs::File::open(&dir) {
+                Ok(file) => file,
+                // Published or collected in the meantime
+                Err(ref e) if e.kind() == io::ErrorKind::NotFound => continue,
+                Err(e) => return Err(error::Error::IO(e)),
+            };
+            if unsafe { libc::flock(file.as_raw_fd(), libc::LOCK_EX | libc::LOCK_NB) } == 0 {
+                fs::remove_dir_all(&dir).map_err(error::Error::IO)?;
+                collected += 1;
+            }
+        }
+
+        Ok(collected)
+    }
+
+    // The number of the generation, if the store is a published one
+    pub fn generation(&self) -> Option<u64> {
+        self.generation.as_ref().and_then(|g| g.number)
+    }
+
+    pub fn log_path(&self) -> &path::Path {
+        &self.log_path
+    }
+
+    pub fn index_path(&self) -> &path::Path {
+        &self.index_path
+    }
+
+    // Appends to the log; call `reindex` afterwards to make the new entries visible to readers
+    pub fn writer(&self) -> error::Result<log::Writer> {
+        log::Writer::append(&self.log_path)
+    }
+
+    pub fn reindex(&self) -> error::Result<hash::IndexUpdateReport> {
+        hash::Writer::update(&self.index_path, &self.log_path)
+    }
+
+    pub fn reader(&self) -> error::Result<hash::Reader> {
+        if !self.index_path.exists() {
+            return Err(error::Error::IndexMissing {
+                path: self.index_path.clone(),
+            });
+        }
+
+        hash::Reader::open_guarded(&self.index_path, &self.log_path, self.guard())
+    }
+
+    pub fn log_reader(&self) -> error::Result<log::Reader> {
+        log::Reader::open_guarded(&self.log_path, self.guard())
+    }
+
+    // Fails with the matching error unless the index covers the whole log
+    pub(crate) fn check_index(&self) -> error::Result<()> {
+        match self.index_status()? {
+            IndexStatus::Fresh => Ok(()),
+            IndexStatus::Stale { unindexed_bytes } => {
+                Err(error::Error::StaleIndex { unindexed_bytes })
+            }
+            IndexStatus::Mismatched => Err(error::Error::FileIdentifierMismatch),
+            IndexStatus::Missing => Err(error::Error::IndexMissing {
+                path: self.index_path.clone(),
+            }),
+        }
+    }
+
+    pub fn index_status(&self) -> error::Result<IndexStatus> {
+        if !self.index_path.exists() {
+            return Ok(IndexStatus::Missing);
+        }
+
+        let log_header = log::LogHeader::read(&self.log_path)?;
+        let hash_header = hash::HashHeader::read(&self.index_path)?;
+
+        let status = match hash::freshness(&hash_header, &log_header) {
+            Ok(hash::Freshness::Fresh) => IndexStatus::Fresh,
+            Ok(hash::Freshness::Stale {
+                unindexed_bytes, ..
+            }) => IndexStatus::Stale { unindexed_bytes },
+            Err(error::Error::FileIdentifierMismatch) => IndexStatus::Mismatched,
+            Err(e) => return Err(e),
+        };
+
+        Ok(status)
+    }
+
+    // Removes both files; a missing index is fine
+    pub fn delete(self) -> error::Result<()> {
+        fs::remove_file(&self.log_path).map_err(error::Error::IO)?;
+
+        match fs::remove_file(&self.index_path) {
+            Ok(()) => Ok(()),
+            Err(ref e) if e.kind() == io::ErrorKind::NotFound => Ok(()),
+            Err(e) => Err(error::Error::IO(e)),
+        }
+    }
+
+    fn open_generation(root: &path::Path, number: u64) -> error::Result<Self> {
+        let dir = root.join(format!("{}{}", GENERATION_PREFIX, number));
+        let lock = lock(&dir)?;
+
+        // Opening the log fails if the generation was collected while we waited for the lock
+        let mut store = Self::open(dir.join(BASE_NAME))?;
+        store.generation = Some(sync::Arc::new(Generation {
+            root: root.to_path_buf(),
+            number: Some(number),
+            lock,
+        }));
+
+        Ok(store)
+    }
+
+    fn guard(&self) -> Option<sync::Arc<dyn fmt::Debug + Send + Sync>> {
+        self.generation
+            .clone()
+            .map(|g| g as sync::Arc<dyn fmt::Debug + Send + Sync>)
+    }
+
+    fn at<P>(base: P) -> Self
+    where
+        P: AsRef<path::Path>,
+    {
+        let (log_path, index_path) = file_paths(base.as_ref());
+        Store {
+            log_path,
+            index_path,
+            generation: None,
+        }
+    }
+}
+
+// The log and index paths for a base path, which may also be the path of either file.  Other
+// extensions are part of the base name, so `data.v1` becomes `data.v1.spl` and `data.v1.spi`.
+pub(crate) fn file_paths(base: &path::Path) -> (path::PathBuf, path::PathBuf) {
+    let base = match base.extension().and_then(|extension| extension.to_str()) {
+        Some("spl") | Some("spi") => base.with_extension(""),
+        _ => base.to_path_buf(),
+    };
+    let with_suffix = |suffix| {
+        let mut name = ffi::OsString::from(base.as_os_str());
+        name.push(suffix);
+        path::PathBuf::from(name)
+    };
+
+    (with_suffix(".spl"), with_suffix(".spi"))
+}
+
+// Takes a shared lock on a generation directory
+fn lock(dir: &path::Path) -> error::Result<fs::File> {
+    let file = fs::File::open(dir).map_err(error::Error::IO)?;
+    if unsafe { libc::flock(file.as_raw_fd(), libc::LOCK_SH) } != 0 {
+        return Err(error::Error::IO(io::Error::last_os_error()));
+    }
+    Ok(file)
+}
+
+// Takes the lock that publishers of a root take turns with
+fn lock_publishing(root: &path::Path) -> error::Result<fs::File> {
+    let file = fs::OpenOptions::new()
+        .create(true)
+        .truncate(false)
+        .write(true)
+        .open(root.join(PUBLISH_LOCK))
+        .map_err(error::Error::IO)?;
+    if unsafe { libc::flock(file.as_raw_fd(), libc::LOCK_EX) } != 0 {
+        return Err(error::Error::IO(io::Error::last_os_error()));
+    }
+    Ok(file)
+}
+
+// Points `CURRENT` at generation `number`
+fn set_current(root: &path::Path, number: u64) -> error::Result<()> {
+    let tmp = root.join(format!("{}.{}{}", CURRENT, GENERATION_PREFIX, number));
+    fs::File::create(&tmp)
+        .and_then(|mut file| {
+            writeln!(file, "{}{}", GENERATION_PREFIX, number)?;
+            file.sync_all()
+        })
+        .and_then(|()| fs::rename(&tmp, root.join(CURRENT)))
+        .map_err(|e| {
+            let _ = fs::remove_file(&tmp);
+            error::Error::IO(e)
+        })
+}
+
+fn sync_dir(dir: &path::Path) -> error::Result<()> {
+    fs::File::open(dir)
+        .and_then(|file| file.sync_all())
+        .map_err(error::Error::IO)
+}
+
+pub(crate) fn current_generation(root: &path::Path) -> error::Result<u64> {
+    let name = fs::read_to_string(root.join(CURRENT)).map_err(error::Error::IO)?;
+    let name = name.trim();
+
+    name.strip_prefix(GENERATION_PREFIX)
+        .and_then(|number| number.parse().ok())
+        .ok_or_else(|| error::Error::InvalidGeneration {
+            name: name.to_owned(),
+        })
+}
+
+fn latest_generation(root: &path::Path) -> error::Result<Option<u64>> {
+    Ok(generations(root)?
+        .into_iter()
+        .map(|(number, _)| number)
+        .max())
+}
+
+fn builds(root: &path::Path) -> error::Result<Vec<path::PathBuf>> {
+    let mut builds = Vec::new();
+
+    for entry in fs::read_dir(root).map_err(error::Error::IO)? {
+        let entry = entry.map_err(error::Error::IO)?;
+        let is_build = entry
+            .file_name()
+            .to_str()
+            .is_some_and(|name| name.starts_with(BUILD_PREFIX));
+        if is_build {
+            builds.push(entry.path());
+        }
+    }
+
+    Ok(builds)
+}
+
+fn generations(root: &path::Path) -> error::Result<Vec<(u64, path::PathBuf)>> {
+    let mut generations = Vec::new();
+
+    for entry in fs::read_dir(root).map_err(error::Error::IO)? {
+        let entry = entry.map_err(error::Error::IO)?;
+        let number = entry
+            .file_name()
+            .to_str()
+            .and_then(|name| name.strip_prefix(GENERATION_PREFIX))
+            .and_then(|number| number.parse().ok());
+        if let Some(number) = number {
+            generations.push((number, entry.path()));
+        }
+    }
+
+    Ok(generations)
+}
@@ -0,0 +1,295 @@
+use std::marker;
+use std::path;
+use std::str;
+
+use crate::error;
+use crate::hash;
+use crate::log;
+
+// Converts keys or values of type `T` to and from the bytes stored in a log.  Typed readers and
+// writers use one codec for both keys and values, so to mix encodings, implement `Codec` for
+// both types on a codec of your own.
+pub trait Codec<T> {
+    fn encode(value: &T, buf: &mut Vec<u8>) -> error::Result<()>;
+    fn decode(data: &[u8]) -> error::Result<T>;
+}
+
+// Stores byte strings as-is
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Raw;
+
+// Stores strings as UTF-8
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Utf8;
+
+// Stores integers in big-endian order, so that their bytes sort like the numbers themselves
+#[derive(Clone, Copy, Debug, Default)]
+pub struct BigEndian;
+
+#[cfg(feature = "serde-bincode")]
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Bincode;
+
+#[cfg(feature = "serde-json")]
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Json;
+
+#[derive(Debug)]
+pub struct TypedWriter<K, V, C>(
+    log::Writer,
+    Vec<u8>,
+    Vec<u8>,
+    marker::PhantomData<fn(K, V, C)>,
+);
+
+pub struct TypedReader<K, V, C>(hash::Reader, marker::PhantomData<fn(K, V, C)>);
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct TypedEntry<K, V> {
+    pub entry_type: log::EntryType,
+    pub key: K,
+    // `None` for deletes, which have no value
+    pub value: Option<V>,
+}
+
+pub struct TypedEntries<'a, K, V, C>(log::Entries<'a>, marker::PhantomData<fn(K, V, C)>);
+
+impl Codec<Vec<u8>> for Raw {
+    fn encode(value: &Vec<u8>, buf: &mut Vec<u8>) -> error::Result<()> {
+        buf.extend_from_slice(value);
+        Ok(())
+    }
+
+    fn decode(data: &[u8]) -> error::Result<Vec<u8>> {
+        Ok(data.to_vec())
+    }
+}
+
+impl Codec<bytes::BytesMut> for Raw {
+    fn encode(value: &bytes::BytesMut, buf: &mut Vec<u8>) -> error::Result<()> {
+        buf.extend_from_slice(value);
+        Ok(())
+    }
+
+    fn decode(data: &[u8]) -> error::Result<bytes::BytesMut> {
+        Ok(bytes::BytesMut::from(data))
+    }
+}
+
+impl Codec<String> for Utf8 {
+    fn encode(value: &String, buf: &mut Vec<u8>) -> error::Result<()> {
+        buf.extend_from_slice(value.as_bytes());
+        Ok(())
+    }
+
+    fn decode(data: &[u8]) -> error::Result<String> {
+        match str::from_utf8(data) {
+            Ok(s) => Ok(s.to_owned()),
+            Err(e) => Err(error::Error::Decode {
+                reason: e.to_string(),
+            }),
+        }
+    }
+}
+
+macro_rules! big_endian_codec {
+    ($($t:ty),*) => {
+        $(
+            impl Codec<$t> for BigEndian {
+                fn encode(value: &$t, buf: &mut Vec<u8>) -> error::Result<()> {
+                    buf.extend_from_slice(&value.to_be_bytes());
+                    Ok(())
+                }
+
+                fn decode(data: &[u8]) -> error::Result<$t> {
+                    use std::convert::TryFrom;
+
+                    match <[u8; std::mem::size_of::<$t>()]>::try_from(data) {
+                        Ok(bytes) => Ok(<$t>::from_be_bytes(bytes)),
+                        Err(_) => Err(error::Error::Decode {
+                            reason: format!(
+                                "expected {} bytes for {} but got {}",
+                                std::mem::size_of::<$t>(),
+                                stringify!($t),
+                                data.len()
+                            ),
+                        }),
+                    }
+                }
+            }
+        )*
+    };
+}
+
+big_endian_codec!(u8, u16, u32, u64, u128, i8, i16, i32, i64, i128);
+
+#[cfg(feature = "serde-bincode")]
+impl<T> Codec<T> for Bincode
+where
+    T: serde::Serialize + serde::de::DeserializeOwned,
+{
+    fn encode(value: &T, buf: &mut Vec<u8>) -> error::Result<()> {
+        bincode::serialize_into(buf, value).map_err(|e| error::Error::Encode {
+            reason: e.to_string(),
+        })
+    }
+
+    fn decode(data: &[u8]) -> error::Result<T> {
+        bincode::deserialize(data).map_err(|e| error::Error::Decode {
+            reason: e.to_string(),
+        })
+    }
+}
+
+#[cfg(feature = "serde-json")]
+impl<T> Codec<T> for Json
+where
+    T: serde::Serialize + serde::de::DeserializeOwned,
+{
+    fn encode(value: &T, buf: &mut Vec<u8>) -> error::Result<()> {
+        serde_json::to_writer(buf, value).map_err(|e| error::Error::Encode {
+            reason: e.to_string(),
+        })
+    }
+
+    fn decode(data: &[u8]) -> error::Result<T> {
+        serde_json::from_slice(data).map_err(|e| error::Error::Decode {
+            reason: e.to_string(),
+        })
+    }
+}
+
+impl<K, V, C> TypedWriter<K, V, C>
+where
+    C: Codec<K> + Codec<V>,
+{
+    pub fn create<P>(
+        path: P,
+        compression_type: log::CompressionType,
+        compression_block_size: u32,
+    ) -> error::Result<Self>
+    where
+        P: AsRef<path::Path>,
+    {
+        Ok(Self::new(log::Writer::create(
+            path,
+            compression_type,
+            compression_block_size,
+        )?))
+    }
+
+    pub fn append<P>(path: P) -> error::Result<Self>
+    where
+        P: AsRef<path::Path>,
+    {
+        Ok(Self::new(log::Writer::append(path)?))
+    }
+
+    pub fn new(writer: log::Writer) -> Self {
+        TypedWriter(writer, Vec::new(), Vec::new(), marker::PhantomData)
+    }
+
+    pub fn as_inner(&self) -> &log::Writer {
+        &self.0
+    }
+
+    pub fn into_inner(self) -> log::Writer {
+        self.0
+    }
+
+    pub fn put(&mut self, key: &K, value: &V) -> error::Result<()> {
+        self.1.clear();
+        self.2.clear();
+        <C as Codec<K>>::encode(key, &mut self.1)?;
+        <C as Codec<V>>::encode(value, &mut self.2)?;
+        self.0.put(&self.1, &self.2)
+    }
+
+    pub fn delete(&mut self, key: &K) -> error::Result<()> {
+        self.1.clear();
+        <C as Codec<K>>::encode(key, &mut self.1)?;
+        self.0.delete(&self.1)
+    }
+
+    pub fn flush(&mut self) -> error::Result<()> {
+        self.0.flush()
+    }
+
+    pub fn close(self) -> error::Result<log::WriterSummary> {
+        self.0.close()
+    }
+}
+
+impl<K, V, C> TypedReader<K, V, C>
+where
+    C: Codec<K> + Codec<V>,
+{
+    pub fn open<P1, P2>(hash_path: P1, log_path: P2) -> error::Result<Self>
+    where
+        P1: AsRef<path::Path>,
+        P2: AsRef<path::Path>,
+    {
+        Ok(Self::new(hash::Reader::open(hash_path, log_path)?))
+    }
+
+    pub fn new(reader: hash::Reader) -> Self {
+        TypedReader(reader, marker::PhantomData)
+    }
+
+    pub fn as_inner(&self) -> &hash::Reader {
+        &self.0
+    }
+
+    pub fn into_inner(self) -> hash::Reader {
+        self.0
+    }
+
+    pub fn get(&self, key: &K) -> error::Result<Option<V>> {
+        let mut buf = Vec::new();
+        <C as Codec<K>>::encode(key, &mut buf)?;
+
+        match self.0.get_borrowed(&buf)? {
+            Some(value) => Ok(Some(<C as Codec<V>>::decode(&value)?)),
+            None => Ok(None),
+        }
+    }
+
+    pub fn entries(&self) -> error::Result<TypedEntries<'_, K, V, C>> {
+        Ok(TypedEntries::new(self.0.entries()?))
+    }
+}
+
+impl<'a, K, V, C> TypedEntries<'a, K, V, C>
+where
+    C: Codec<K> + Codec<V>,
+{
+    // Decodes the entries of any iterator, e.g. `log::Reader::entries()`
+    pub fn new(entries: log::Entries<'a>) -> Self {
+        TypedEntries(entries, marker::PhantomData)
+    }
+
+    fn decode(entry: log::Entry) -> error::Result<TypedEntry<K, V>> {
+        let key = <C as Codec<K>>::decode(&entry.key)?;
+        let value = match entry.entry_type {
+            log::EntryType::Put => Some(<C as Codec<V>>::decode(&entry.value)?),
+            log::EntryType::Delete => None,
+        };
+
+        Ok(TypedEntry {
+            entry_type: entry.entry_type,
+            key,
+            value,
+        })
+    }
+}
+
+impl<'a, K, V, C> Iterator for TypedEntries<'a, K, V, C>
+where
+    C: Codec<K> + Codec<V>,
+{
+    type Item = error::Result<TypedEntry<K, V>>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.0.next().map(|entry| entry.and_then(Self::decode))
+    }
+}
@@ -1,9 +1,11 @@
+use std::borrow;
 use std::ffi;
 use std::io;
 use std::os;
 use std::path;
+use std::ptr;
+use std::slice;
 
-use libc;
 use sparkey_sys::*;
 
 use crate::error;
@@ -118,3 +120,128 @@ pub fn read_value(iter: *mut logiter, reader: *mut logreader) -> error::Result<b
 
     Ok(buf)
 }
+
+type ChunkFn =
+    unsafe extern "C" fn(*mut logiter, *mut logreader, u64, *mut *mut u8, *mut u64) -> returncode;
+
+pub fn read_value_into(
+    iter: *mut logiter,
+    reader: *mut logreader,
+    buf: &mut Vec<u8>,
+) -> error::Result<()> {
+    use std::convert::TryFrom;
+
+    let expected_len = unsafe { logiter_valuelen(iter) };
+    let mut actual_len = 0;
+    buf.clear();
+    buf.reserve(usize::try_from(expected_len).unwrap());
+
+    unsafe {
+        handle(logiter_fill_value(
+            iter,
+            reader,
+            expected_len,
+            buf.as_mut_ptr(),
+            &mut actual_len,
+        ))?;
+        assert_eq!(expected_len, actual_len);
+        buf.set_len(usize::try_from(actual_len).unwrap());
+    }
+
+    Ok(())
+}
+
+// The returned slice points into the mmap of `reader` when the log is uncompressed, so the
+// caller must make sure that `'a` does not outlive the reader.
+pub unsafe fn borrow_key<'a>(
+    iter: *mut logiter,
+    reader: *mut logreader,
+) -> error::Result<borrow::Cow<'a, [u8]>> {
+    let mut buf = Vec::new();
+    match borrow_chunks(
+        iter,
+        reader,
+        logiter_keylen(iter),
+        logiter_keychunk,
+        &mut buf,
+    )? {
+        Some(data) => Ok(borrow::Cow::Borrowed(data)),
+        None => Ok(borrow::Cow::Owned(buf)),
+    }
+}
+
+// The returned slice points into the mmap of `reader` when the log is uncompressed, so the
+// caller must make sure that `'a` does not outlive the reader.
+pub unsafe fn borrow_value<'a>(
+    iter: *mut logiter,
+    reader: *mut logreader,
+) -> error::Result<borrow::Cow<'a, [u8]>> {
+    let mut buf = Vec::new();
+    match borrow_value_into(iter, reader, &mut buf)? {
+        Some(data) => Ok(borrow::Cow::Borrowed(data)),
+        None => Ok(borrow::Cow::Owned(buf)),
+    }
+}
+
+// Like `borrow_value`, but copies into `buf` (returning `None`) when the value can't be borrowed.
+pub unsafe fn borrow_value_into<'a>(
+    iter: *mut logiter,
+    reader: *mut logreader,
+    buf: &mut Vec<u8>,
+) -> error::Result<Option<&'a [u8]>> {
+    borrow_chunks(
+        iter,
+        reader,
+        logiter_valuelen(iter),
+        logiter_valuechunk,
+        buf,
+    )
+}
+
+unsafe fn borrow_chunks<'a>(
+    iter: *mut logiter,
+    reader: *mut logreader,
+    expected_len: u64,
+    chunk: ChunkFn,
+    buf: &mut Vec<u8>,
+) -> error::Result<Option<&'a [u8]>> {
+    use std::convert::TryFrom;
+
+    if expected_len == 0 {
+        return Ok(Some(&[]));
+    }
+
+    // Chunks of compressed logs point into the decompression buffer of the iterator, which
+    // gets overwritten when moving to the next block, so only uncompressed logs can be borrowed.
+    let compressed = match logreader_get_compression_type(reader) {
+        compression_type::COMPRESSION_NONE => false,
+        compression_type::COMPRESSION_SNAPPY | compression_type::COMPRESSION_ZSTD => true,
+    };
+
+    let mut data = ptr::null_mut();
+    let mut len = 0;
+    handle(chunk(iter, reader, expected_len, &mut data, &mut len))?;
+
+    if len == expected_len && !compressed {
+        let len = usize::try_from(len).unwrap();
+        return Ok(Some(slice::from_raw_parts(data, len)));
+    }
+
+    buf.clear();
+    buf.reserve(usize::try_from(expected_len).unwrap());
+
+    loop {
+        if len == 0 {
+            return Err(error::Error::UnexpectedEof);
+        }
+        buf.extend_from_slice(slice::from_raw_parts(data, usize::try_from(len).unwrap()));
+
+        let remaining = expected_len - buf.len() as u64;
+        if remaining == 0 {
+            break;
+        }
+        handle(chunk(iter, reader, remaining, &mut data, &mut len))?;
+    }
+
+    Ok(None)
+}